@@ -0,0 +1,47 @@
+//! Benchmarks acquiring and releasing short locks.
+//!
+//! Requires a Redis instance at `redis://127.0.0.1/`, run with `cargo bench`.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use redis::Client;
+use redis_lock::MultiResourceLock;
+use std::time::Duration;
+
+/// Acquires and releases a lock on 1, 8 and 64 resources.
+#[expect(clippy::expect_used, reason = "A benchmark cannot run without Redis.")]
+fn acquire_release(c: &mut Criterion) {
+    let runtime = tokio::runtime::Runtime::new().expect("runtime");
+    let lock = runtime.block_on(async {
+        let client = Client::open("redis://127.0.0.1/").expect("client");
+        redis_lock::setup(&client).await.expect("setup");
+        // Shares one connection so the results exclude opening connections.
+        let connection = client
+            .get_multiplexed_async_connection()
+            .await
+            .expect("connection");
+        MultiResourceLock::new(connection).expect("lock")
+    });
+
+    let mut group = c.benchmark_group("acquire_release");
+    for count in [1usize, 8, 64] {
+        let resources = (0..count).map(|i| format!("bench{i}")).collect::<Vec<_>>();
+        group.bench_with_input(
+            BenchmarkId::from_parameter(count),
+            &resources,
+            |b, input| {
+                b.to_async(&runtime).iter(|| async {
+                    let lock_id = lock
+                        .try_acquire(input, Duration::from_secs(1))
+                        .await
+                        .expect("acquire")
+                        .expect("contended");
+                    lock.release(&lock_id).await.expect("release");
+                });
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, acquire_release);
+criterion_main!(benches);
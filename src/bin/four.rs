@@ -12,6 +12,7 @@ use std::error::Error;
 use std::time::Duration;
 use thiserror::Error;
 
+/// Error for [`transfer`].
 #[derive(Debug, Display, Error)]
 enum TransferError {
     /// Failed to open connection: {0}
@@ -76,9 +77,11 @@ impl Distribution<TransferType> for Standard {
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
-    let redis_url = "redis://127.0.0.1/";
+    // The server of the test running this, see `TestRedis::url`.
+    let redis_url =
+        std::env::var("REDIS_URL").unwrap_or_else(|_| String::from("redis://127.0.0.1/"));
     let client = Client::open(redis_url)?;
-    let mut lock = MultiResourceLock::new(client.clone())?;
+    let lock = MultiResourceLock::new(client.clone())?;
 
     let mut rng = rand::thread_rng();
 
@@ -29,9 +29,11 @@ async fn transfer(
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
-    let redis_url = "redis://127.0.0.1/";
+    // The server of the test running this, see `TestRedis::url`.
+    let redis_url =
+        std::env::var("REDIS_URL").unwrap_or_else(|_| String::from("redis://127.0.0.1/"));
     let client = Client::open(redis_url)?;
-    let mut lock = MultiResourceLock::new(client.clone())?;
+    let lock = MultiResourceLock::new(client.clone())?;
     let mut conn = client.get_multiplexed_async_connection().await?;
     let mut rng = rand::thread_rng();
     for _ in 0..10usize {
@@ -0,0 +1,51 @@
+use redis::{RedisWrite, ToRedisArgs};
+
+/// A condition on a Redis key checked in the same call as acquiring a lock, see
+/// [`MultiResourceLock::try_acquire_if`](crate::MultiResourceLock::try_acquire_if).
+///
+/// Keys are used as given, without the prefixes of the
+/// [`KeyFormatter`](crate::keys::KeyFormatter) or the [`HashTag`](crate::hash_tag::HashTag).
+#[derive(Debug, Clone, PartialEq)]
+pub enum LuaCondition {
+    /// `key` exists.
+    Exists(String),
+    /// `key` does not exist.
+    Missing(String),
+    /// The string at `key` equals `value`.
+    Equals {
+        /// The key to read.
+        key: String,
+        /// The value to compare with.
+        value: String,
+    },
+    /// The number at `key` is greater than `value`, failing when `key` does not exist or does not
+    /// hold a number.
+    GreaterThan {
+        /// The key to read.
+        key: String,
+        /// The value to compare with.
+        value: f64,
+    },
+    /// The number at `key` is less than `value`, failing when `key` does not exist or does not
+    /// hold a number.
+    LessThan {
+        /// The key to read.
+        key: String,
+        /// The value to compare with.
+        value: f64,
+    },
+}
+
+impl ToRedisArgs for LuaCondition {
+    /// Writes the kind, key and value read by `check_condition` in the Lua library.
+    #[inline]
+    fn write_redis_args<W: ?Sized + RedisWrite>(&self, out: &mut W) {
+        match self {
+            LuaCondition::Exists(key) => ("exists", key, "").write_redis_args(out),
+            LuaCondition::Missing(key) => ("missing", key, "").write_redis_args(out),
+            LuaCondition::Equals { key, value } => ("eq", key, value).write_redis_args(out),
+            LuaCondition::GreaterThan { key, value } => ("gt", key, value).write_redis_args(out),
+            LuaCondition::LessThan { key, value } => ("lt", key, value).write_redis_args(out),
+        }
+    }
+}
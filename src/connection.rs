@@ -0,0 +1,117 @@
+use redis::aio::{ConnectionLike, MultiplexedConnection};
+use redis::RedisResult;
+#[cfg(feature = "bb8")]
+use redis::{Cmd, ErrorKind, Pipeline, RedisError, RedisFuture, Value};
+use std::future::Future;
+
+/// A source of connections for [`MultiResourceLock`](crate::MultiResourceLock).
+///
+/// A connection is requested for each operation, so implementations for long-lived connections
+/// (e.g. [`MultiplexedConnection`]) share the one connection by cloning it.
+///
+/// Any other [`ConnectionLike`] type can be used by implementing this trait for it.
+pub trait ConnectionProvider {
+    /// The connection commands are run on.
+    type Connection: ConnectionLike + Send;
+
+    /// Gets a connection to run a command on.
+    fn connection(&self) -> impl Future<Output = RedisResult<Self::Connection>> + Send;
+}
+
+/// Opens a new multiplexed connection for each operation.
+#[cfg(any(feature = "tokio", feature = "async-std"))]
+#[cfg_attr(docsrs, doc(cfg(any(feature = "tokio", feature = "async-std"))))]
+impl ConnectionProvider for redis::Client {
+    type Connection = MultiplexedConnection;
+
+    #[inline]
+    fn connection(&self) -> impl Future<Output = RedisResult<Self::Connection>> + Send {
+        self.get_multiplexed_async_connection()
+    }
+}
+
+/// Shares the connection between all operations.
+impl ConnectionProvider for MultiplexedConnection {
+    type Connection = MultiplexedConnection;
+
+    #[inline]
+    fn connection(&self) -> impl Future<Output = RedisResult<Self::Connection>> + Send {
+        let connection = self.clone();
+        async move { Ok(connection) }
+    }
+}
+
+/// Shares the managed connection between all operations, reconnecting as configured by the
+/// manager.
+#[cfg(feature = "connection-manager")]
+#[cfg_attr(docsrs, doc(cfg(feature = "connection-manager")))]
+impl ConnectionProvider for redis::aio::ConnectionManager {
+    type Connection = redis::aio::ConnectionManager;
+
+    #[inline]
+    fn connection(&self) -> impl Future<Output = RedisResult<Self::Connection>> + Send {
+        let connection = self.clone();
+        async move { Ok(connection) }
+    }
+}
+
+/// Checks out a connection from the pool for each operation, returning it to the pool once the
+/// operation completes.
+///
+/// Connections are not held between attempts while waiting to acquire the lock.
+#[cfg(feature = "bb8")]
+#[cfg_attr(docsrs, doc(cfg(feature = "bb8")))]
+impl ConnectionProvider for bb8::Pool<bb8_redis::RedisConnectionManager> {
+    type Connection = PooledConnection;
+
+    #[inline]
+    fn connection(&self) -> impl Future<Output = RedisResult<Self::Connection>> + Send {
+        let pool = self.clone();
+        async move {
+            match pool.get_owned().await {
+                Ok(connection) => Ok(PooledConnection(connection)),
+                Err(bb8::RunError::User(err)) => Err(err),
+                Err(bb8::RunError::TimedOut) => Err(RedisError::from((
+                    ErrorKind::IoError,
+                    "timed out waiting for a pooled connection",
+                ))),
+            }
+        }
+    }
+}
+
+/// A connection checked out from a [`bb8::Pool`], returned to the pool when dropped.
+#[cfg(feature = "bb8")]
+#[cfg_attr(docsrs, doc(cfg(feature = "bb8")))]
+pub struct PooledConnection(bb8::PooledConnection<'static, bb8_redis::RedisConnectionManager>);
+
+#[cfg(feature = "bb8")]
+impl std::fmt::Debug for PooledConnection {
+    #[inline]
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("PooledConnection").field(&"..").finish()
+    }
+}
+
+#[cfg(feature = "bb8")]
+impl ConnectionLike for PooledConnection {
+    #[inline]
+    fn req_packed_command<'a>(&'a mut self, cmd: &'a Cmd) -> RedisFuture<'a, Value> {
+        self.0.req_packed_command(cmd)
+    }
+
+    #[inline]
+    fn req_packed_commands<'a>(
+        &'a mut self,
+        cmd: &'a Pipeline,
+        offset: usize,
+        count: usize,
+    ) -> RedisFuture<'a, Vec<Value>> {
+        self.0.req_packed_commands(cmd, offset, count)
+    }
+
+    #[inline]
+    fn get_db(&self) -> i64 {
+        self.0.get_db()
+    }
+}
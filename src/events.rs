@@ -0,0 +1,39 @@
+use crate::hash_tag::HashTag;
+
+/// A lock being acquired or released, see
+/// [`MultiResourceLock::events`](crate::MultiResourceLock::events).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LockEvent {
+    /// The lock `lock_id` was acquired on `resources`, or extended to them.
+    Acquired {
+        /// The identifier of the lock.
+        lock_id: String,
+        /// The resources acquired.
+        resources: Vec<String>,
+    },
+    /// The lock `lock_id` was released from `resources`.
+    Released {
+        /// The identifier of the lock.
+        lock_id: String,
+        /// The resources released.
+        resources: Vec<String>,
+    },
+}
+
+impl LockEvent {
+    /// Reads an event published by the Lua library, its kind, lock identifier then resources
+    /// separated by NUL, mapping the resources back by `hash_tag`.
+    ///
+    /// Returns `None` when `payload` is not an event.
+    pub(crate) fn from_payload(payload: &str, hash_tag: &HashTag) -> Option<Self> {
+        let mut fields = payload.split('\0');
+        let kind = fields.next()?;
+        let lock_id = fields.next()?.to_owned();
+        let resources = hash_tag.resources(fields.map(str::to_owned).collect());
+        match kind {
+            "acquired" => Some(LockEvent::Acquired { lock_id, resources }),
+            "released" => Some(LockEvent::Released { lock_id, resources }),
+            _ => None,
+        }
+    }
+}
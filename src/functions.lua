@@ -1,8 +1,59 @@
-#!lua name=multilock
+#!lua name=multilock_v3
+
+-- The key prefixes, passed ahead of the arguments of every call, see `KeyFormatter`
+local prefix = {}
+
+-- Whether the `locks` and `resources` index sets are maintained, passed after the key prefixes
+local maintain_index = true
+
+-- Functions are suffixed with the library version so binaries built against different versions
+-- can run side by side, bump it here, in the library name and in `LIBRARY_VERSION` together
+-- whenever the arguments or replies of a function change.
+local function register(name, callback, flags)
+    redis.register_function{
+        function_name = name .. "_v3",
+        callback = function(keys, args)
+            prefix.lock, prefix.queue, prefix.waiter = args[1], args[2], args[3]
+            prefix.weighted, prefix.weights, prefix.index = args[4], args[5], args[6]
+            prefix.poison, prefix.events = args[7], args[8]
+            maintain_index = args[9] == "1"
+            local rest = {}
+            for i = 10, #args do
+                rest[i - 9] = args[i]
+            end
+            return callback(keys, rest)
+        end,
+        flags = flags,
+    }
+end
+
+local function now_millis()
+    local time = redis.call("TIME")
+    return tonumber(time[1]) * 1000 + math.floor(tonumber(time[2]) / 1000)
+end
+
+-- How long a contended attempt is counted by `waiter_count`, the default wait between attempts
+local ATTEMPT_WINDOW = 1000
+
+-- Runs `command` on the `set` index sorted set, doing nothing when the index is not maintained
+local function index_call(command, set, ...)
+    if not maintain_index then
+        return 0
+    end
+    return redis.call(command, prefix.index .. set, ...)
+end
+
+-- The expiry recorded in the `index:locks` and `index:resources` sorted sets
+local function index_expiry(expiration)
+    if expiration == 0 then
+        return "+inf"  -- Indefinite
+    end
+    return now_millis() + expiration
+end
 
 local function check_conflicts(resources)
     for i = 1, #resources do
-        local lock_key = "lock:" .. resources[i]
+        local lock_key = prefix.lock .. resources[i]
         local existing_lock = redis.call("GET", lock_key)
         if existing_lock then
             local lock_info = cjson.decode(existing_lock)
@@ -18,41 +69,471 @@ local function check_conflicts(resources)
     return false  -- No conflict
 end
 
-local function set_locks(lock_id, resources, expiration)
-    local lock_info = cjson.encode({holder = lock_id, resources = resources})
+local function queue_head(resource)
+    local queue_key = prefix.queue .. resource
+    while true do
+        local head = redis.call("ZRANGE", queue_key, 0, 0)[1]
+        if not head or redis.call("EXISTS", prefix.waiter .. head) == 1 then
+            return head
+        end
+        redis.call("ZREM", queue_key, head)  -- Gave up without leaving the queue
+    end
+end
+
+local function queued_ahead(resources, lock_id)
+    for i = 1, #resources do
+        local head = queue_head(resources[i])
+        if head and head ~= lock_id then
+            return true  -- Another waiter is first
+        end
+    end
+    return false
+end
+
+-- The resources held by another lock or with another waiter first in their queue
+local function contended_resources(resources, lock_id)
+    local contended = {}
+    for i = 1, #resources do
+        local head = queue_head(resources[i])
+        if (head and head ~= lock_id) or redis.call("EXISTS", prefix.lock .. resources[i]) == 1 then
+            table.insert(contended, resources[i])
+        end
+    end
+    return contended
+end
+
+local function leave_queue(lock_id, resources)
+    for i = 1, #resources do
+        redis.call("ZREM", prefix.queue .. resources[i], lock_id)
+    end
+    redis.call("DEL", prefix.waiter .. lock_id)
+end
+
+-- Sets the lock on each of `resources` to expire after the matching entry of `expirations`
+local function set_locks_expiring(lock_id, resources, expirations, metadata)
+    local lock_info = cjson.encode({holder = lock_id, resources = resources, metadata = metadata})
+    local indefinite = false
+    local longest = 0
+    for i = 1, #resources do
+        local lock_key = prefix.lock .. resources[i]
+        local expiration = expirations[i]
+        if expiration == 0 then
+            redis.call("SET", lock_key, lock_info)  -- Indefinite
+            indefinite = true
+        else
+            redis.call("SET", lock_key, lock_info, "PX", expiration)
+            longest = math.max(longest, expiration)
+        end
+        index_call("ZADD", "resources", index_expiry(expiration), resources[i])
+    end
+    -- The lock stays indexed until its longest-lived key expires
+    if indefinite then
+        longest = 0
+    end
+    index_call("ZADD", "locks", index_expiry(longest), lock_id)
+end
+
+local function set_locks(lock_id, resources, expiration, metadata)
+    local expirations = {}
     for i = 1, #resources do
-        local lock_key = "lock:" .. resources[i]
-        redis.call("SET", lock_key, lock_info, "EX", expiration)
+        expirations[i] = expiration
     end
+    set_locks_expiring(lock_id, resources, expirations, metadata)
+end
+
+-- The longest remaining expiration of the held keys of `resources`, 0 when one is indefinite
+local function longest_pttl(resources)
+    local longest = 0
+    for i = 1, #resources do
+        local ttl = redis.call("PTTL", prefix.lock .. resources[i])
+        if ttl == -1 then
+            return 0  -- Indefinite
+        end
+        longest = math.max(longest, ttl)
+    end
+    return longest
+end
+
+-- Rewrites the lock `lock_id` to hold `resources`, keeping the expiration of each key that is
+-- still held and setting missing keys to expire after `expiration`, or skipping them when it is
+-- nil
+local function update_locks(lock_id, resources, metadata, expiration)
+    local lock_info = cjson.encode({holder = lock_id, resources = resources, metadata = metadata})
+    for i = 1, #resources do
+        local lock_key = prefix.lock .. resources[i]
+        if redis.call("EXISTS", lock_key) == 1 then
+            redis.call("SET", lock_key, lock_info, "KEEPTTL")
+        elseif expiration == 0 then
+            redis.call("SET", lock_key, lock_info)  -- Indefinite
+            index_call("ZADD", "resources", index_expiry(0), resources[i])
+        elseif expiration then
+            redis.call("SET", lock_key, lock_info, "PX", expiration)
+            index_call("ZADD", "resources", index_expiry(expiration), resources[i])
+        end
+    end
+    -- The lock stays indexed until its longest-lived key expires
+    index_call("ZADD", "locks", index_expiry(longest_pttl(resources)), lock_id)
+end
+
+-- Publishes `event` for the lock `lock_id` on `resources` as NUL separated fields, see `LockEvent`
+local function publish(event, lock_id, resources)
+    if #resources > 0 then
+        redis.call("PUBLISH", prefix.events, table.concat({event, lock_id, unpack(resources)}, "\0"))
+    end
+end
+
+-- Strips the lock prefix from each of `lock_keys`
+local function key_resources(lock_keys)
+    local resources = {}
+    for _, key in ipairs(lock_keys) do
+        table.insert(resources, string.sub(key, #prefix.lock + 1))
+    end
+    return resources
+end
+
+-- Records a contended attempt by `lock_id` on each of `resources`, see `waiter_count`, doing
+-- nothing when the index is not maintained
+local function record_attempt(lock_id, resources)
+    if not maintain_index then
+        return
+    end
+    local now = now_millis()
+    for _, resource in ipairs(resources) do
+        local attempts_key = prefix.index .. "attempts:" .. resource
+        redis.call("ZADD", attempts_key, now, lock_id)
+        redis.call("ZREMRANGEBYSCORE", attempts_key, "-inf", now - ATTEMPT_WINDOW)
+        redis.call("PEXPIRE", attempts_key, ATTEMPT_WINDOW)
+    end
+end
+
+-- Checks the condition `kind` on `key`, an empty `kind` always passing, see `LuaCondition`
+local function check_condition(kind, key, value)
+    if kind == "" then
+        return true
+    elseif kind == "exists" then
+        return redis.call("EXISTS", key) == 1
+    elseif kind == "missing" then
+        return redis.call("EXISTS", key) == 0
+    end
+    local current = redis.call("GET", key)
+    if kind == "eq" then
+        return current == value
+    end
+    local number = current and tonumber(current)
+    if not number then
+        return false
+    elseif kind == "gt" then
+        return number > tonumber(value)
+    end
+    return number < tonumber(value)  -- "lt"
 end
 
 local function acquire_lock(keys, args)
     local lock_id = args[1]
     local expiration = tonumber(args[2])
+    local metadata = args[3]
+    if metadata == "" then
+        metadata = nil
+    end
     local resources = {}
-    for i = 3, #args do
+    for i = 7, #args do
+        table.insert(resources, args[i])
+    end
+    
+    if #resources == 0 then
+        return redis.error_reply("No resources specified")
+    end
+    
+    if not check_condition(args[4], args[5], args[6]) then
+        return 0  -- Precondition failed
+    end
+    
+    local contended = contended_resources(resources, lock_id)
+    if #contended > 0 then
+        record_attempt(lock_id, contended)
+        return contended  -- Conflict found
+    end
+    
+    set_locks(lock_id, resources, expiration, metadata)
+    publish("acquired", lock_id, resources)
+    return lock_id
+end
+
+local function acquire_lock_expiring(keys, args)
+    local lock_id = args[1]
+    local resources = {}
+    local expirations = {}
+    for i = 2, #args, 2 do
         table.insert(resources, args[i])
+        table.insert(expirations, tonumber(args[i + 1]))
     end
     
     if #resources == 0 then
         return redis.error_reply("No resources specified")
     end
     
-    if check_conflicts(resources) then
+    local contended = contended_resources(resources, lock_id)
+    if #contended > 0 then
+        record_attempt(lock_id, contended)
         return nil  -- Conflict found
     end
     
-    set_locks(lock_id, resources, expiration)
+    set_locks_expiring(lock_id, resources, expirations, nil)
+    publish("acquired", lock_id, resources)
     return lock_id
 end
 
-local function release_lock(keys, args)
+local function acquire_lock_queued(keys, args)
+    local lock_id = args[1]
+    local expiration = tonumber(args[2])
+    local metadata = args[3]
+    if metadata == "" then
+        metadata = nil
+    end
+    local waiter_expiration = tonumber(args[4])
+    local priority = tonumber(args[5])
+    local resources = {}
+    for i = 6, #args do
+        table.insert(resources, args[i])
+    end
+    
+    if #resources == 0 then
+        return redis.error_reply("No resources specified")
+    end
+    
+    -- Joins the queues ordered by descending priority then arrival, keeping the original
+    -- score when already queued. Priorities are within -128..127 so sit above the 42 bits
+    -- needed for the arrival in milliseconds, equal scores are ordered by lock id.
+    local score = (127 - priority) * 4398046511104 + now_millis()
+    redis.call("SET", prefix.waiter .. lock_id, 1, "PX", waiter_expiration)
+    for i = 1, #resources do
+        redis.call("ZADD", prefix.queue .. resources[i], "NX", score, lock_id)
+    end
+    
+    local contended = contended_resources(resources, lock_id)
+    if #contended > 0 then
+        return contended  -- Conflict found
+    end
+    
+    leave_queue(lock_id, resources)
+    set_locks(lock_id, resources, expiration, metadata)
+    publish("acquired", lock_id, resources)
+    return lock_id
+end
+
+local function dequeue(keys, args)
+    local lock_id = args[1]
+    local resources = {}
+    for i = 2, #args do
+        table.insert(resources, args[i])
+    end
+    leave_queue(lock_id, resources)
+    return 0
+end
+
+local function acquire_locks(keys, args)
+    local expiration = tonumber(args[1])
+    local sets = {}
+    local all_resources = {}
+    local owners = {}
+    local i = 2
+    while i <= #args do
+        local lock_id = args[i]
+        local count = tonumber(args[i + 1])
+        local resources = {}
+        for j = i + 2, i + 1 + count do
+            table.insert(resources, args[j])
+        end
+        
+        if #resources == 0 then
+            return redis.error_reply("No resources specified")
+        end
+        
+        for _, resource in ipairs(resources) do
+            if owners[resource] and owners[resource] ~= lock_id then
+                return redis.error_reply("Resource sets overlap")
+            end
+            owners[resource] = lock_id
+            table.insert(all_resources, resource)
+        end
+        table.insert(sets, {lock_id = lock_id, resources = resources})
+        i = i + 2 + count
+    end
+    
+    if queued_ahead(all_resources, nil) or check_conflicts(all_resources) then
+        return nil  -- Conflict found
+    end
+    
+    local lock_ids = {}
+    for _, set in ipairs(sets) do
+        set_locks(set.lock_id, set.resources, expiration, nil)
+        publish("acquired", set.lock_id, set.resources)
+        table.insert(lock_ids, set.lock_id)
+    end
+    return lock_ids
+end
+
+local function acquire_any(keys, args)
+    local lock_id = args[1]
+    local expiration = tonumber(args[2])
+    local sets = {}
+    local i = 3
+    while i <= #args do
+        local count = tonumber(args[i])
+        local resources = {}
+        for j = i + 1, i + count do
+            table.insert(resources, args[j])
+        end
+        
+        if #resources == 0 then
+            return redis.error_reply("No resources specified")
+        end
+        
+        table.insert(sets, resources)
+        i = i + 1 + count
+    end
+    
+    for index, resources in ipairs(sets) do
+        if #contended_resources(resources, lock_id) == 0 then
+            set_locks(lock_id, resources, expiration, nil)
+            publish("acquired", lock_id, resources)
+            return {index - 1, lock_id}  -- Indexed from 0
+        end
+    end
+    return nil  -- Every set is contended
+end
+
+-- Reclaims the weight of holders of `resource` that expired without releasing
+local function reclaim_weighted(resource)
+    local holders_key = prefix.weighted .. resource
+    local weights_key = prefix.weights .. resource
+    local now = now_millis()
+    local expired = redis.call("ZRANGEBYSCORE", holders_key, "-inf", now)
+    for i = 1, #expired do
+        redis.call("HDEL", weights_key, expired[i])
+    end
+    redis.call("ZREMRANGEBYSCORE", holders_key, "-inf", now)
+end
+
+local function acquire_weighted(keys, args)
+    local lock_id = args[1]
+    local expiration = tonumber(args[2])
+    local weight = tonumber(args[3])
+    local capacity = tonumber(args[4])
+    local resource = args[5]
+    
+    reclaim_weighted(resource)
+    local usage = 0
+    for _, held in ipairs(redis.call("HVALS", prefix.weights .. resource)) do
+        usage = usage + tonumber(held)
+    end
+    if usage + weight > capacity then
+        return nil  -- Over capacity
+    end
+    
+    redis.call("ZADD", prefix.weighted .. resource, index_expiry(expiration), lock_id)
+    redis.call("HSET", prefix.weights .. resource, lock_id, weight)
+    return lock_id
+end
+
+local function release_weighted(keys, args)
+    local resource = args[1]
+    local lock_id = args[2]
+    redis.call("ZREM", prefix.weighted .. resource, lock_id)
+    return redis.call("HDEL", prefix.weights .. resource, lock_id)
+end
+
+local function add_resources(keys, args)
+    local lock_id = args[1]
+    local held_count = tonumber(args[2])
+    local resources = {}
+    for i = 3, #args do
+        table.insert(resources, args[i])
+    end
+    
+    if held_count == 0 then
+        return redis.error_reply("No resources held")
+    end
+    
+    for i = 1, held_count do
+        local lock_data = redis.call("GET", prefix.lock .. resources[i])
+        if not lock_data or cjson.decode(lock_data).holder ~= lock_id then
+            return redis.error_reply("Lock not held")
+        end
+    end
+    
+    -- Resources already held by this lock are kept rather than contended
+    local added = {}
+    for i = held_count + 1, #resources do
+        local lock_data = redis.call("GET", prefix.lock .. resources[i])
+        if not lock_data or cjson.decode(lock_data).holder ~= lock_id then
+            local head = queue_head(resources[i])
+            if lock_data or (head and head ~= lock_id) then
+                return 0  -- Conflict found
+            end
+            table.insert(added, resources[i])
+        end
+    end
+    
+    -- Keeps the metadata and the expiration of each held resource, the added resources expire
+    -- with the longest-lived of them
+    local lock_info = cjson.decode(redis.call("GET", prefix.lock .. resources[1]))
+    update_locks(lock_id, resources, lock_info.metadata, longest_pttl(resources))
+    publish("acquired", lock_id, added)
+    return 1
+end
+
+-- Extends the lock `lock_id` on each of `resources` to expire after `expiration`, or no sooner
+-- than it when `at_least`, failing unless it holds every one of them
+local function extend_resources(lock_id, resources, expiration, at_least)
+    for i = 1, #resources do
+        local lock_data = redis.call("GET", prefix.lock .. resources[i])
+        if not lock_data or cjson.decode(lock_data).holder ~= lock_id then
+            return 0  -- Expired or held by another lock
+        end
+    end
+    
+    for i = 1, #resources do
+        local lock_key = prefix.lock .. resources[i]
+        if expiration == 0 then
+            redis.call("PERSIST", lock_key)  -- Indefinite
+        elseif at_least then
+            redis.call("PEXPIRE", lock_key, expiration, "GT")  -- Indefinite keys are left as is
+        else
+            redis.call("PEXPIRE", lock_key, expiration)
+        end
+    end
+    local expiry = index_expiry(expiration)
+    local update = at_least and "GT" or "CH"  -- CH changes nothing but the reply
+    index_call("ZADD", "locks", "XX", update, expiry, lock_id)
+    for i = 1, #resources do
+        index_call("ZADD", "resources", "XX", update, expiry, resources[i])
+    end
+    return 1
+end
+
+local function extend_lock(keys, args)
     local lock_id = args[1]
+    local expiration = tonumber(args[2])
+    local at_least = args[3] == "1"
+    local resources = {}
+    for i = 4, #args do
+        table.insert(resources, args[i])
+    end
+    
+    if #resources == 0 then
+        return redis.error_reply("No resources specified")
+    end
+    
+    return extend_resources(lock_id, resources, expiration, at_least)
+end
+
+local function find_held_keys(lock_ids)
     local cursor = "0"
-    local keys_to_delete = {}
+    local lock_keys = {}
     
     repeat
-        local result = redis.call("SCAN", cursor, "MATCH", "lock:*")
+        local result = redis.call("SCAN", cursor, "MATCH", prefix.lock .. "*")
         cursor = result[1]
         local keys = result[2]
         
@@ -60,20 +541,438 @@ local function release_lock(keys, args)
             local lock_data = redis.call("GET", key)
             if lock_data then
                 local lock_info = cjson.decode(lock_data)
-                if lock_info.holder == lock_id then
-                    table.insert(keys_to_delete, key)
+                if lock_ids[lock_info.holder] then
+                    table.insert(lock_keys, {key = key, holder = lock_info.holder})
                 end
             end
         end
     until cursor == "0"
     
+    return lock_keys
+end
+
+local function find_lock_keys(lock_id)
+    local lock_keys = {}
+    for _, held in ipairs(find_held_keys({[lock_id] = true})) do
+        table.insert(lock_keys, held.key)
+    end
+    return lock_keys
+end
+
+-- Whether the lock `lock_id` holds every one of `resources`
+local function holds_resources(lock_id, resources)
+    for i = 1, #resources do
+        local lock_data = redis.call("GET", prefix.lock .. resources[i])
+        if not lock_data or cjson.decode(lock_data).holder ~= lock_id then
+            return false
+        end
+    end
+    return true
+end
+
+local function delete_keys(keys_to_delete)
     if #keys_to_delete > 0 then
         redis.call("DEL", unpack(keys_to_delete))
     end
+    for _, resource in ipairs(key_resources(keys_to_delete)) do
+        index_call("ZREM", "resources", resource)
+    end
     
     return #keys_to_delete
 end
 
+local function release_lock(keys, args)
+    local lock_id = args[1]
+    local poison, poison_ttl = args[2] == "1", tonumber(args[3])
+    local lock_keys = find_lock_keys(lock_id)
+    local released = delete_keys(lock_keys)
+    local resources = key_resources(lock_keys)
+    -- Marks the resources as released in a potentially inconsistent state
+    if poison then
+        for _, resource in ipairs(resources) do
+            if poison_ttl > 0 then
+                redis.call("SET", prefix.poison .. resource, lock_id, "PX", poison_ttl)
+            else
+                redis.call("SET", prefix.poison .. resource, lock_id)
+            end
+        end
+    end
+    publish("released", lock_id, resources)
+    -- Still indexed without keys when it expired, until the index is next pruned
+    local indexed = index_call("ZREM", "locks", lock_id)
+    local expired = 0
+    if released == 0 and indexed == 1 then
+        expired = 1
+    end
+    return {released, expired}
+end
+
+local function release_resources(keys, args)
+    local lock_id = args[1]
+    local release_count = tonumber(args[2])
+    local released = {}
+    local remaining = {}
+    for i = 3, #args do
+        if i < release_count + 3 then
+            table.insert(released, prefix.lock .. args[i])
+        else
+            table.insert(remaining, args[i])
+        end
+    end
+    
+    for i = 1, #released do
+        local lock_data = redis.call("GET", released[i])
+        if not lock_data or cjson.decode(lock_data).holder ~= lock_id then
+            return redis.error_reply("Resource not held by lock")
+        end
+    end
+    
+    local lock_info = nil
+    if #remaining > 0 then
+        local lock_data = redis.call("GET", prefix.lock .. remaining[1])
+        if lock_data then
+            lock_info = cjson.decode(lock_data)
+        end
+    end
+    
+    local cleared = delete_keys(released)
+    publish("released", lock_id, key_resources(released))
+    if lock_info and lock_info.holder == lock_id then
+        -- Keeps the metadata and the expiration of each remaining resource
+        update_locks(lock_id, remaining, lock_info.metadata, nil)
+    else
+        index_call("ZREM", "locks", lock_id)
+    end
+    return cleared
+end
+
+local function release_locks(keys, args)
+    local lock_ids = {}
+    for _, lock_id in ipairs(args) do
+        lock_ids[lock_id] = true
+    end
+    local lock_keys = {}
+    local released = {}
+    local released_count = 0
+    for _, held in ipairs(find_held_keys(lock_ids)) do
+        table.insert(lock_keys, held.key)
+        if not released[held.holder] then
+            released[held.holder] = {}
+            released_count = released_count + 1
+            index_call("ZREM", "locks", held.holder)
+        end
+        table.insert(released[held.holder], held.key)
+    end
+    local cleared = delete_keys(lock_keys)
+    for holder, held_keys in pairs(released) do
+        publish("released", holder, key_resources(held_keys))
+    end
+    return {cleared, released_count}
+end
+
+local function force_release(keys, args)
+    local resource = args[1]
+    local lock_data = redis.call("GET", prefix.lock .. resource)
+    if not lock_data then
+        return 0
+    end
+    local lock_info = cjson.decode(lock_data)
+    local cleared = delete_keys({prefix.lock .. resource})
+    publish("released", lock_info.holder, {resource})
+    -- Drops the lock from the index when this was the last of its resources
+    for _, other in ipairs(lock_info.resources) do
+        local other_data = redis.call("GET", prefix.lock .. other)
+        if other_data and cjson.decode(other_data).holder == lock_info.holder then
+            return cleared
+        end
+    end
+    index_call("ZREM", "locks", lock_info.holder)
+    return cleared
+end
+
+local function force_release_resources(keys, args)
+    local lock_keys = {}
+    local released = {}
+    local holders = {}
+    local seen = {}
+    for _, resource in ipairs(args) do
+        local lock_data = not seen[resource] and redis.call("GET", prefix.lock .. resource)
+        seen[resource] = true
+        if lock_data then
+            local lock_info = cjson.decode(lock_data)
+            table.insert(lock_keys, prefix.lock .. resource)
+            if not released[lock_info.holder] then
+                released[lock_info.holder] = {info = lock_info, resources = {}}
+                table.insert(holders, lock_info.holder)
+            end
+            table.insert(released[lock_info.holder].resources, resource)
+        end
+    end
+    local cleared = delete_keys(lock_keys)
+    for _, holder in ipairs(holders) do
+        publish("released", holder, released[holder].resources)
+        -- Drops the lock from the index when none of its resources remain held
+        local remaining = false
+        for _, other in ipairs(released[holder].info.resources) do
+            local other_data = redis.call("GET", prefix.lock .. other)
+            if other_data and cjson.decode(other_data).holder == holder then
+                remaining = true
+                break
+            end
+        end
+        if not remaining then
+            index_call("ZREM", "locks", holder)
+        end
+    end
+    return {cleared, holders}
+end
+
+local function force_release_lock(keys, args)
+    local lock_id = args[1]
+    index_call("ZREM", "locks", lock_id)
+    local lock_keys = find_lock_keys(lock_id)
+    publish("released", lock_id, key_resources(lock_keys))
+    return delete_keys(lock_keys)
+end
+
+local function lock_stats(keys, args)
+    local now = now_millis()
+    index_call("ZREMRANGEBYSCORE", "locks", "-inf", now)
+    index_call("ZREMRANGEBYSCORE", "resources", "-inf", now)
+    return {index_call("ZCARD", "locks"), index_call("ZCARD", "resources")}
+end
+
+local function compact_index(keys, args)
+    local indexed = {}
+    for _, lock_id in ipairs(redis.call("ZRANGE", prefix.index .. "locks", 0, -1)) do
+        indexed[lock_id] = true
+    end
+    local held = {}
+    for _, entry in ipairs(find_held_keys(indexed)) do
+        held[entry.holder] = true
+    end
+    local pruned = 0
+    for lock_id in pairs(indexed) do
+        if not held[lock_id] then
+            redis.call("ZREM", prefix.index .. "locks", lock_id)
+            pruned = pruned + 1
+        end
+    end
+    for _, resource in ipairs(redis.call("ZRANGE", prefix.index .. "resources", 0, -1)) do
+        if redis.call("EXISTS", prefix.lock .. resource) == 0 then
+            redis.call("ZREM", prefix.index .. "resources", resource)
+        end
+    end
+    return pruned
+end
+
+local function lock_held(keys, args)
+    local lock_id = args[1]
+    return #find_lock_keys(lock_id) > 0
+end
+
+local function lock_owns(keys, args)
+    local lock_id = args[1]
+    local resources = {}
+    for i = 2, #args do
+        table.insert(resources, args[i])
+    end
+    return holds_resources(lock_id, resources) and 1 or 0
+end
+
+local function queue_position(keys, args)
+    local resource = args[1]
+    local lock_id = args[2]
+    if redis.call("EXISTS", prefix.waiter .. lock_id) == 0 then
+        return nil  -- Not waiting
+    end
+    local queue_key = prefix.queue .. resource
+    local rank = redis.call("ZRANK", queue_key, lock_id)
+    if not rank then
+        return nil  -- Not queued on this resource
+    end
+    -- Counts live waiters ahead, skipping those that gave up without leaving the queue
+    local position = 0
+    local ahead = redis.call("ZRANGE", queue_key, 0, rank - 1)
+    for i = 1, #ahead do
+        if redis.call("EXISTS", prefix.waiter .. ahead[i]) == 1 then
+            position = position + 1
+        end
+    end
+    return position
+end
+
+-- Runs the commands, each prefixed by its number of arguments, when the lock `lock_id` holds
+-- every one of the resources before them
+local function guarded_exec(keys, args)
+    local lock_id = args[1]
+    local resource_count = tonumber(args[2])
+    if resource_count == 0 then
+        return redis.error_reply("No resources specified")
+    end
+    local resources = {}
+    for i = 3, 2 + resource_count do
+        table.insert(resources, args[i])
+    end
+    if not holds_resources(lock_id, resources) then
+        return nil  -- Not held
+    end
+    local results = {}
+    local i = 3 + resource_count
+    while i <= #args do
+        local count = tonumber(args[i])
+        local command = {}
+        for j = i + 1, i + count do
+            table.insert(command, args[j])
+        end
+        table.insert(results, redis.call(unpack(command)))
+        i = i + 1 + count
+    end
+    return results
+end
+
+-- Moves `amount` from the integer at `from` to the integer at `to` when `from` holds enough and
+-- the lock `lock_id` holds both
+local function guarded_move(keys, args)
+    local lock_id = args[1]
+    local from = args[2]
+    local to = args[3]
+    local amount = tonumber(args[4])
+    if amount < 0 then
+        return redis.error_reply("Negative amount")
+    end
+    -- Both keys must be resources of the lock, under the names they are locked under
+    if not holds_resources(lock_id, {args[5], args[6]}) then
+        return nil  -- Not held
+    end
+    local balance = tonumber(redis.call("GET", from) or "0")  -- Missing keys hold 0
+    if not balance then
+        return redis.error_reply("Balance is not a number")
+    end
+    if balance < amount then
+        return 0  -- Insufficient balance
+    end
+    redis.call("DECRBY", from, amount)
+    redis.call("INCRBY", to, amount)
+    return 1
+end
+
+local function resource_metadata(keys, args)
+    local resource = args[1]
+    local lock_data = redis.call("GET", prefix.lock .. resource)
+    if lock_data then
+        return cjson.decode(lock_data).metadata
+    end
+    return nil
+end
+
+local function lock_metadata(keys, args)
+    local lock_id = args[1]
+    local lock_keys = find_lock_keys(lock_id)
+    if #lock_keys > 0 then
+        local lock_data = redis.call("GET", lock_keys[1])
+        return cjson.decode(lock_data).metadata
+    end
+    return nil
+end
+
+-- Returns 1 for each resource held by a lock or an unexpired weighted lock, else 0, writing nothing
+local function probe(keys, args)
+    local now = now_millis()
+    local held = {}
+    for i = 1, #args do
+        local locked = redis.call("EXISTS", prefix.lock .. args[i]) == 1
+            or redis.call("ZCOUNT", prefix.weighted .. args[i], "(" .. now, "+inf") > 0
+        held[i] = locked and 1 or 0
+    end
+    return held
+end
+
+-- The live waiters queued for a resource, plus the contended attempts on it within the last
+-- `ATTEMPT_WINDOW` approximating the waiters retrying without queueing
+local function waiter_count(keys, args)
+    local resource = args[1]
+    local waiters = 0
+    for _, waiter in ipairs(redis.call("ZRANGE", prefix.queue .. resource, 0, -1)) do
+        if redis.call("EXISTS", prefix.waiter .. waiter) == 1 then
+            waiters = waiters + 1
+        end
+    end
+    local since = now_millis() - ATTEMPT_WINDOW
+    local attempts = redis.call("ZCOUNT", prefix.index .. "attempts:" .. resource, "(" .. since, "+inf")
+    return waiters + attempts
+end
+
+local function poisoned(keys, args)
+    return redis.call("EXISTS", prefix.poison .. args[1])
+end
+
+local function clear_poison(keys, args)
+    return redis.call("DEL", prefix.poison .. args[1])
+end
+
+local function lock_resources(keys, args)
+    local lock_id = args[1]
+    local resources = key_resources(find_lock_keys(lock_id))
+    table.sort(resources)
+    return resources
+end
+
+-- Returns each resource held by the lock `lock_id` sorted, with its remaining time to live in
+-- milliseconds, -1 when indefinite
+local function lock_ttls(keys, args)
+    local lock_id = args[1]
+    local lock_keys = find_lock_keys(lock_id)
+    table.sort(lock_keys)
+    local ttls = {}
+    for _, key in ipairs(lock_keys) do
+        table.insert(ttls, {string.sub(key, #prefix.lock + 1), redis.call("PTTL", key)})
+    end
+    return ttls
+end
+
+-- Extends every resource held by the lock `lock_id` to expire no sooner than `minimum`, finding
+-- them in the same call so the lock cannot change between the two
+local function extend_lock_at_least(keys, args)
+    local lock_id = args[1]
+    local minimum = tonumber(args[2])
+    local resources = key_resources(find_lock_keys(lock_id))
+    if #resources == 0 then
+        return 0  -- Not held
+    end
+    return extend_resources(lock_id, resources, minimum, true)
+end
+
 -- Register functions
-redis.register_function('acquire_lock', acquire_lock)
-redis.register_function('release_lock', release_lock)
\ No newline at end of file
+register('acquire_lock', acquire_lock)
+register('acquire_lock_queued', acquire_lock_queued)
+register('acquire_lock_expiring', acquire_lock_expiring)
+register('dequeue', dequeue)
+register('acquire_locks', acquire_locks)
+register('acquire_any', acquire_any)
+register('acquire_weighted', acquire_weighted)
+register('release_weighted', release_weighted)
+register('add_resources', add_resources)
+register('extend_lock', extend_lock)
+register('extend_lock_at_least', extend_lock_at_least)
+register('release_lock', release_lock)
+register('release_locks', release_locks)
+register('release_resources', release_resources)
+register('force_release', force_release)
+register('force_release_lock', force_release_lock)
+register('force_release_resources', force_release_resources)
+register('compact_index', compact_index)
+register('lock_stats', lock_stats)
+register('lock_held', lock_held)
+register('lock_owns', lock_owns, { 'no-writes' })
+register('queue_position', queue_position)
+register('guarded_exec', guarded_exec)
+register('guarded_move', guarded_move)
+register('resource_metadata', resource_metadata)
+register('lock_metadata', lock_metadata)
+register('lock_resources', lock_resources)
+register('lock_ttls', lock_ttls)
+register('probe', probe, { 'no-writes' })
+register('waiter_count', waiter_count, { 'no-writes' })
+register('poisoned', poisoned, { 'no-writes' })
+register('clear_poison', clear_poison)
\ No newline at end of file
@@ -0,0 +1,114 @@
+use crate::keys::{KeyFormatter as _, LockKeys};
+use redis::{ErrorKind, RedisError, RedisResult};
+use std::borrow::Cow;
+
+/// How resources map to the names they are locked under, see
+/// [`MultiResourceLock::with_hash_tag`](crate::MultiResourceLock::with_hash_tag).
+///
+/// This only controls the slots of the keys holding the locks on resources, named by
+/// [`KeyFormatter::lock_prefix`](crate::keys::KeyFormatter::lock_prefix). The slot of a key
+/// containing a hash tag, e.g. `lock:{group}:a`, is that of the text between the braces.
+///
+/// Locking is not supported on Redis Cluster. The Lua functions are called without declaring the
+/// keys they use, and also use the queue, waiter and index keys shared between locks.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum HashTag {
+    /// Resources are locked under their own names without checking their slots.
+    #[default]
+    Disabled,
+    /// Resources are locked under their own names, erroring when the resources of a lock hash to
+    /// different slots, e.g. for resources already containing hash tags.
+    Existing,
+    /// Resources are locked under their names prefixed with the hash tag `{group}:`, so the
+    /// resources of every lock share a slot.
+    Group(String),
+}
+
+impl HashTag {
+    /// Maps `resources` to the names they are locked under, with their keys named by `formatter`.
+    ///
+    /// # Errors
+    ///
+    /// When the keys of the resources hash to different slots.
+    pub(crate) fn keys<'a>(
+        &self,
+        resources: Cow<'a, [String]>,
+        formatter: &LockKeys,
+    ) -> RedisResult<Cow<'a, [String]>> {
+        match self {
+            HashTag::Disabled => Ok(resources),
+            HashTag::Existing => {
+                let mut slots = resources.iter().map(|resource| {
+                    (
+                        resource,
+                        slot(&format!("{}{resource}", formatter.lock_prefix())),
+                    )
+                });
+                if let Some((_, first)) = slots.next() {
+                    if let Some((resource, _)) = slots.find(|&(_, other)| other != first) {
+                        return Err(RedisError::from((
+                            ErrorKind::CrossSlot,
+                            "Resources hash to different slots",
+                            resource.clone(),
+                        )));
+                    }
+                }
+                Ok(resources)
+            }
+            HashTag::Group(_) => Ok(Cow::Owned(
+                resources
+                    .iter()
+                    .map(|resource| self.key(resource).into_owned())
+                    .collect(),
+            )),
+        }
+    }
+
+    /// Maps `resource` to the name it is locked under.
+    pub(crate) fn key<'a>(&self, resource: &'a str) -> Cow<'a, str> {
+        match self {
+            HashTag::Disabled | HashTag::Existing => Cow::Borrowed(resource),
+            HashTag::Group(group) => Cow::Owned(format!("{{{group}}}:{resource}")),
+        }
+    }
+
+    /// Maps the names `keys` are locked under back to their resources.
+    pub(crate) fn resources(&self, keys: Vec<String>) -> Vec<String> {
+        match self {
+            HashTag::Disabled | HashTag::Existing => keys,
+            HashTag::Group(group) => {
+                let prefix = format!("{{{group}}}:");
+                keys.into_iter()
+                    .map(|key| match key.strip_prefix(&prefix) {
+                        Some(resource) => resource.to_owned(),
+                        None => key,
+                    })
+                    .collect()
+            }
+        }
+    }
+}
+
+/// Returns the Redis Cluster hash slot of `key`.
+pub(crate) fn slot(key: &str) -> u16 {
+    let hashed = key
+        .split_once('{')
+        .and_then(|(_, rest)| rest.split_once('}'))
+        .map(|(tag, _)| tag)
+        .filter(|tag| !tag.is_empty())
+        .unwrap_or(key);
+    crc16(hashed.as_bytes()) & 0x3FFF
+}
+
+/// The CRC16 (XMODEM) checksum Redis Cluster hashes keys with.
+fn crc16(bytes: &[u8]) -> u16 {
+    bytes.iter().fold(0u16, |checksum, &byte| {
+        (0u8..8).fold(checksum ^ (u16::from(byte) << 8u8), |crc, _| {
+            if crc & 0x8000 == 0 {
+                crc << 1u8
+            } else {
+                (crc << 1u8) ^ 0x1021
+            }
+        })
+    })
+}
@@ -0,0 +1,33 @@
+use uuid::Uuid;
+
+/// Generates the identifiers of locks, see
+/// [`MultiResourceLock::with_id_generator`](crate::MultiResourceLock::with_id_generator).
+///
+/// Identifiers must be unique among every user of a Redis instance, as a lock can be released or
+/// extended by anyone with its identifier. Waiters queued in the same millisecond with the same
+/// priority acquire the lock in the order of their identifiers, so time sortable identifiers
+/// (e.g. `UUIDv7` or `ULID`) make them acquire it in the order they started waiting.
+///
+/// Implemented for closures, e.g. `Arc::new(|| format!("{host}-{}", counter.fetch_add(1, ..)))`.
+pub trait IdGenerator: Send + Sync {
+    /// Returns a new lock identifier.
+    fn generate(&self) -> String;
+}
+
+/// Generates random version 4 UUIDs, used by default.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct UuidV4;
+
+impl IdGenerator for UuidV4 {
+    #[inline]
+    fn generate(&self) -> String {
+        Uuid::new_v4().to_string()
+    }
+}
+
+impl<F: Fn() -> String + Send + Sync> IdGenerator for F {
+    #[inline]
+    fn generate(&self) -> String {
+        self()
+    }
+}
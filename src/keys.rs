@@ -0,0 +1,140 @@
+use std::sync::Arc;
+
+/// Names the Redis keys locks are stored under, see
+/// [`MultiResourceLock::with_key_formatter`](crate::MultiResourceLock::with_key_formatter).
+///
+/// Each key is a prefix followed by a resource or lock identifier, by default:
+///
+/// - `lock:<resource>`: a string holding the lock on `resource` as JSON, e.g.
+///   `{"holder":"<lock_id>","resources":["a","b"],"metadata":"..."}`, expiring with the lock.
+/// - `queue:<resource>`: a sorted set of the lock identifiers of waiters queued for `resource`.
+/// - `waiter:<lock_id>`: a string present while the waiter `lock_id` is queued.
+/// - `weighted:<resource>`: a sorted set of the weighted locks holding `resource` by expiry.
+/// - `weights:<resource>`: a hash of the units of `resource` held by each weighted lock.
+/// - `index:locks` and `index:resources`: sorted sets of the held locks and locked resources by
+///   expiry, unless disabled with
+///   [`MultiResourceLock::with_index`](crate::MultiResourceLock::with_index).
+/// - `index:attempts:<resource>`: a sorted set of the recent contended attempts on `resource` by
+///   time.
+/// - `poison:<resource>`: a string holding the identifier of the lock that released `resource`
+///   while panicking, expiring with the poison TTL.
+///
+/// Lock events are published on the channel `lock-events`, see
+/// [`MultiResourceLock::events`](crate::MultiResourceLock::events).
+///
+/// The prefixes are passed to the Lua library functions with each call, so every user of a Redis
+/// instance must use the same prefixes. The lock prefix is matched with `SCAN`, so should not
+/// contain glob characters.
+#[expect(
+    clippy::unnecessary_literal_bound,
+    reason = "Implementations may borrow their prefixes from `self`."
+)]
+pub trait KeyFormatter: Send + Sync {
+    /// The prefix of the key holding the lock on a resource.
+    #[inline]
+    fn lock_prefix(&self) -> &str {
+        "lock:"
+    }
+    /// The prefix of the key queueing waiters for a resource.
+    #[inline]
+    fn queue_prefix(&self) -> &str {
+        "queue:"
+    }
+    /// The prefix of the key marking a queued waiter.
+    #[inline]
+    fn waiter_prefix(&self) -> &str {
+        "waiter:"
+    }
+    /// The prefix of the key holding the weighted locks on a resource.
+    #[inline]
+    fn weighted_prefix(&self) -> &str {
+        "weighted:"
+    }
+    /// The prefix of the key holding the weights of the weighted locks on a resource.
+    #[inline]
+    fn weights_prefix(&self) -> &str {
+        "weights:"
+    }
+    /// The prefix of the `locks`, `resources` and `attempts` index keys.
+    #[inline]
+    fn index_prefix(&self) -> &str {
+        "index:"
+    }
+    /// The prefix of the key marking a resource as poisoned.
+    #[inline]
+    fn poison_prefix(&self) -> &str {
+        "poison:"
+    }
+    /// The channel lock events are published on.
+    #[inline]
+    fn events_channel(&self) -> &str {
+        "lock-events"
+    }
+}
+
+/// [`KeyFormatter`] with the default prefixes, used by default.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DefaultKeys;
+
+impl KeyFormatter for DefaultKeys {}
+
+/// The [`KeyFormatter`] of a lock, along with whether it maintains the `index:locks` and
+/// `index:resources` sets.
+pub(crate) struct LockKeys {
+    /// The names of the keys.
+    formatter: Arc<dyn KeyFormatter>,
+    /// Whether the index sets are maintained.
+    indexed: bool,
+}
+
+impl LockKeys {
+    /// Names the keys with `formatter`, maintaining the index sets when `indexed`.
+    pub(crate) fn new(formatter: Arc<dyn KeyFormatter>, indexed: bool) -> Arc<Self> {
+        Arc::new(LockKeys { formatter, indexed })
+    }
+
+    /// Returns the names of the keys.
+    pub(crate) fn formatter(&self) -> &Arc<dyn KeyFormatter> {
+        &self.formatter
+    }
+
+    /// Returns whether the index sets are maintained.
+    pub(crate) fn indexed(&self) -> bool {
+        self.indexed
+    }
+}
+
+impl KeyFormatter for LockKeys {
+    #[inline]
+    fn lock_prefix(&self) -> &str {
+        self.formatter.lock_prefix()
+    }
+    #[inline]
+    fn queue_prefix(&self) -> &str {
+        self.formatter.queue_prefix()
+    }
+    #[inline]
+    fn waiter_prefix(&self) -> &str {
+        self.formatter.waiter_prefix()
+    }
+    #[inline]
+    fn weighted_prefix(&self) -> &str {
+        self.formatter.weighted_prefix()
+    }
+    #[inline]
+    fn weights_prefix(&self) -> &str {
+        self.formatter.weights_prefix()
+    }
+    #[inline]
+    fn index_prefix(&self) -> &str {
+        self.formatter.index_prefix()
+    }
+    #[inline]
+    fn poison_prefix(&self) -> &str {
+        self.formatter.poison_prefix()
+    }
+    #[inline]
+    fn events_channel(&self) -> &str {
+        self.formatter.events_channel()
+    }
+}
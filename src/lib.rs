@@ -7,11 +7,11 @@
 //! # #[allow(dependency_on_unit_never_type_fallback)]
 //! # fn main() -> Result<(), Box<dyn std::error::Error>> {
 //! # tokio::runtime::Runtime::new()?.block_on(async {
-//! # let client = todo!();
+//! # let client: redis::Client = todo!();
 //! // Setup.
 //! redis_lock::setup(&client).await?;
 //! // Get lock.
-//! let mut lock = redis_lock::MultiResourceLock::new(client.clone())?;
+//! let lock = redis_lock::MultiResourceLock::new(client.clone())?;
 //! let resources = vec![String::from("account1"), String::from("account2")];
 //! // Execute a function with the lock.
 //! lock.map_default(&resources, async move { /* .. */ }).await?;
@@ -20,6 +20,12 @@
 //! # }
 //! ```
 //!
+//! ## Runtimes
+//!
+//! The `tokio` feature is enabled by default, for `async-std` disable default features and enable
+//! `async-std`. The clock used while waiting for the lock can be replaced with
+//! [`MultiResourceLock::with_clock`].
+//!
 //! ## Vs [rslock](https://github.com/hexcowboy/rslock)
 //!
 //! I would recommend this library over [rslock](https://github.com/hexcowboy/rslock) when:
@@ -30,34 +36,173 @@
 //!
 //! - <https://github.com/hexcowboy/rslock>
 
+use condition::LuaCondition;
+use connection::ConnectionProvider;
+use hash_tag::HashTag;
+use id::{IdGenerator, UuidV4};
+use keys::{DefaultKeys, KeyFormatter, LockKeys};
+#[cfg(not(any(feature = "tokio", feature = "async-std")))]
+compile_error!("either the `tokio` or `async-std` feature must be enabled");
+
 use displaydoc::Display;
-use redis::{Client, RedisError, RedisResult};
+use events::LockEvent;
+use futures_util::{Stream, StreamExt as _};
+use metrics::{LockStats, Metrics, NoopMetrics};
+use rand::Rng as _;
+use redis::aio::ConnectionLike;
+use redis::{Arg, Client, Cmd, ErrorKind, FromRedisValue, RedisError, RedisResult, Value};
+use resource::{Resource, ResourceKey};
+use retry::{Attempts, Constant, RetryStrategy};
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
 use std::error::Error;
 use std::future::Future;
-use std::time::Duration;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use thiserror::Error;
-use uuid::Uuid;
+use time::Clock;
+use token::LockToken;
 
 /// Synchronous implementation of the lock.
 #[cfg(feature = "sync")]
 #[cfg_attr(docsrs, doc(cfg(feature = "sync")))]
 pub mod sync;
 
+/// Hooks for exporting lock metrics.
+pub mod metrics;
+
+/// Lock metrics exported in the Prometheus text format.
+#[cfg(feature = "prometheus")]
+#[cfg_attr(docsrs, doc(cfg(feature = "prometheus")))]
+pub mod prometheus;
+
+/// Sources of Redis connections.
+pub mod connection;
+
+/// Clocks used while waiting to acquire the lock.
+pub mod time;
+
+/// Strategies for retrying attempts to acquire the lock.
+pub mod retry;
+
+/// Mapping of resources to the hash slots of their lock keys.
+pub mod hash_tag;
+
+/// Conditions checked atomically with acquiring the lock.
+pub mod condition;
+
+/// Naming of the Redis keys locks are stored under.
+pub mod keys;
+
+/// Generation of lock identifiers.
+pub mod id;
+
+/// Events published as locks are acquired and released.
+pub mod events;
+
+/// Tokens for passing a held lock between processes.
+pub mod token;
+
+/// Resources owned by namespaces.
+pub mod resource;
+
+/// A lock that never excludes, for running without Redis.
+pub mod null;
+
+/// Locks released together at the end of a unit of work.
+pub mod scope;
+
+/// Which locks block each other, computed without Redis.
+pub mod overlap;
+
+/// In-memory implementation of the lock for tests.
+#[cfg(feature = "mock")]
+#[cfg_attr(docsrs, doc(cfg(feature = "mock")))]
+pub mod mock;
+
+/// Ephemeral Redis servers for integration tests.
+#[cfg(feature = "test-support")]
+#[cfg_attr(docsrs, doc(cfg(feature = "test-support")))]
+pub mod test_support;
+
+/// Queueing of contenders within the process.
+#[cfg(feature = "tokio")]
+mod local;
+
 /// A distributed mutual exclusion lock backed by Redis.
 ///
 /// Supports exclusion based on multiple resources and partial overlaps.
 ///
 /// E.g. a lock on resources `["a", "b"]` will block a lock on `["a"]` or `["b", "c"]`.
-pub struct MultiResourceLock {
-    /// The Redis client.
-    client: Client,
+///
+/// Commands are run on connections from `C`, by default a new connection is opened from a
+/// [`Client`] for each command.
+///
+/// Operations take `&self`, so the lock can be shared between tasks by reference or in an
+/// [`Arc`]. Clones are cheap and share the connection provider, metrics hooks and clock.
+#[expect(
+    clippy::struct_excessive_bools,
+    reason = "Each is an independent option set by its own builder method."
+)]
+#[derive(Clone)]
+pub struct MultiResourceLock<C = Client> {
+    /// The source of Redis connections.
+    provider: C,
+    /// The metrics hooks.
+    metrics: Arc<dyn Metrics>,
+    /// The fraction of the expiration randomly added to or subtracted from it.
+    expiration_jitter: f64,
+    /// Whether waiters queue to acquire the lock in order.
+    fair: bool,
+    /// Whether duplicate resources are an error rather than ignored.
+    strict_resources: bool,
+    /// Whether an expiration shorter than the wait to acquire the lock is an error rather than
+    /// logged.
+    strict_expiration: bool,
+    /// Whether the Lua library is loaded when a function is found to be missing.
+    auto_setup: bool,
+    /// Whether transient errors while waiting to acquire the lock are retried.
+    retry_transient: bool,
+    /// How many times releasing a lock is retried after a transient error.
+    release_retries: u32,
+    /// How long each Redis call may take, unbounded when `None`.
+    command_timeout: Option<Duration>,
+    /// How resources map to the names they are locked under.
+    hash_tag: HashTag,
+    /// Names the Redis keys locks are stored under.
+    key_formatter: Arc<LockKeys>,
+    /// Generates the identifiers of locks.
+    id_generator: Arc<dyn IdGenerator>,
+    /// The clock used to time attempts to acquire the lock.
+    clock: Arc<dyn Clock>,
+    /// The target lock lifecycle events are logged to.
+    #[cfg(feature = "log")]
+    log_target: &'static str,
+    /// Queues the contenders within this process, shared between clones.
+    #[cfg(feature = "tokio")]
+    local: Option<Arc<local::LocalQueue>>,
 }
 
-impl std::fmt::Debug for MultiResourceLock {
+impl<C> std::fmt::Debug for MultiResourceLock<C> {
     #[inline]
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("MultiResourceLock")
             .field("conn", &"..")
+            .field("metrics", &"..")
+            .field("expiration_jitter", &"..")
+            .field("fair", &"..")
+            .field("strict_resources", &"..")
+            .field("strict_expiration", &"..")
+            .field("auto_setup", &"..")
+            .field("retry_transient", &"..")
+            .field("release_retries", &"..")
+            .field("command_timeout", &"..")
+            .field("hash_tag", &"..")
+            .field("key_formatter", &"..")
+            .field("id_generator", &"..")
+            .field("clock", &"..")
+            .field("log_target", &"..")
+            .field("local", &"..")
             .finish()
     }
 }
@@ -66,45 +211,438 @@ impl std::fmt::Debug for MultiResourceLock {
 ///
 /// This only needs to be done once per Redis instance, although re-doing it should be fine.
 ///
+/// The library is loaded as `multilock_v{LIBRARY_VERSION}` with each function suffixed by the
+/// version, so binaries built against different versions of this crate can share an instance
+/// during a rolling deploy. Libraries of older versions are left loaded and can be removed with
+/// `FUNCTION DELETE` once no binary uses them. When the same version is already loaded with
+/// different code it is replaced, with a warning when the `tracing` feature is enabled.
+///
+/// Returns whether the library was loaded, replaced or already current, e.g. to count real loads
+/// when every instance of a service calls this on startup.
+///
 /// # Errors
 ///
-/// - When [`Client::get_connection`] errors.
+/// - When [`ConnectionProvider::connection`] errors.
 /// - When the Lua library functions cannot be loaded into Redis.
 #[inline]
-pub async fn setup(client: &Client) -> Result<(), Box<dyn Error>> {
+pub async fn setup<C: ConnectionProvider>(provider: &C) -> Result<SetupOutcome, Box<dyn Error>> {
     // Connect to Redis
-    let mut con = client.get_multiplexed_async_connection().await?;
+    let mut con = provider.connection().await?;
+    Ok(load_library(&mut con).await?)
+}
+
+/// The outcome of [`setup`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SetupOutcome {
+    /// The library was not loaded, so was loaded.
+    Loaded,
+    /// The library was loaded with different code of the same version, so was replaced.
+    Replaced,
+    /// The library was already loaded with the same code, so was left as is.
+    AlreadyCurrent,
+}
 
-    // Define your Lua library
-    let lua_library = include_str!("functions.lua");
+/// Loads the Lua library into Redis on `con`, unless this version is already loaded.
+async fn load_library<Con: ConnectionLike + Send>(con: &mut Con) -> RedisResult<SetupOutcome> {
+    // Check whether this version of the Lua library is already loaded
+    let loaded: Vec<HashMap<String, Value>> = redis::cmd("FUNCTION")
+        .arg("LIST")
+        .arg("LIBRARYNAME")
+        .arg(library_name())
+        .arg("WITHCODE")
+        .query_async(con)
+        .await?;
+    let outcome = load_outcome(&loaded)?;
+    if outcome == SetupOutcome::AlreadyCurrent {
+        return Ok(outcome);
+    }
 
     // Load the Lua library into Redis
     redis::cmd("FUNCTION")
         .arg("LOAD")
         .arg("REPLACE")
-        .arg(lua_library)
-        .exec_async(&mut con)
+        .arg(LIBRARY_CODE)
+        .exec_async(con)
         .await?;
+    Ok(outcome)
+}
+
+/// The version of the Lua library functions called by this crate, see [`setup`].
+///
+/// Bumped whenever the arguments or replies of a function change.
+pub const LIBRARY_VERSION: u32 = 3;
+
+/// The Lua library functions required for locking.
+pub(crate) const LIBRARY_CODE: &str = include_str!("functions.lua");
+
+/// The name the Lua library is loaded as.
+pub(crate) fn library_name() -> String {
+    format!("multilock_v{LIBRARY_VERSION}")
+}
 
+/// Checks the library listed by `FUNCTION LIST`, returning what loading it does.
+pub(crate) fn load_outcome(loaded: &[HashMap<String, Value>]) -> RedisResult<SetupOutcome> {
+    let Some(code) = loaded
+        .first()
+        .and_then(|library| library.get("library_code"))
+    else {
+        return Ok(SetupOutcome::Loaded);
+    };
+    if String::from_redis_value(code)? == LIBRARY_CODE {
+        return Ok(SetupOutcome::AlreadyCurrent);
+    }
+    #[cfg(feature = "tracing")]
+    tracing::warn!(
+        library = library_name(),
+        "replacing Lua library with different code of the same version"
+    );
+    Ok(SetupOutcome::Replaced)
+}
+
+/// Checks the library listed by `FUNCTION LIST` is loaded with the code of this version.
+pub(crate) fn check_loaded(loaded: &[HashMap<String, Value>]) -> RedisResult<()> {
+    let Some(code) = loaded
+        .first()
+        .and_then(|library| library.get("library_code"))
+    else {
+        return Err(RedisError::from((
+            ErrorKind::ClientError,
+            "Lua library not loaded",
+            library_name(),
+        )));
+    };
+    if String::from_redis_value(code)? != LIBRARY_CODE {
+        return Err(RedisError::from((
+            ErrorKind::ClientError,
+            "Lua library loaded with different code",
+            library_name(),
+        )));
+    }
     Ok(())
 }
 
+/// Returns whether `err` is Redis rejecting a call to a function or script that is not loaded,
+/// e.g. on a fresh instance or after `FUNCTION FLUSH`.
+///
+/// Errors raised by the functions themselves are not matched, so are never retried.
+pub(crate) fn is_missing_function(err: &RedisError) -> bool {
+    match err.kind() {
+        ErrorKind::ResponseError => err.detail() == Some("Function not found"),
+        ErrorKind::NoScriptError => true,
+        _ => false,
+    }
+}
+
+/// Returns whether `err` is likely to pass on retrying, i.e. an IO error, such as a dropped or
+/// refused connection, or Redis being temporarily unable to serve the call.
+#[inline]
+#[must_use]
+pub fn is_transient(err: &RedisError) -> bool {
+    err.is_io_error()
+        || matches!(
+            err.kind(),
+            ErrorKind::TryAgain
+                | ErrorKind::ClusterDown
+                | ErrorKind::MasterDown
+                | ErrorKind::BusyLoadingError
+                | ErrorKind::ReadOnly
+        )
+}
+
+/// The poison arguments of the `release_lock` function, whether to poison the released resources
+/// and for how long.
+pub(crate) fn poison_args(poison: Option<Duration>) -> (&'static str, String) {
+    match poison {
+        Some(ttl) => ("1", expiration_millis(ttl)),
+        None => ("0", expiration_millis(INDEFINITE)),
+    }
+}
+
+/// Builds a call to the Lua library function `function` of [`LIBRARY_VERSION`], with the key
+/// prefixes and events channel of `keys` and whether it maintains the index ahead of its
+/// arguments.
+pub(crate) fn fcall(function: &str, keys: &LockKeys) -> Cmd {
+    let mut cmd = redis::cmd("FCALL");
+    cmd.arg(format!("{function}_v{LIBRARY_VERSION}"))
+        .arg(0i32)
+        .arg(keys.lock_prefix())
+        .arg(keys.queue_prefix())
+        .arg(keys.waiter_prefix())
+        .arg(keys.weighted_prefix())
+        .arg(keys.weights_prefix())
+        .arg(keys.index_prefix())
+        .arg(keys.poison_prefix())
+        .arg(keys.events_channel())
+        .arg(if keys.indexed() { "1" } else { "0" });
+    cmd
+}
+
 /// Default expiration duration for the lock.
 pub const DEFAULT_EXPIRATION: Duration = Duration::from_secs(3600);
 /// Default timeout duration for acquiring the lock.
 pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(60);
 /// Default sleep duration between attempts to acquire the lock.
 pub const DEFAULT_SLEEP: Duration = Duration::from_secs(1);
+/// How long to wait before retrying to release a lock after a transient error.
+pub(crate) const RELEASE_RETRY_SLEEP: Duration = Duration::from_millis(50);
+/// Priority of waiters in the fair queue, see [`MultiResourceLock::acquire_with_priority`].
+pub const DEFAULT_PRIORITY: i8 = 0;
+/// Default duration resources stay poisoned after a guard is dropped while panicking, see
+/// [`sync::MultiResourceLock::with_poison_ttl`].
+#[cfg(feature = "sync")]
+#[cfg_attr(docsrs, doc(cfg(feature = "sync")))]
+pub const DEFAULT_POISON_TTL: Duration = Duration::from_secs(86400);
+/// Expiration for a lock that never expires.
+///
+/// An indefinite lock is held until it is released, if the holder dies without releasing it the
+/// lock is held forever and can only be broken with [`MultiResourceLock::force_release_lock`].
+pub const INDEFINITE: Duration = Duration::ZERO;
 
-impl MultiResourceLock {
-    /// Create a new instance of the lock.
+impl<C: ConnectionProvider> MultiResourceLock<C> {
+    /// Create a new instance of the lock running commands on connections from `provider`.
+    ///
+    /// E.g. pass a `redis::aio::ConnectionManager` so commands run on an existing
+    /// auto-reconnecting connection, or a `bb8::Pool` (behind the `bb8` feature) so each command
+    /// checks out a pooled connection.
     ///
     /// # Errors
     ///
     /// When [`Client::get_connection`] errors.
     #[inline]
-    pub fn new(client: Client) -> RedisResult<Self> {
-        Ok(MultiResourceLock { client })
+    pub fn new(provider: C) -> RedisResult<Self> {
+        Ok(MultiResourceLock {
+            provider,
+            metrics: Arc::new(NoopMetrics),
+            expiration_jitter: 0.0,
+            fair: false,
+            strict_resources: false,
+            strict_expiration: false,
+            auto_setup: false,
+            retry_transient: false,
+            release_retries: 0,
+            command_timeout: None,
+            hash_tag: HashTag::Disabled,
+            key_formatter: LockKeys::new(Arc::new(DefaultKeys), true),
+            id_generator: Arc::new(UuidV4),
+            clock: time::default_clock(),
+            #[cfg(feature = "log")]
+            log_target: "redis_lock",
+            #[cfg(feature = "tokio")]
+            local: None,
+        })
+    }
+
+    /// Sets the hooks called to export metrics, by default these are [`NoopMetrics`].
+    #[inline]
+    #[must_use]
+    pub fn with_metrics(mut self, metrics: Arc<dyn Metrics>) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    /// Randomly adds or subtracts up to `jitter` of the expiration each time the lock is
+    /// acquired, e.g. `0.1` for ±10%, by default this is `0.0`.
+    ///
+    /// This prevents locks acquired together with the same expiration from expiring together.
+    ///
+    /// # Panics
+    ///
+    /// When `jitter` is not within `0.0..=1.0`.
+    #[inline]
+    #[must_use]
+    pub fn with_expiration_jitter(mut self, jitter: f64) -> Self {
+        assert!(
+            (0.0f64..=1.0f64).contains(&jitter),
+            "expiration jitter must be within 0.0..=1.0"
+        );
+        self.expiration_jitter = jitter;
+        self
+    }
+
+    /// Sets whether waiters queue to acquire the lock in the order they started waiting, by
+    /// default this is `false` and waiters retry in no particular order.
+    ///
+    /// A fair waiter joins a queue for each resource and may only acquire the lock once it is at
+    /// the head of every queue, locks acquired without waiting also give way to queued waiters.
+    /// This prevents starvation under heavy contention at the cost of an extra key per waiter and
+    /// queue per contended resource, a few extra commands per attempt, and waiting behind a
+    /// waiter that died until it misses a few attempts.
+    #[inline]
+    #[must_use]
+    pub fn with_fair_queue(mut self, fair: bool) -> Self {
+        self.fair = fair;
+        self
+    }
+
+    /// Sets whether acquiring the lock on duplicate resources, e.g. `["a", "a", "b"]`, errors,
+    /// by default this is `false` and duplicates are ignored.
+    ///
+    /// This surfaces bugs in the code deriving the resources to lock.
+    #[inline]
+    #[must_use]
+    pub fn with_strict_resources(mut self, strict: bool) -> Self {
+        self.strict_resources = strict;
+        self
+    }
+
+    /// Sets whether waiting to acquire a lock with an expiration shorter than the sleep between
+    /// attempts or the timeout errors, by default this is `false` and it is logged when the
+    /// `tracing` feature is enabled.
+    ///
+    /// E.g. a `500ms` expiration with a `60s` timeout, where the lock is likely to expire before
+    /// the holder expects or before another waiter next attempts to acquire it. Locks that
+    /// never expire are always allowed.
+    #[inline]
+    #[must_use]
+    pub fn with_strict_expiration(mut self, strict: bool) -> Self {
+        self.strict_expiration = strict;
+        self
+    }
+
+    /// Sets whether a call to a Lua library function that is missing from the Redis instance
+    /// loads the library, as by [`setup`], and is retried once, by default this is `false`
+    /// and the call errors.
+    ///
+    /// This recovers from a fresh Redis instance coming up without the library loaded, or the
+    /// library being removed, e.g. by `FUNCTION FLUSH`, while locks are held.
+    #[inline]
+    #[must_use]
+    pub fn with_auto_setup(mut self, auto_setup: bool) -> Self {
+        self.auto_setup = auto_setup;
+        self
+    }
+
+    /// Sets whether transient errors, see [`is_transient`](crate::is_transient), while waiting
+    /// to acquire the lock are retried like a contended attempt, by default this is `false` and
+    /// they error.
+    ///
+    /// This rides out brief outages, e.g. a failover. Other errors, e.g. a missing function,
+    /// still error immediately. An attempt interrupted by the error may still have acquired the
+    /// lock, which is left to expire.
+    #[inline]
+    #[must_use]
+    pub fn with_retry_transient(mut self, retry_transient: bool) -> Self {
+        self.retry_transient = retry_transient;
+        self
+    }
+
+    /// Sets how many times releasing a lock is retried after a transient error, see
+    /// [`is_transient`](crate::is_transient), by default this is `0` and they error.
+    ///
+    /// A lock that fails to release blocks other holders until it expires, so a brief outage is
+    /// worth riding out. A release interrupted by the error may still have released the lock, in
+    /// which case the retry reports it as [`ReleaseOutcome::NotHeld`].
+    #[inline]
+    #[must_use]
+    pub fn with_release_retries(mut self, release_retries: u32) -> Self {
+        self.release_retries = release_retries;
+        self
+    }
+
+    /// Sets how long each Redis call, including getting a connection, may take before it errors,
+    /// by default calls are unbounded.
+    ///
+    /// This is separate from the timeout to acquire the lock, e.g. to wait 30 seconds for a
+    /// contended lock but fail within 2 seconds when Redis is unreachable. A call that times out
+    /// errors with [`RedisError::is_timeout`], so is retried when
+    /// [`MultiResourceLock::with_retry_transient`] is set.
+    #[inline]
+    #[must_use]
+    pub fn with_command_timeout(mut self, command_timeout: Duration) -> Self {
+        self.command_timeout = Some(command_timeout);
+        self
+    }
+
+    /// Sets how resources map to the names they are locked under, by default this is
+    /// [`HashTag::Disabled`].
+    ///
+    /// [`HashTag::Group`] keeps the lock keys of every resource in one hash slot, while
+    /// [`HashTag::Existing`] errors on resources of a lock whose keys hash to different slots.
+    /// Locking is still not supported on Redis Cluster, see [`HashTag`].
+    #[inline]
+    #[must_use]
+    pub fn with_hash_tag(mut self, hash_tag: HashTag) -> Self {
+        self.hash_tag = hash_tag;
+        self
+    }
+
+    /// Sets the names of the Redis keys locks are stored under, by default these are
+    /// [`DefaultKeys`].
+    ///
+    /// Every user of a Redis instance must use the same names.
+    #[inline]
+    #[must_use]
+    pub fn with_key_formatter(mut self, key_formatter: Arc<dyn KeyFormatter>) -> Self {
+        self.key_formatter = LockKeys::new(key_formatter, self.key_formatter.indexed());
+        self
+    }
+
+    /// Sets whether the `index:locks` and `index:resources` sets of held locks and locked
+    /// resources, and the `index:attempts` sets of contended attempts, are maintained, by default
+    /// this is `true`.
+    ///
+    /// Not maintaining them saves their writes on every acquisition and release, but
+    /// [`MultiResourceLock::stats`] then counts nothing, [`MultiResourceLock::waiter_count`]
+    /// counts only queued waiters, and releasing an expired lock reports
+    /// [`ReleaseOutcome::NotHeld`] rather than [`ReleaseOutcome::AlreadyExpired`]. Users of a
+    /// Redis instance that do maintain them may see locks acquired by users that do not as
+    /// expired.
+    #[inline]
+    #[must_use]
+    pub fn with_index(mut self, indexed: bool) -> Self {
+        self.key_formatter = LockKeys::new(Arc::clone(self.key_formatter.formatter()), indexed);
+        self
+    }
+
+    /// Sets how the identifiers of locks are generated, by default this is [`UuidV4`].
+    ///
+    /// E.g. to generate time sortable identifiers, or to embed the host in identifiers to find
+    /// the holder of a lock.
+    #[inline]
+    #[must_use]
+    pub fn with_id_generator(mut self, id_generator: Arc<dyn IdGenerator>) -> Self {
+        self.id_generator = id_generator;
+        self
+    }
+
+    /// Sets the clock used to time attempts to acquire the lock.
+    ///
+    /// By default this is [`time::TokioClock`] when the `tokio` feature is enabled and
+    /// [`time::ThreadClock`] otherwise.
+    #[inline]
+    #[must_use]
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Sets the target lock lifecycle events are logged to, by default this is `redis_lock`.
+    ///
+    /// Attempts to acquire the lock, timeouts and releases are logged at debug level with the lock
+    /// identifier and the number of resources.
+    #[cfg(feature = "log")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "log")))]
+    #[inline]
+    #[must_use]
+    pub fn with_log_target(mut self, log_target: &'static str) -> Self {
+        self.log_target = log_target;
+        self
+    }
+
+    /// Sets whether contenders within this process queue locally, by default this is `false`.
+    ///
+    /// Only one of the contenders for a resource waiting with this lock, or its clones, polls
+    /// Redis at a time, the others wait for it to acquire the lock or give up. A contender
+    /// waiting between attempts retries as soon as a lock is released by this lock or its clones.
+    /// This reduces the load on Redis when many tasks in one process contend, while Redis still
+    /// decides who holds the lock across processes.
+    #[cfg(feature = "tokio")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "tokio")))]
+    #[inline]
+    #[must_use]
+    pub fn with_local_queue(mut self, local_queue: bool) -> Self {
+        self.local = local_queue.then(|| Arc::new(local::LocalQueue::default()));
+        self
     }
 
     /// Calls [`MultiResourceLock::acquire`] with [`DEFAULT_EXPIRATION`], [`DEFAULT_TIMEOUT`] and [`DEFAULT_SLEEP`].
@@ -113,7 +651,7 @@ impl MultiResourceLock {
     ///
     /// When [`MultiResourceLock::acquire`] errors.
     #[inline]
-    pub async fn acquire_default(&mut self, resources: &[String]) -> RedisResult<Option<String>> {
+    pub async fn acquire_default(&self, resources: &[String]) -> RedisResult<Option<String>> {
         self.acquire(
             resources,
             DEFAULT_EXPIRATION,
@@ -129,132 +667,2330 @@ impl MultiResourceLock {
     ///
     /// Returns `None` when it times out.
     ///
+    /// The lock expires after `expiration`, or never when it is [`INDEFINITE`].
+    ///
     /// # Errors
     ///
-    /// When [`MultiResourceLock::try_acquire`] errors.
+    /// When [`MultiResourceLock::acquire_with_metadata`] errors.
     #[inline]
+    #[expect(
+        clippy::same_name_method,
+        reason = "The `Lock` implementation delegates to this."
+    )]
     pub async fn acquire(
-        &mut self,
+        &self,
         resources: &[String],
         expiration: Duration,
         timeout: Duration,
         sleep: Duration,
     ) -> RedisResult<Option<String>> {
-        let now = std::time::Instant::now();
-        loop {
-            if now.elapsed() > timeout {
-                return Ok(None);
-            }
-            match self.try_acquire(resources, expiration).await? {
-                Some(res) => break Ok(Some(res)),
-                None => tokio::time::sleep(sleep).await,
-            }
-        }
+        self.acquire_with_metadata(resources, expiration, timeout, sleep, None)
+            .await
     }
 
-    /// Calls [`MultiResourceLock::try_acquire`] with [`DEFAULT_EXPIRATION`].
+    /// Calls [`MultiResourceLock::acquire`], returning the time spent waiting for the lock
+    /// alongside its identifier.
+    ///
+    /// The wait is measured on the lock's clock, see [`MultiResourceLock::with_clock`].
     ///
     /// # Errors
     ///
-    /// When [`MultiResourceLock::try_acquire`] errors.
+    /// When [`MultiResourceLock::acquire`] errors.
     #[inline]
-    pub async fn try_acquire_default(
-        &mut self,
+    pub async fn acquire_timed(
+        &self,
         resources: &[String],
-    ) -> RedisResult<Option<String>> {
-        self.try_acquire(resources, DEFAULT_EXPIRATION).await
+        expiration: Duration,
+        timeout: Duration,
+        sleep: Duration,
+    ) -> RedisResult<Option<(String, Duration)>> {
+        let start = self.clock.now();
+        Ok(self
+            .acquire(resources, expiration, timeout, sleep)
+            .await?
+            .map(|lock_id| (lock_id, self.clock.now().saturating_duration_since(start))))
     }
 
-    /// Attempts to acquire the lock returning immediately if it cannot be immediately acquired.
+    /// Attempts to acquire the lock blocking until the lock can be acquired, storing `metadata`
+    /// alongside it.
+    ///
+    /// Blocks up to `timeout` duration making attempts every `sleep` duration.
+    ///
+    /// Returns `None` when it times out.
     ///
     /// # Errors
     ///
-    /// - When the `acquire_lock` function is missing from the Redis instance.
+    /// When [`MultiResourceLock::acquire_outcome`] errors.
     #[inline]
-    pub async fn try_acquire(
-        &mut self,
+    pub async fn acquire_with_metadata(
+        &self,
         resources: &[String],
         expiration: Duration,
+        timeout: Duration,
+        sleep: Duration,
+        metadata: Option<&str>,
     ) -> RedisResult<Option<String>> {
-        let mut connection = self.client.get_multiplexed_async_connection().await?;
-        let lock_id = Uuid::new_v4().to_string();
-        let mut args = vec![lock_id.clone(), expiration.as_millis().to_string()];
-        args.extend(resources.iter().cloned());
-
-        let result: Option<String> = redis::cmd("FCALL")
-            .arg("acquire_lock")
-            .arg(0i32)
-            .arg(&args)
-            .query_async(&mut connection)
-            .await?;
+        Ok(self
+            .acquire_outcome(resources, expiration, timeout, sleep, metadata)
+            .await?
+            .acquired())
+    }
 
-        Ok(result)
+    /// Attempts to acquire the lock blocking until the lock can be acquired, storing `metadata`
+    /// alongside it.
+    ///
+    /// Blocks up to `timeout` duration making attempts every `sleep` duration.
+    ///
+    /// Returns [`AcquireResult::TimedOut`] when it times out. Each attempt is bounded by the time
+    /// remaining, this is measured in real time rather than by the configured clock.
+    ///
+    /// # Errors
+    ///
+    /// - When [`MultiResourceLock::try_acquire_outcome`] errors.
+    /// - When an attempt does not complete within `timeout`, [`RedisError::is_timeout`] is `true`
+    ///   for this error. The attempt may still have acquired the lock, which is left to expire.
+    #[inline]
+    pub async fn acquire_outcome(
+        &self,
+        resources: &[String],
+        expiration: Duration,
+        timeout: Duration,
+        sleep: Duration,
+        metadata: Option<&str>,
+    ) -> RedisResult<AcquireResult<String>> {
+        let priority = self.fair.then_some(DEFAULT_PRIORITY);
+        let mut retry = Constant::new(sleep, timeout);
+        self.acquire_queued(
+            resources, expiration, &mut retry, metadata, priority, None, None,
+        )
+        .await
     }
 
-    /// Releases a held lock.
+    /// Attempts to acquire the lock blocking until the lock can be acquired, returning within
+    /// `total` however slow Redis is.
+    ///
+    /// Makes attempts every `sleep` duration like [`MultiResourceLock::acquire_outcome`], but
+    /// returns [`AcquireResult::TimedOut`] rather than an error when `total` elapses during a
+    /// Redis round trip. `total` is measured in real time as well as by the configured clock.
+    ///
+    /// An attempt cut short may still have acquired the lock, so its lock identifier is released
+    /// and, when [`MultiResourceLock::with_fair_queue`] is set, its waiter leaves the queue. Within
+    /// a Tokio runtime this is spawned as a task rather than awaited, otherwise it takes a further
+    /// round trip after `total`, bound by [`MultiResourceLock::with_command_timeout`]. Either way
+    /// it is best effort, logging failures rather than returning them.
     ///
     /// # Errors
     ///
-    /// - When the `release_lock` function is missing from the Redis instance.
-    /// - When `lock_id` does not refer to a held lock.
-    #[inline]
-    pub async fn release(&mut self, lock_id: &str) -> RedisResult<usize> {
-        let mut connection = self.client.get_multiplexed_async_connection().await?;
-        let result: usize = redis::cmd("FCALL")
-            .arg("release_lock")
-            .arg(0i32)
-            .arg(lock_id)
-            .query_async(&mut connection)
-            .await?;
+    /// When [`MultiResourceLock::try_acquire_outcome`] errors.
+    #[inline]
+    pub async fn acquire_within(
+        &self,
+        resources: &[String],
+        expiration: Duration,
+        total: Duration,
+        sleep: Duration,
+    ) -> RedisResult<AcquireResult<String>>
+    where
+        C: Clone + Send + Sync + 'static,
+    {
+        let start = Instant::now();
+        let priority = self.fair.then_some(DEFAULT_PRIORITY);
+        let mut retry = Constant::new(sleep, total);
+        let mut abandoned = None;
+        let acquiring = self.acquire_queued(
+            resources,
+            expiration,
+            &mut retry,
+            None,
+            priority,
+            None,
+            Some(&mut abandoned),
+        );
+        let result = Box::pin(acquiring).await;
+        if let Some(left) = abandoned {
+            self.detach(left).await;
+        }
+        match result {
+            // An attempt bounded by the time remaining was cut short, having already reported the
+            // timeout.
+            Err(err) if err.is_timeout() && start.elapsed() >= total => Ok(AcquireResult::TimedOut),
+            other => other,
+        }
+    }
 
-        Ok(result)
+    /// Cleans up after `abandoned` in a task spawned on the current Tokio runtime, or else before
+    /// returning.
+    async fn detach(&self, abandoned: Abandoned)
+    where
+        C: Clone + Send + Sync + 'static,
+    {
+        #[cfg(feature = "tokio")]
+        if let Ok(runtime) = tokio::runtime::Handle::try_current() {
+            let lock = self.clone();
+            runtime.spawn(async move { lock.abandon(abandoned).await });
+            return;
+        }
+        self.abandon(abandoned).await;
     }
 
-    /// Since we cannot safely drop a guard in an async context, we need to provide a way to release the lock in case of an error.
+    /// Attempts to acquire the lock blocking until the lock can be acquired, calling `on_wait`
+    /// with the number of attempts made and the time elapsed after each failed attempt.
     ///
-    /// This is the suggested approach, it is less ergonomic but it is safe.
+    /// E.g. to log progress during long waits.
+    ///
+    /// Blocks up to `timeout` duration making attempts every `sleep` duration.
+    ///
+    /// Returns `None` when it times out.
+    ///
+    /// # Errors
+    ///
+    /// When [`MultiResourceLock::acquire_with_metadata`] would error.
     #[inline]
-    pub async fn map<F>(
-        &mut self,
+    pub async fn acquire_with_progress<F>(
+        &self,
         resources: &[String],
         expiration: Duration,
         timeout: Duration,
         sleep: Duration,
-        f: F,
-    ) -> Result<F::Output, MapError>
+        mut on_wait: F,
+    ) -> RedisResult<Option<String>>
     where
-        F: Future + Send + 'static,
-        F::Output: Send + 'static,
+        F: FnMut(u32, Duration) + Send,
     {
-        let lock_id = self
-            .acquire(resources, expiration, timeout, sleep)
-            .await
-            .map_err(MapError::Acquire)?
-            .ok_or(MapError::Timeout)?;
-        let result = f.await;
-        self.release(&lock_id).await.map_err(MapError::Release)?;
-        Ok(result)
+        let priority = self.fair.then_some(DEFAULT_PRIORITY);
+        let mut retry = Constant::new(sleep, timeout);
+        Ok(self
+            .acquire_queued(
+                resources,
+                expiration,
+                &mut retry,
+                None,
+                priority,
+                Some(&mut on_wait),
+                None,
+            )
+            .await?
+            .acquired())
     }
 
-    /// Calls [`MultiResourceLock::map`] with [`DEFAULT_EXPIRATION`], [`DEFAULT_TIMEOUT`] and [`DEFAULT_SLEEP`].
+    /// Attempts to acquire the lock blocking until the lock can be acquired, waiting between
+    /// attempts as decided by `retry`.
+    ///
+    /// Returns `None` when `retry` gives up.
+    ///
+    /// # Errors
+    ///
+    /// When [`MultiResourceLock::acquire_with_metadata`] would error.
     #[inline]
-    pub async fn map_default<F>(
-        &mut self,
+    pub async fn acquire_with_retry(
+        &self,
         resources: &[String],
-        f: F,
-    ) -> Result<F::Output, MapError>
-    where
-        F: Future + Send + 'static,
-        F::Output: Send + 'static,
-    {
-        self.map(
-            resources,
-            DEFAULT_EXPIRATION,
-            DEFAULT_TIMEOUT,
-            DEFAULT_SLEEP,
-            f,
+        expiration: Duration,
+        retry: &mut dyn RetryStrategy,
+    ) -> RedisResult<Option<String>> {
+        let priority = self.fair.then_some(DEFAULT_PRIORITY);
+        Ok(self
+            .acquire_queued(resources, expiration, retry, None, priority, None, None)
+            .await?
+            .acquired())
+    }
+
+    /// Attempts to acquire the lock blocking until the lock can be acquired, queueing with
+    /// `priority` regardless of [`MultiResourceLock::with_fair_queue`].
+    ///
+    /// Among waiters on a resource those with a higher priority acquire the lock first, then
+    /// those that started waiting first. Waiters with the same priority that started waiting in
+    /// the same millisecond acquire it in an arbitrary order. Fair waiters have
+    /// [`DEFAULT_PRIORITY`].
+    ///
+    /// Blocks up to `timeout` duration making attempts every `sleep` duration.
+    ///
+    /// Returns `None` when it times out.
+    ///
+    /// # Errors
+    ///
+    /// When [`MultiResourceLock::acquire_with_metadata`] would error.
+    #[inline]
+    pub async fn acquire_with_priority(
+        &self,
+        resources: &[String],
+        expiration: Duration,
+        timeout: Duration,
+        sleep: Duration,
+        priority: i8,
+    ) -> RedisResult<Option<String>> {
+        let mut retry = Constant::new(sleep, timeout);
+        Ok(self
+            .acquire_queued(
+                resources,
+                expiration,
+                &mut retry,
+                None,
+                Some(priority),
+                None,
+                None,
+            )
+            .await?
+            .acquired())
+    }
+
+    /// Attempts to acquire the lock blocking until the lock can be acquired, waiting between
+    /// attempts as decided by `retry`, queueing with `priority` and calling `on_wait` after each
+    /// failed attempt when they are `Some`.
+    ///
+    /// What an attempt cut short or failed leaves behind is stored in `deferred` when it is
+    /// `Some`, for the caller to clean up, rather than cleaned up before returning.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            name = "acquire_with_metadata",
+            level = "debug",
+            skip(self, retry, on_wait, deferred),
+            err
         )
-        .await
+    )]
+    #[expect(
+        clippy::too_many_arguments,
+        reason = "Each public way to acquire the lock passes a different subset."
+    )]
+    async fn acquire_queued(
+        &self,
+        resources: &[String],
+        expiration: Duration,
+        retry: &mut dyn RetryStrategy,
+        metadata: Option<&str>,
+        priority: Option<i8>,
+        mut on_wait: Option<&mut (dyn FnMut(u32, Duration) + Send)>,
+        deferred: Option<&mut Option<Abandoned>>,
+    ) -> RedisResult<AcquireResult<String>> {
+        let now = self.clock.now();
+        let timeout = retry.timeout();
+        // One timer bounds every step, rather than one per attempt.
+        let mut deadline = time::Deadline::after(timeout);
+        #[cfg(feature = "tokio")]
+        let _permit = match &self.local {
+            Some(local) => match deadline.run(local.enter(resources)).await {
+                Some(permit) => Some(permit),
+                None => return self.give_up(resources, None, deferred).await,
+            },
+            None => None,
+        };
+        let mut queue = priority.map(|level| Waiter::new(self.id_generator.generate(), level));
+        let mut attempt = 0u32;
+        loop {
+            let elapsed = self.clock.now().saturating_duration_since(now);
+            if timeout.is_some_and(|limit| elapsed > limit) {
+                #[cfg(feature = "tracing")]
+                tracing::debug!(?elapsed, outcome = "timeout");
+                return self.give_up(resources, queue, deferred).await;
+            }
+            attempt = attempt.saturating_add(1);
+            let delay = retry.next_delay(attempt, elapsed);
+            if attempt == 1 {
+                if let Some(reason) = short_expiration(expiration, timeout, delay) {
+                    if self.strict_expiration {
+                        return Err(RedisError::from((ErrorKind::ClientError, reason)));
+                    }
+                    #[cfg(feature = "tracing")]
+                    tracing::warn!(?expiration, ?timeout, sleep = ?delay, reason);
+                }
+            }
+            if let Some(waiter) = &mut queue {
+                waiter.wait(delay.unwrap_or_default());
+            }
+            // Generated here so an attempt cut short can still be released.
+            let lock_id = queue.as_ref().map_or_else(
+                || self.id_generator.generate(),
+                |waiter| waiter.lock_id.clone(),
+            );
+            let acquiring = self.attempt_acquire(
+                lock_id.clone(),
+                resources,
+                expiration,
+                metadata,
+                now,
+                queue.as_ref(),
+                None,
+            );
+            let Some(result) = deadline.run(acquiring).await else {
+                #[cfg(feature = "tracing")]
+                tracing::debug!(?elapsed, outcome = "call timeout");
+                self.metrics.on_timeout();
+                // The attempt may have acquired the lock or queued the waiter before it was cut
+                // short.
+                let abandoned = Abandoned::new(resources, Some(lock_id), queue);
+                self.clean_up(abandoned, deferred).await;
+                return Err(call_timeout());
+            };
+            match result {
+                Ok(AcquireResult::Acquired(res)) => {
+                    #[cfg(feature = "tracing")]
+                    tracing::debug!(lock_id = res, ?elapsed, outcome = "acquired");
+                    break Ok(AcquireResult::Acquired(res));
+                }
+                Err(err) if !(self.retry_transient && is_transient(&err)) => {
+                    let abandoned = Abandoned::new(resources, None, queue);
+                    self.clean_up(abandoned, deferred).await;
+                    return Err(err);
+                }
+                #[cfg_attr(
+                    not(feature = "tracing"),
+                    expect(unused_variables, reason = "Only traced.")
+                )]
+                Err(err) => {
+                    #[cfg(feature = "tracing")]
+                    tracing::debug!(?elapsed, %err, outcome = "transient error");
+                }
+                Ok(_) => {}
+            }
+            let Some(wait) = delay else {
+                #[cfg(feature = "tracing")]
+                tracing::debug!(?elapsed, outcome = "gave up");
+                return self.give_up(resources, queue, deferred).await;
+            };
+            #[cfg(feature = "tracing")]
+            tracing::trace!(?elapsed, outcome = "contended");
+            if let Some(callback) = on_wait.as_mut() {
+                callback(attempt, self.clock.now().saturating_duration_since(now));
+            }
+            if deadline.run(self.pause(wait)).await.is_none() {
+                #[cfg(feature = "tracing")]
+                tracing::debug!(?elapsed, outcome = "timeout");
+                return self.give_up(resources, queue, deferred).await;
+            }
+        }
+    }
+
+    /// Sleeps for `wait` between attempts to acquire the lock, waking early on a local release
+    /// when [`MultiResourceLock::with_local_queue`] is set.
+    async fn pause(&self, wait: Duration) {
+        #[cfg(feature = "tokio")]
+        if let Some(local) = &self.local {
+            let released = std::pin::pin!(local.released());
+            futures_util::future::select(released, self.clock.sleep(wait)).await;
+            return;
+        }
+        self.clock.sleep(wait).await;
+    }
+
+    /// Reports giving up on acquiring the lock, leaving the queue when `queue` is `Some`.
+    async fn give_up(
+        &self,
+        resources: &[String],
+        queue: Option<Waiter>,
+        deferred: Option<&mut Option<Abandoned>>,
+    ) -> RedisResult<AcquireResult<String>> {
+        self.metrics.on_timeout();
+        #[cfg(feature = "log")]
+        log::debug!(
+            target: self.log_target,
+            "timed out acquiring lock on {} resources",
+            resources.len()
+        );
+        match (queue, deferred) {
+            (Some(waiter), Some(slot)) => {
+                *slot = Some(Abandoned::new(resources, None, Some(waiter)));
+            }
+            (Some(waiter), None) => self.dequeue(&waiter.lock_id, resources).await?,
+            (None, _) => {}
+        }
+        Ok(AcquireResult::TimedOut)
+    }
+
+    /// Stores `abandoned` in `deferred` when it is `Some`, or else leaves the queue before
+    /// returning.
+    ///
+    /// The lock is then left to expire rather than released, which would take a further round
+    /// trip after every attempt cut short.
+    async fn clean_up(&self, abandoned: Abandoned, deferred: Option<&mut Option<Abandoned>>) {
+        if abandoned.lock_id.is_none() && abandoned.queue.is_none() {
+            return;
+        }
+        match deferred {
+            Some(slot) => *slot = Some(abandoned),
+            None => {
+                self.leave_queue(&abandoned.resources, abandoned.queue.as_ref())
+                    .await;
+            }
+        }
+    }
+
+    /// Releases the lock an attempt cut short may have acquired and leaves the queue it may have
+    /// joined, logging rather than returning failures as the attempt's outcome is returned.
+    ///
+    /// The release is not reported to the metrics hooks, which never saw the lock acquired.
+    async fn abandon(&self, abandoned: Abandoned) {
+        if let Some(lock_id) = &abandoned.lock_id {
+            let mut cmd = fcall("release_lock", &self.key_formatter);
+            cmd.arg(lock_id).arg(poison_args(None));
+            match self.query::<(usize, bool)>(&cmd).await {
+                #[cfg(feature = "tokio")]
+                Ok(_) => {
+                    if let Some(local) = &self.local {
+                        local.notify_released();
+                    }
+                }
+                #[cfg(not(feature = "tokio"))]
+                Ok(_) => {}
+                Err(_err) => {
+                    #[cfg(feature = "tracing")]
+                    tracing::warn!(
+                        lock_id,
+                        %_err,
+                        "failed to release an abandoned attempt, left until it expires"
+                    );
+                }
+            }
+        }
+        self.leave_queue(&abandoned.resources, abandoned.queue.as_ref())
+            .await;
+    }
+
+    /// Leaves the queue when `queue` is `Some` after an attempt to acquire the lock errored,
+    /// logging rather than returning a failure to leave as the attempt's error is returned.
+    async fn leave_queue(&self, resources: &[String], queue: Option<&Waiter>) {
+        let Some(waiter) = queue else {
+            return;
+        };
+        if let Err(_err) = self.dequeue(&waiter.lock_id, resources).await {
+            #[cfg(feature = "tracing")]
+            tracing::warn!(%_err, "failed to leave the queue, left until the waiter expires");
+        }
+    }
+
+    /// Calls [`MultiResourceLock::try_acquire`] with [`DEFAULT_EXPIRATION`].
+    ///
+    /// # Errors
+    ///
+    /// When [`MultiResourceLock::try_acquire`] errors.
+    #[inline]
+    pub async fn try_acquire_default(&self, resources: &[String]) -> RedisResult<Option<String>> {
+        self.try_acquire(resources, DEFAULT_EXPIRATION).await
+    }
+
+    /// Attempts to acquire the lock returning immediately if it cannot be immediately acquired.
+    ///
+    /// The lock expires after `expiration`, or never when it is [`INDEFINITE`].
+    ///
+    /// # Errors
+    ///
+    /// When [`MultiResourceLock::try_acquire_with_metadata`] errors.
+    #[inline]
+    #[expect(
+        clippy::same_name_method,
+        reason = "The `Lock` implementation delegates to this."
+    )]
+    pub async fn try_acquire(
+        &self,
+        resources: &[String],
+        expiration: Duration,
+    ) -> RedisResult<Option<String>> {
+        self.try_acquire_with_metadata(resources, expiration, None)
+            .await
+    }
+
+    /// Attempts to acquire the lock on resources across namespaces returning immediately if it
+    /// cannot be immediately acquired.
+    ///
+    /// The resources are locked under their [`Resource::key`].
+    ///
+    /// # Errors
+    ///
+    /// When [`MultiResourceLock::try_acquire`] errors.
+    #[inline]
+    pub async fn try_acquire_resources(
+        &self,
+        resources: &[Resource],
+        expiration: Duration,
+    ) -> RedisResult<Option<String>> {
+        self.try_acquire(&Resource::keys(resources), expiration)
+            .await
+    }
+
+    /// Attempts to acquire the lock on `keys`, e.g. domain identifiers, returning immediately if
+    /// it cannot be immediately acquired.
+    ///
+    /// Each key is locked under its [`ResourceKey::resource_key`].
+    ///
+    /// # Errors
+    ///
+    /// When [`MultiResourceLock::try_acquire`] errors.
+    #[inline]
+    pub async fn try_acquire_keys<K: ResourceKey>(
+        &self,
+        keys: &[K],
+        expiration: Duration,
+    ) -> RedisResult<Option<String>> {
+        let resources = keys
+            .iter()
+            .map(ResourceKey::resource_key)
+            .collect::<Vec<_>>();
+        self.try_acquire(&resources, expiration).await
+    }
+
+    /// Attempts to acquire the lock returning immediately if it cannot be immediately acquired,
+    /// storing `metadata` alongside it.
+    ///
+    /// The metadata is written in the same transaction as the lock so it never outlives it, it
+    /// can be read back with [`MultiResourceLock::lock_metadata`] or
+    /// [`MultiResourceLock::resource_metadata`].
+    ///
+    /// # Errors
+    ///
+    /// When [`MultiResourceLock::try_acquire_outcome`] errors.
+    #[inline]
+    pub async fn try_acquire_with_metadata(
+        &self,
+        resources: &[String],
+        expiration: Duration,
+        metadata: Option<&str>,
+    ) -> RedisResult<Option<String>> {
+        Ok(self
+            .try_acquire_outcome(resources, expiration, metadata)
+            .await?
+            .acquired())
+    }
+
+    /// Attempts to acquire the lock returning immediately if it cannot be immediately acquired,
+    /// storing `metadata` alongside it.
+    ///
+    /// Returns [`AcquireResult::Contended`] with the resources held by other locks, or waited on
+    /// by queued waiters, when it cannot be acquired.
+    ///
+    /// # Errors
+    ///
+    /// - When the `acquire_lock` function is missing from the Redis instance.
+    /// - When `resources` contain duplicates and [`MultiResourceLock::with_strict_resources`] is
+    ///   set.
+    #[inline]
+    pub async fn try_acquire_outcome(
+        &self,
+        resources: &[String],
+        expiration: Duration,
+        metadata: Option<&str>,
+    ) -> RedisResult<AcquireResult<String>> {
+        self.attempt_acquire(
+            self.id_generator.generate(),
+            resources,
+            expiration,
+            metadata,
+            self.clock.now(),
+            None,
+            None,
+        )
+        .await
+    }
+
+    /// Attempts to acquire the lock returning immediately if it cannot be immediately acquired,
+    /// only acquiring it when `check` holds.
+    ///
+    /// `check` is evaluated in the same call as the acquisition, so nothing can change the key it
+    /// reads in between. Returns [`AcquireResult::PreconditionFailed`], locking nothing, when it
+    /// fails.
+    ///
+    /// # Errors
+    ///
+    /// When [`MultiResourceLock::try_acquire_outcome`] errors.
+    #[inline]
+    pub async fn try_acquire_if(
+        &self,
+        resources: &[String],
+        expiration: Duration,
+        check: &LuaCondition,
+    ) -> RedisResult<AcquireResult<String>> {
+        self.attempt_acquire(
+            self.id_generator.generate(),
+            resources,
+            expiration,
+            None,
+            self.clock.now(),
+            None,
+            Some(check),
+        )
+        .await
+    }
+
+    /// Attempts to acquire the lock returning immediately if it cannot be immediately acquired,
+    /// returning a [`LockToken`] that can be passed to another process.
+    ///
+    /// # Errors
+    ///
+    /// When [`MultiResourceLock::try_acquire`] errors.
+    #[inline]
+    pub async fn try_acquire_token(
+        &self,
+        resources: &[String],
+        expiration: Duration,
+    ) -> RedisResult<Option<LockToken>> {
+        Ok(self
+            .try_acquire(resources, expiration)
+            .await?
+            .map(|lock_id| LockToken::new(lock_id, resources.to_vec(), expiration)))
+    }
+
+    /// Attempts to acquire the lock returning immediately if it cannot be immediately acquired,
+    /// then runs `commands` under it.
+    ///
+    /// The attempt and the commands are pipelined so share a single round trip, the commands
+    /// only run when the lock is acquired, as with [`MultiResourceLock::guarded_exec`]. E.g.
+    /// `redis::cmd("GET").arg("account1")` to read a resource as it is locked.
+    ///
+    /// Returns the lock identifier and the reply of each command, or `None` when the lock cannot
+    /// be acquired.
+    ///
+    /// # Errors
+    ///
+    /// - When the `acquire_lock` or `guarded_exec` functions are missing from the Redis instance.
+    /// - When `resources` contain duplicates and [`MultiResourceLock::with_strict_resources`] is
+    ///   set.
+    /// - When a command errors, the lock is released and commands before it will have been run.
+    ///   Failing to release the lock is reported to [`Metrics::on_release_error`] rather than
+    ///   replacing the error.
+    #[inline]
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip(self, commands), fields(lock_id), err)
+    )]
+    pub async fn try_acquire_and_exec(
+        &self,
+        resources: &[String],
+        expiration: Duration,
+        commands: &[Cmd],
+    ) -> RedisResult<Option<(String, Vec<Value>)>> {
+        let start = self.clock.now();
+        self.metrics.on_acquire_attempt();
+        let mut connection = self.bounded(Box::pin(self.provider.connection())).await?;
+        let lock_id = self.id_generator.generate();
+        #[cfg(feature = "tracing")]
+        tracing::Span::current().record("lock_id", &lock_id);
+        let unique = self.hash_tag.keys(
+            unique_resources(resources, self.strict_resources)?,
+            &self.key_formatter,
+        )?;
+        let jittered = jitter(expiration, self.expiration_jitter);
+        let mut pipe = redis::pipe();
+        pipe.add_command(acquire_cmd(
+            &self.key_formatter,
+            &lock_id,
+            &unique,
+            jittered,
+            None,
+            None,
+            None,
+        ))
+        .add_command(guarded_exec_cmd(
+            &self.key_formatter,
+            &lock_id,
+            &unique,
+            commands,
+        ));
+        let reply: RedisResult<(Value, Option<Vec<Value>>)> =
+            match self.bounded(pipe.query_async(&mut connection)).await {
+                Err(err) if self.auto_setup && is_missing_function(&err) => {
+                    self.bounded(load_library(&mut connection)).await?;
+                    self.bounded(pipe.query_async(&mut connection)).await
+                }
+                reply => reply,
+            };
+        let (acquired, replies) = match reply {
+            Ok((acquire_reply, replies)) => (
+                AcquireResult::from_reply(acquire_reply, &self.hash_tag)?.acquired(),
+                replies,
+            ),
+            Err(err) => {
+                drop(connection);
+                if let Err(error) = self.release(&lock_id).await {
+                    report_release_error(&*self.metrics, &lock_id, &error);
+                }
+                return Err(err);
+            }
+        };
+
+        #[cfg(feature = "tracing")]
+        tracing::debug!(
+            outcome = if acquired.is_some() {
+                "acquired"
+            } else {
+                "contended"
+            }
+        );
+        if acquired.is_some() {
+            self.metrics.on_acquired_resources(
+                resources,
+                self.clock.now().saturating_duration_since(start),
+            );
+        }
+        Ok(acquired.zip(replies))
+    }
+
+    /// Attempts to acquire the lock up to `attempts` times, at least once, waiting `sleep`
+    /// between attempts.
+    ///
+    /// Unlike [`MultiResourceLock::acquire`] this is bounded by the number of attempts rather
+    /// than by time.
+    ///
+    /// Returns `None` when every attempt fails.
+    ///
+    /// # Errors
+    ///
+    /// When [`MultiResourceLock::try_acquire`] errors.
+    #[inline]
+    pub async fn try_acquire_n(
+        &self,
+        resources: &[String],
+        expiration: Duration,
+        attempts: u32,
+        sleep: Duration,
+    ) -> RedisResult<Option<String>> {
+        let priority = self.fair.then_some(DEFAULT_PRIORITY);
+        let mut retry = Attempts::new(attempts, sleep);
+        Ok(self
+            .acquire_queued(
+                resources, expiration, &mut retry, None, priority, None, None,
+            )
+            .await?
+            .acquired())
+    }
+
+    /// Makes a single attempt to acquire the lock, reporting the wait since `start` to the
+    /// metrics hooks when acquired.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            name = "try_acquire",
+            level = "debug",
+            skip(self, start),
+            fields(lock_id),
+            err
+        )
+    )]
+    #[expect(
+        clippy::too_many_arguments,
+        reason = "The lock identifier is generated by the caller to release it if cut short."
+    )]
+    async fn attempt_acquire(
+        &self,
+        lock_id: String,
+        resources: &[String],
+        expiration: Duration,
+        metadata: Option<&str>,
+        start: Instant,
+        queue: Option<&Waiter>,
+        condition: Option<&LuaCondition>,
+    ) -> RedisResult<AcquireResult<String>> {
+        self.metrics.on_acquire_attempt();
+        #[cfg(feature = "tracing")]
+        tracing::Span::current().record("lock_id", &lock_id);
+        let unique = self.hash_tag.keys(
+            unique_resources(resources, self.strict_resources)?,
+            &self.key_formatter,
+        )?;
+        #[cfg(feature = "log")]
+        log::debug!(
+            target: self.log_target,
+            "attempting to acquire lock {lock_id} on {} resources",
+            unique.len()
+        );
+        let jittered = jitter(expiration, self.expiration_jitter);
+        let result = AcquireResult::from_reply(
+            self.query(&acquire_cmd(
+                &self.key_formatter,
+                &lock_id,
+                &unique,
+                jittered,
+                metadata,
+                queue,
+                condition,
+            ))
+            .await?,
+            &self.hash_tag,
+        )?;
+
+        #[cfg(feature = "log")]
+        log_attempt(self.log_target, &lock_id, unique.len(), &result);
+        #[cfg(feature = "tracing")]
+        tracing::debug!(
+            outcome = match result {
+                AcquireResult::Acquired(_) => "acquired",
+                AcquireResult::PreconditionFailed => "precondition failed",
+                AcquireResult::Contended { .. } | AcquireResult::TimedOut => "contended",
+            }
+        );
+        if result.is_acquired() {
+            self.metrics.on_acquired_resources(
+                resources,
+                self.clock.now().saturating_duration_since(start),
+            );
+        }
+        Ok(result)
+    }
+
+    /// Removes the waiter `lock_id` from the queue of each of `resources`.
+    async fn dequeue(&self, lock_id: &str, resources: &[String]) -> RedisResult<()> {
+        self.query::<()>(
+            fcall("dequeue", &self.key_formatter).arg(lock_id).arg(
+                &*self
+                    .hash_tag
+                    .keys(Cow::Borrowed(resources), &self.key_formatter)?,
+            ),
+        )
+        .await
+    }
+
+    /// Attempts to acquire a lock on each of `sets` returning immediately if they cannot all be
+    /// immediately acquired.
+    ///
+    /// Either every set is locked or none are, returning the lock identifier of each set in
+    /// order. Each lock is independent and must be released separately.
+    ///
+    /// # Errors
+    ///
+    /// - When the `acquire_locks` function is missing from the Redis instance.
+    /// - When a set is empty or `sets` overlap.
+    /// - When a set contains duplicates and [`MultiResourceLock::with_strict_resources`] is set.
+    #[inline]
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip(self), err)
+    )]
+    pub async fn try_acquire_many(
+        &self,
+        sets: &[Vec<String>],
+        expiration: Duration,
+    ) -> RedisResult<Option<Vec<String>>> {
+        let start = self.clock.now();
+        for _ in sets {
+            self.metrics.on_acquire_attempt();
+        }
+        let mut cmd = fcall("acquire_locks", &self.key_formatter);
+        cmd.arg(expiration_millis(jitter(
+            expiration,
+            self.expiration_jitter,
+        )));
+        for set in sets {
+            let unique = self.hash_tag.keys(
+                unique_resources(set, self.strict_resources)?,
+                &self.key_formatter,
+            )?;
+            cmd.arg(self.id_generator.generate())
+                .arg(unique.len())
+                .arg(&*unique);
+        }
+
+        let result: Option<Vec<String>> = self.query(&cmd).await?;
+
+        #[cfg(feature = "tracing")]
+        tracing::debug!(
+            outcome = if result.is_some() {
+                "acquired"
+            } else {
+                "contended"
+            }
+        );
+        if result.is_some() {
+            let wait = self.clock.now().saturating_duration_since(start);
+            for set in sets {
+                self.metrics.on_acquired_resources(set, wait);
+            }
+        }
+        Ok(result)
+    }
+
+    /// Attempts to acquire the lock on the first of `sets` that is not contended, returning
+    /// immediately if every set is contended.
+    ///
+    /// The sets are tried in order in a single call, returning the index of the acquired set and
+    /// the lock identifier, e.g. to lock whichever of several shards is free.
+    ///
+    /// # Errors
+    ///
+    /// - When the `acquire_any` function is missing from the Redis instance.
+    /// - When a set is empty.
+    /// - When a set contains duplicates and [`MultiResourceLock::with_strict_resources`] is set.
+    #[inline]
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip(self), err)
+    )]
+    pub async fn try_acquire_any(
+        &self,
+        sets: &[Vec<String>],
+        expiration: Duration,
+    ) -> RedisResult<Option<(usize, String)>> {
+        let start = self.clock.now();
+        self.metrics.on_acquire_attempt();
+        let mut cmd = fcall("acquire_any", &self.key_formatter);
+        cmd.arg(self.id_generator.generate())
+            .arg(expiration_millis(jitter(
+                expiration,
+                self.expiration_jitter,
+            )));
+        for set in sets {
+            let unique = self.hash_tag.keys(
+                unique_resources(set, self.strict_resources)?,
+                &self.key_formatter,
+            )?;
+            cmd.arg(unique.len()).arg(&*unique);
+        }
+
+        let result: Option<(usize, String)> = self.query(&cmd).await?;
+
+        #[cfg(feature = "tracing")]
+        tracing::debug!(
+            outcome = if result.is_some() {
+                "acquired"
+            } else {
+                "contended"
+            }
+        );
+        if let Some(set) = result.as_ref().and_then(|(index, _)| sets.get(*index)) {
+            self.metrics
+                .on_acquired_resources(set, self.clock.now().saturating_duration_since(start));
+        }
+        Ok(result)
+    }
+
+    /// Attempts to acquire the lock on each resource of `resources` with its own expiration,
+    /// returning immediately if the lock cannot be immediately acquired.
+    ///
+    /// Each resource is released when its expiration elapses, or never when it is
+    /// [`INDEFINITE`], so the lock is no longer held in full once its shortest-lived resource
+    /// expires. Extending the lock gives every resource the same expiration.
+    ///
+    /// # Errors
+    ///
+    /// - When the `acquire_lock_expiring` function is missing from the Redis instance.
+    /// - When `resources` is empty or contains duplicates.
+    #[inline]
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip(self), err)
+    )]
+    pub async fn try_acquire_expiring(
+        &self,
+        resources: &[(String, Duration)],
+    ) -> RedisResult<Option<String>> {
+        let names = resources
+            .iter()
+            .map(|(resource, _)| resource.clone())
+            .collect::<Vec<_>>();
+        let keys = self
+            .hash_tag
+            .keys(unique_resources(&names, true)?, &self.key_formatter)?;
+        let start = self.clock.now();
+        self.metrics.on_acquire_attempt();
+        let mut cmd = fcall("acquire_lock_expiring", &self.key_formatter);
+        cmd.arg(self.id_generator.generate());
+        for (key, (_, expiration)) in keys.iter().zip(resources) {
+            cmd.arg(key).arg(expiration_millis(jitter(
+                *expiration,
+                self.expiration_jitter,
+            )));
+        }
+
+        let result: Option<String> = self.query(&cmd).await?;
+
+        #[cfg(feature = "tracing")]
+        tracing::debug!(
+            outcome = if result.is_some() {
+                "acquired"
+            } else {
+                "contended"
+            }
+        );
+        if result.is_some() {
+            self.metrics
+                .on_acquired_resources(&names, self.clock.now().saturating_duration_since(start));
+        }
+        Ok(result)
+    }
+
+    /// Attempts to acquire `weight` units of `resource`, which has `capacity` units in total,
+    /// returning immediately if they cannot be immediately acquired.
+    ///
+    /// Succeeds while the units held by other locks plus `weight` do not exceed `capacity`,
+    /// units held by locks that expired without being released are reclaimed. Weighted locks
+    /// are independent of the exclusive locks acquired by [`MultiResourceLock::try_acquire`]
+    /// and must be released with [`MultiResourceLock::release_weighted`].
+    ///
+    /// # Errors
+    ///
+    /// - When the `acquire_weighted` function is missing from the Redis instance.
+    #[inline]
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip(self), err)
+    )]
+    pub async fn acquire_weighted(
+        &self,
+        resource: &str,
+        weight: u32,
+        capacity: u32,
+        expiration: Duration,
+    ) -> RedisResult<Option<String>> {
+        let start = self.clock.now();
+        self.metrics.on_acquire_attempt();
+        let result: Option<String> = self
+            .query(
+                fcall("acquire_weighted", &self.key_formatter)
+                    .arg(self.id_generator.generate())
+                    .arg(expiration_millis(jitter(
+                        expiration,
+                        self.expiration_jitter,
+                    )))
+                    .arg(weight)
+                    .arg(capacity)
+                    .arg(&*self.hash_tag.key(resource)),
+            )
+            .await?;
+
+        #[cfg(feature = "tracing")]
+        tracing::debug!(
+            outcome = if result.is_some() {
+                "acquired"
+            } else {
+                "contended"
+            }
+        );
+        if result.is_some() {
+            self.metrics.on_acquired_resources(
+                &[resource.to_owned()],
+                self.clock.now().saturating_duration_since(start),
+            );
+        }
+        Ok(result)
+    }
+
+    /// Runs `cmd` on a new connection, loading the Lua library and retrying once when a function
+    /// is missing and [`MultiResourceLock::with_auto_setup`] is set.
+    async fn query<T: FromRedisValue>(&self, cmd: &Cmd) -> RedisResult<T> {
+        // Connecting is boxed as its future is large and would be held by every caller.
+        let mut connection = self.bounded(Box::pin(self.provider.connection())).await?;
+        match self.bounded(cmd.query_async(&mut connection)).await {
+            Err(err) if self.auto_setup && is_missing_function(&err) => {
+                #[cfg(feature = "tracing")]
+                tracing::warn!(library = library_name(), "loading missing Lua library");
+                self.bounded(load_library(&mut connection)).await?;
+                self.bounded(cmd.query_async(&mut connection)).await
+            }
+            result => result,
+        }
+    }
+
+    /// Runs the Redis call `call`, erroring when it does not complete within
+    /// [`MultiResourceLock::with_command_timeout`].
+    async fn bounded<T, F: Future<Output = RedisResult<T>>>(&self, call: F) -> RedisResult<T> {
+        let Some(limit) = self.command_timeout else {
+            return call.await;
+        };
+        time::timeout(limit, call)
+            .await
+            .unwrap_or_else(|| Err(command_timeout()))
+    }
+
+    /// Checks Redis is reachable and the Lua library functions required for locking are loaded
+    /// by [`setup`], e.g. for a readiness probe.
+    ///
+    /// # Errors
+    ///
+    /// - When [`ConnectionProvider::connection`] errors.
+    /// - When Redis cannot be reached.
+    /// - When the Lua library functions of [`LIBRARY_VERSION`](crate::LIBRARY_VERSION) are
+    ///   missing from the Redis instance, or differ from those of this crate.
+    #[inline]
+    pub async fn healthcheck(&self) -> RedisResult<()> {
+        let mut connection = self.bounded(Box::pin(self.provider.connection())).await?;
+        self.bounded(redis::cmd("PING").exec_async(&mut connection))
+            .await?;
+        let loaded: Vec<HashMap<String, Value>> = self
+            .bounded(
+                redis::cmd("FUNCTION")
+                    .arg("LIST")
+                    .arg("LIBRARYNAME")
+                    .arg(library_name())
+                    .arg("WITHCODE")
+                    .query_async(&mut connection),
+            )
+            .await?;
+        check_loaded(&loaded)
+    }
+
+    /// Counts the held locks and locked resources across all users of the Redis instance.
+    ///
+    /// This reads an index maintained as locks are acquired and released, rather than scanning
+    /// the locks, so is cheap enough for a health check.
+    ///
+    /// # Errors
+    ///
+    /// - When the `lock_stats` function is missing from the Redis instance.
+    #[inline]
+    pub async fn stats(&self) -> RedisResult<LockStats> {
+        let (active_locks, locked_resources): (usize, usize) = self
+            .query(&fcall("lock_stats", &self.key_formatter))
+            .await?;
+
+        Ok(LockStats {
+            active_locks,
+            locked_resources,
+        })
+    }
+
+    /// Removes the locks whose keys no longer exist from the index read by
+    /// [`MultiResourceLock::stats`], along with the resources no longer locked.
+    ///
+    /// Entries are normally removed as locks are released or expire, but can be left behind when
+    /// lock keys are removed by other means, e.g. eviction or `DEL`. This scans every lock key, so
+    /// is meant for occasional maintenance rather than every call.
+    ///
+    /// Returns the number of locks removed.
+    ///
+    /// # Errors
+    ///
+    /// - When the `compact_index` function is missing from the Redis instance.
+    #[inline]
+    pub async fn compact_index(&self) -> RedisResult<usize> {
+        self.query(&fcall("compact_index", &self.key_formatter))
+            .await
+    }
+
+    /// Checks whether the lock `lock_id` is still held.
+    ///
+    /// E.g. to confirm a lock identifier persisted before a restart still refers to a held lock
+    /// before resuming work under it.
+    ///
+    /// # Errors
+    ///
+    /// - When the `lock_held` function is missing from the Redis instance.
+    #[inline]
+    pub async fn is_held(&self, lock_id: &str) -> RedisResult<bool> {
+        let result: bool = self
+            .query(fcall("lock_held", &self.key_formatter).arg(lock_id))
+            .await?;
+
+        Ok(result)
+    }
+
+    /// Checks whether the lock `lock_id` still holds every one of `resources`.
+    ///
+    /// E.g. to confirm the lock has not expired, say during a long GC pause, just before a
+    /// critical write. Unlike [`MultiResourceLock::is_held`] this only reads the listed resources
+    /// so is cheap, but the lock may still expire straight after it returns.
+    ///
+    /// # Errors
+    ///
+    /// - When the `lock_owns` function is missing from the Redis instance.
+    #[inline]
+    pub async fn owns(&self, lock_id: &str, resources: &[String]) -> RedisResult<bool> {
+        let result: bool = self
+            .query(
+                fcall("lock_owns", &self.key_formatter).arg(lock_id).arg(
+                    &*self
+                        .hash_tag
+                        .keys(Cow::Borrowed(resources), &self.key_formatter)?,
+                ),
+            )
+            .await?;
+
+        Ok(result)
+    }
+
+    /// Counts the waiters on `resource`, e.g. as a contention metric for autoscaling.
+    ///
+    /// Waiters queued by [`MultiResourceLock::with_fair_queue`] or
+    /// [`MultiResourceLock::acquire_with_priority`] are counted exactly, waiters that died are not
+    /// counted. Waiters retrying without queueing are approximated by the contended attempts on
+    /// `resource` within the last [`DEFAULT_SLEEP`], so are over counted when retrying more
+    /// often and under counted when retrying less often.
+    ///
+    /// # Errors
+    ///
+    /// - When the `waiter_count` function is missing from the Redis instance.
+    #[inline]
+    pub async fn waiter_count(&self, resource: &str) -> RedisResult<usize> {
+        let result: usize = self
+            .query(fcall("waiter_count", &self.key_formatter).arg(&*self.hash_tag.key(resource)))
+            .await?;
+
+        Ok(result)
+    }
+
+    /// Gets the position of the waiter `lock_id` in the queue for `resource`, where `0` is the
+    /// head of the queue.
+    ///
+    /// Waiters queue when [`MultiResourceLock::with_fair_queue`] is set or when acquiring with
+    /// [`MultiResourceLock::acquire_with_priority`], waiters that died are not counted.
+    ///
+    /// Returns `None` when `lock_id` is not waiting on `resource`.
+    ///
+    /// # Errors
+    ///
+    /// - When the `queue_position` function is missing from the Redis instance.
+    #[inline]
+    pub async fn queue_position(
+        &self,
+        resource: &str,
+        lock_id: &str,
+    ) -> RedisResult<Option<usize>> {
+        let result: Option<usize> = self
+            .query(
+                fcall("queue_position", &self.key_formatter)
+                    .arg(&*self.hash_tag.key(resource))
+                    .arg(lock_id),
+            )
+            .await?;
+
+        Ok(result)
+    }
+
+    /// Runs `commands` only if the lock `lock_id` still holds every one of `resources`.
+    ///
+    /// The ownership check and the commands run atomically in Redis, so the lock cannot expire
+    /// between them. E.g. `redis::cmd("DECRBY").arg("account1").arg(100)` and
+    /// `redis::cmd("INCRBY").arg("account2").arg(100)` to transfer between the accounts
+    /// `account1` and `account2`.
+    ///
+    /// Returns the reply of each command, or `None` when a resource is not held.
+    ///
+    /// # Errors
+    ///
+    /// - When the `guarded_exec` function is missing from the Redis instance.
+    /// - When `resources` is empty.
+    /// - When a command errors, commands before it will have been run.
+    #[inline]
+    pub async fn guarded_exec(
+        &self,
+        lock_id: &str,
+        resources: &[String],
+        commands: &[Cmd],
+    ) -> RedisResult<Option<Vec<Value>>> {
+        let keys = self
+            .hash_tag
+            .keys(Cow::Borrowed(resources), &self.key_formatter)?;
+        let result: Option<Vec<Value>> = self
+            .query(&guarded_exec_cmd(
+                &self.key_formatter,
+                lock_id,
+                &keys,
+                commands,
+            ))
+            .await?;
+
+        Ok(result)
+    }
+
+    /// Moves `amount` from the integer at `from` to the integer at `to`, only if the lock
+    /// `lock_id` still holds both as resources and `from` holds at least `amount`.
+    ///
+    /// The ownership check, the balance check and both updates run atomically in Redis in a
+    /// single round trip, e.g. to transfer between accounts locked by `lock_id`. A missing key
+    /// holds `0`. Nothing is moved when either key is not a resource of the lock.
+    ///
+    /// # Errors
+    ///
+    /// - When the `guarded_move` function is missing from the Redis instance.
+    /// - When `amount` is negative or `from` does not hold an integer.
+    #[inline]
+    pub async fn guarded_move(
+        &self,
+        lock_id: &str,
+        from: &str,
+        to: &str,
+        amount: i64,
+    ) -> RedisResult<MoveOutcome> {
+        let result: Option<bool> = self
+            .query(
+                fcall("guarded_move", &self.key_formatter)
+                    .arg(lock_id)
+                    .arg(from)
+                    .arg(to)
+                    .arg(amount)
+                    .arg(&*self.hash_tag.key(from))
+                    .arg(&*self.hash_tag.key(to)),
+            )
+            .await?;
+
+        Ok(MoveOutcome::from_reply(result))
+    }
+
+    /// Gets the resources held by the lock `lock_id`, sorted.
+    ///
+    /// Returns an empty list when `lock_id` is not held.
+    ///
+    /// # Errors
+    ///
+    /// - When the `lock_resources` function is missing from the Redis instance.
+    #[inline]
+    pub async fn lock_resources(&self, lock_id: &str) -> RedisResult<Vec<String>> {
+        let result: Vec<String> = self
+            .query(fcall("lock_resources", &self.key_formatter).arg(lock_id))
+            .await?;
+
+        Ok(self.hash_tag.resources(result))
+    }
+
+    /// Gets the remaining time to live of the lock `lock_id` on each resource it holds, sorted
+    /// by resource.
+    ///
+    /// Resources that never expire have [`INDEFINITE`]. Returns an empty list when `lock_id`
+    /// is not held.
+    ///
+    /// # Errors
+    ///
+    /// - When the `lock_ttls` function is missing from the Redis instance.
+    #[inline]
+    pub async fn ttls(&self, lock_id: &str) -> RedisResult<Vec<(String, Duration)>> {
+        let result: Vec<(String, i64)> = self
+            .query(fcall("lock_ttls", &self.key_formatter).arg(lock_id))
+            .await?;
+
+        Ok(ttls_from_reply(result, &self.hash_tag))
+    }
+
+    /// Gets the metadata stored alongside the lock `lock_id`.
+    ///
+    /// Returns `None` when `lock_id` is not held or was acquired without metadata.
+    ///
+    /// # Errors
+    ///
+    /// - When the `lock_metadata` function is missing from the Redis instance.
+    #[inline]
+    pub async fn lock_metadata(&self, lock_id: &str) -> RedisResult<Option<String>> {
+        let result: Option<String> = self
+            .query(fcall("lock_metadata", &self.key_formatter).arg(lock_id))
+            .await?;
+
+        Ok(result)
+    }
+
+    /// Gets the metadata stored alongside the lock holding `resource`.
+    ///
+    /// Returns `None` when `resource` is not locked or was locked without metadata.
+    ///
+    /// # Errors
+    ///
+    /// - When the `resource_metadata` function is missing from the Redis instance.
+    #[inline]
+    pub async fn resource_metadata(&self, resource: &str) -> RedisResult<Option<String>> {
+        let result: Option<String> = self
+            .query(
+                fcall("resource_metadata", &self.key_formatter).arg(&*self.hash_tag.key(resource)),
+            )
+            .await?;
+
+        Ok(result)
+    }
+
+    /// Checks which of `resources` are held, by a lock or a weighted lock, without acquiring
+    /// anything.
+    ///
+    /// Returns each resource alongside whether it is held. Nothing is written, so the result may
+    /// be stale by the time it is acted on.
+    ///
+    /// # Errors
+    ///
+    /// - When the `probe` function is missing from the Redis instance.
+    /// - When the resources hash to different slots with [`HashTag::Existing`].
+    #[inline]
+    pub async fn probe(&self, resources: &[String]) -> RedisResult<Vec<(String, bool)>> {
+        let held: Vec<bool> = self
+            .query(
+                fcall("probe", &self.key_formatter).arg(
+                    &*self
+                        .hash_tag
+                        .keys(Cow::Borrowed(resources), &self.key_formatter)?,
+                ),
+            )
+            .await?;
+
+        Ok(resources.iter().cloned().zip(held).collect())
+    }
+
+    /// Resets the expiration of the lock `lock_id` on `resources` to `expiration` from now, or to
+    /// never expire when it is [`INDEFINITE`].
+    ///
+    /// Returns `false`, leaving the lock unchanged, when any of `resources` is no longer held by
+    /// `lock_id`. An expired lock is never recreated, as another lock may have since acquired
+    /// its resources.
+    ///
+    /// # Errors
+    ///
+    /// - When the `extend_lock` function is missing from the Redis instance.
+    #[inline]
+    pub async fn try_extend(
+        &self,
+        lock_id: &str,
+        resources: &[String],
+        expiration: Duration,
+    ) -> RedisResult<bool> {
+        let result: bool = self
+            .query(
+                fcall("extend_lock", &self.key_formatter)
+                    .arg(lock_id)
+                    .arg(expiration_millis(expiration))
+                    .arg(false)
+                    .arg(
+                        &*self
+                            .hash_tag
+                            .keys(Cow::Borrowed(resources), &self.key_formatter)?,
+                    ),
+            )
+            .await?;
+
+        Ok(result)
+    }
+
+    /// Extends the lock `lock_id` on each resource it holds to expire no sooner than `minimum`
+    /// from now, or never when it is [`INDEFINITE`].
+    ///
+    /// Unlike [`MultiResourceLock::try_extend`] an expiration already later than `minimum` is
+    /// kept, so concurrent renewers never shorten the lock. Returns `false` when `lock_id` is not
+    /// held.
+    ///
+    /// # Errors
+    ///
+    /// - When the `extend_lock_at_least` function is missing from the Redis instance.
+    #[inline]
+    pub async fn extend_at_least(&self, lock_id: &str, minimum: Duration) -> RedisResult<bool> {
+        let result: bool = self
+            .query(
+                fcall("extend_lock_at_least", &self.key_formatter)
+                    .arg(lock_id)
+                    .arg(expiration_millis(minimum)),
+            )
+            .await?;
+
+        Ok(result)
+    }
+
+    /// Releases a held lock.
+    ///
+    /// Returns the number of resources released, see [`MultiResourceLock::release_outcome`] to
+    /// tell whether the lock expired before it was released.
+    ///
+    /// # Errors
+    ///
+    /// When [`MultiResourceLock::release_outcome`] errors.
+    #[inline]
+    #[expect(
+        clippy::same_name_method,
+        reason = "The `Lock` implementation delegates to this."
+    )]
+    pub async fn release(&self, lock_id: &str) -> RedisResult<usize> {
+        Ok(self.release_outcome(lock_id).await?.released())
+    }
+
+    /// Releases a held lock, reporting whether it expired before it was released.
+    ///
+    /// A lock released after it expired is reported to [`Metrics::on_release_expired`], and
+    /// logged as a warning when the `tracing` feature is enabled.
+    ///
+    /// # Errors
+    ///
+    /// - When the `release_lock` function is missing from the Redis instance.
+    #[inline]
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip(self), err)
+    )]
+    pub async fn release_outcome(&self, lock_id: &str) -> RedisResult<ReleaseOutcome> {
+        self.release_marking(lock_id, None).await
+    }
+
+    /// Releases a held lock, marking its resources as poisoned for `ttl`, or until cleared when it
+    /// is [`INDEFINITE`].
+    ///
+    /// Like a [`std::sync::Mutex`] poisoned by a panic, this records that the resources were
+    /// released in a potentially inconsistent state, so the next holder can check
+    /// [`MultiResourceLock::is_poisoned`] and run recovery before
+    /// [`MultiResourceLock::clear_poison`].
+    ///
+    /// # Errors
+    ///
+    /// - When the `release_lock` function is missing from the Redis instance.
+    #[inline]
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip(self), err)
+    )]
+    pub async fn release_poisoned(
+        &self,
+        lock_id: &str,
+        ttl: Duration,
+    ) -> RedisResult<ReleaseOutcome> {
+        self.release_marking(lock_id, Some(ttl)).await
+    }
+
+    /// Releases the lock `lock_id`, marking its resources as poisoned for `poison` when set.
+    async fn release_marking(
+        &self,
+        lock_id: &str,
+        poison: Option<Duration>,
+    ) -> RedisResult<ReleaseOutcome> {
+        let mut cmd = fcall("release_lock", &self.key_formatter);
+        cmd.arg(lock_id).arg(poison_args(poison));
+        let mut attempt = 0u32;
+        let reply: (usize, bool) = loop {
+            match self.query(&cmd).await {
+                Err(err) if attempt < self.release_retries && is_transient(&err) => {
+                    #[cfg(feature = "tracing")]
+                    tracing::debug!(lock_id, %err, "retrying release after transient error");
+                    attempt = attempt.saturating_add(1);
+                    self.clock.sleep(RELEASE_RETRY_SLEEP).await;
+                }
+                reply => break reply?,
+            }
+        };
+        let outcome = ReleaseOutcome::from_reply(reply);
+        report_release(&*self.metrics, lock_id, outcome);
+        #[cfg(feature = "log")]
+        log_release(self.log_target, lock_id, outcome);
+        #[cfg(feature = "tokio")]
+        if let Some(local) = &self.local {
+            local.notify_released();
+        }
+        Ok(outcome)
+    }
+
+    /// Checks whether `resource` was released poisoned, see
+    /// [`MultiResourceLock::release_poisoned`].
+    ///
+    /// # Errors
+    ///
+    /// - When the `poisoned` function is missing from the Redis instance.
+    #[inline]
+    pub async fn is_poisoned(&self, resource: &str) -> RedisResult<bool> {
+        let result: bool = self
+            .query(fcall("poisoned", &self.key_formatter).arg(&*self.hash_tag.key(resource)))
+            .await?;
+
+        Ok(result)
+    }
+
+    /// Clears the poison on `resource`, e.g. once recovery has run.
+    ///
+    /// Returns `false` when `resource` was not poisoned.
+    ///
+    /// # Errors
+    ///
+    /// - When the `clear_poison` function is missing from the Redis instance.
+    #[inline]
+    pub async fn clear_poison(&self, resource: &str) -> RedisResult<bool> {
+        let result: bool = self
+            .query(fcall("clear_poison", &self.key_formatter).arg(&*self.hash_tag.key(resource)))
+            .await?;
+
+        Ok(result)
+    }
+
+    /// Releases the units of `resource` held by the weighted lock `lock_id`.
+    ///
+    /// Returns `false` when `lock_id` does not hold units of `resource`.
+    ///
+    /// # Errors
+    ///
+    /// - When the `release_weighted` function is missing from the Redis instance.
+    #[inline]
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip(self), err)
+    )]
+    pub async fn release_weighted(&self, resource: &str, lock_id: &str) -> RedisResult<bool> {
+        let result: bool = self
+            .query(
+                fcall("release_weighted", &self.key_formatter)
+                    .arg(&*self.hash_tag.key(resource))
+                    .arg(lock_id),
+            )
+            .await?;
+
+        if result {
+            self.metrics.on_release();
+        }
+        Ok(result)
+    }
+
+    /// Releases several held locks at once.
+    ///
+    /// Returns the total number of keys cleared, lock identifiers which do not refer to a held
+    /// lock are ignored.
+    ///
+    /// # Errors
+    ///
+    /// - When the `release_locks` function is missing from the Redis instance.
+    #[inline]
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip(self), err)
+    )]
+    pub async fn release_many(&self, lock_ids: &[String]) -> RedisResult<usize> {
+        let (cleared, released): (usize, usize) = self
+            .query(fcall("release_locks", &self.key_formatter).arg(lock_ids))
+            .await?;
+
+        #[cfg(feature = "tracing")]
+        tracing::debug!(released = cleared);
+        for _ in 0..released {
+            self.metrics.on_release();
+        }
+        Ok(cleared)
+    }
+
+    /// Forcibly releases `resource` regardless of which lock holds it.
+    ///
+    /// This is an escape hatch for breaking locks left behind by dead workers and should not be
+    /// used in place of [`MultiResourceLock::release`]. Other resources held by the same lock
+    /// remain held.
+    ///
+    /// Returns the number of keys cleared.
+    ///
+    /// # Errors
+    ///
+    /// - When the `force_release` function is missing from the Redis instance.
+    #[inline]
+    pub async fn force_release(&self, resource: &str) -> RedisResult<usize> {
+        let result: usize = self
+            .query(fcall("force_release", &self.key_formatter).arg(&*self.hash_tag.key(resource)))
+            .await?;
+
+        Ok(result)
+    }
+
+    /// Forcibly releases `resources` regardless of which locks hold them.
+    ///
+    /// Unlike [`MultiResourceLock::release`] this needs no lock id and checks no ownership, so it
+    /// is an escape hatch for recovering resources whose holder is unknown, e.g. when the lock id
+    /// was not persisted. Other resources held by the same locks remain held. Each lock broken is
+    /// logged at warn level for auditing.
+    ///
+    /// Returns the number of keys cleared.
+    ///
+    /// # Errors
+    ///
+    /// - When the `force_release_resources` function is missing from the Redis instance.
+    #[inline]
+    pub async fn release_by_resources(&self, resources: &[String]) -> RedisResult<usize> {
+        let (cleared, lock_ids): (usize, Vec<String>) = self
+            .query(
+                fcall("force_release_resources", &self.key_formatter).arg(
+                    &*self
+                        .hash_tag
+                        .keys(Cow::Borrowed(resources), &self.key_formatter)?,
+                ),
+            )
+            .await?;
+
+        #[cfg(any(feature = "tracing", feature = "log"))]
+        for lock_id in &lock_ids {
+            #[cfg(feature = "tracing")]
+            tracing::warn!(lock_id, "forcibly released resources of lock");
+            #[cfg(feature = "log")]
+            log::warn!(target: self.log_target, "forcibly released resources of lock {lock_id}");
+        }
+        #[cfg(not(any(feature = "tracing", feature = "log")))]
+        let _: Vec<String> = lock_ids;
+        Ok(cleared)
+    }
+
+    /// Forcibly releases the lock `lock_id` ignoring ownership checks.
+    ///
+    /// This is an escape hatch for breaking locks left behind by dead workers and should not be
+    /// used in place of [`MultiResourceLock::release`].
+    ///
+    /// Returns the number of keys cleared.
+    ///
+    /// # Errors
+    ///
+    /// - When the `force_release_lock` function is missing from the Redis instance.
+    #[inline]
+    pub async fn force_release_lock(&self, lock_id: &str) -> RedisResult<usize> {
+        let result: usize = self
+            .query(fcall("force_release_lock", &self.key_formatter).arg(lock_id))
+            .await?;
+
+        Ok(result)
+    }
+
+    /// Creates a scope holding the locks acquired through it, released together, the most
+    /// recently acquired first, by [`LockScope::release_all`](scope::LockScope::release_all).
+    ///
+    /// E.g. for the locks acquired at different steps of a saga.
+    #[inline]
+    #[must_use]
+    pub fn scope(&self) -> scope::LockScope<'_, C> {
+        scope::LockScope::new(self)
+    }
+
+    /// Since we cannot safely drop a guard in an async context, we need to provide a way to release the lock in case of an error.
+    ///
+    /// This is the suggested approach, it is less ergonomic but it is safe.
+    ///
+    /// # Errors
+    ///
+    /// - When [`MultiResourceLock::acquire`] errors.
+    /// - When [`MultiResourceLock::acquire`] times out.
+    /// - When [`MultiResourceLock::release`] errors.
+    #[inline]
+    pub async fn map<F>(
+        &self,
+        resources: &[String],
+        expiration: Duration,
+        timeout: Duration,
+        sleep: Duration,
+        f: F,
+    ) -> Result<F::Output, MapError>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        let lock_id = self
+            .acquire(resources, expiration, timeout, sleep)
+            .await
+            .map_err(MapError::Acquire)?
+            .ok_or(MapError::Timeout)?;
+        let result = f.await;
+        self.release(&lock_id).await.map_err(MapError::Release)?;
+        Ok(result)
+    }
+
+    /// Calls [`MultiResourceLock::map`] with [`DEFAULT_EXPIRATION`], [`DEFAULT_TIMEOUT`] and [`DEFAULT_SLEEP`].
+    ///
+    /// # Errors
+    ///
+    /// When [`MultiResourceLock::map`] errors.
+    #[inline]
+    pub async fn map_default<F>(&self, resources: &[String], f: F) -> Result<F::Output, MapError>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        self.map(
+            resources,
+            DEFAULT_EXPIRATION,
+            DEFAULT_TIMEOUT,
+            DEFAULT_SLEEP,
+            f,
+        )
+        .await
+    }
+}
+
+/// Encodes `expiration` as the milliseconds passed to the Lua functions, where `0` is
+/// [`INDEFINITE`] so shorter expirations are rounded up.
+pub(crate) fn expiration_millis(expiration: Duration) -> String {
+    if expiration == INDEFINITE {
+        String::from("0")
+    } else {
+        expiration.as_millis().max(1).to_string()
+    }
+}
+
+/// The error returned when a Redis call does not complete within
+/// [`MultiResourceLock::with_command_timeout`], [`RedisError::is_timeout`] is `true` for it.
+pub(crate) fn command_timeout() -> RedisError {
+    RedisError::from(std::io::Error::new(
+        std::io::ErrorKind::TimedOut,
+        "Redis call did not complete before the command timeout",
+    ))
+}
+
+/// The error returned when a Redis call does not complete within the time remaining to acquire
+/// the lock, [`RedisError::is_timeout`] is `true` for it.
+pub(crate) fn call_timeout() -> RedisError {
+    RedisError::from(std::io::Error::new(
+        std::io::ErrorKind::TimedOut,
+        "Redis call did not complete before the acquire timeout",
+    ))
+}
+
+impl MultiResourceLock<Client> {
+    /// Subscribes to the locks acquired and released by every holder on the Redis instance.
+    ///
+    /// E.g. for an audit log or to watch contention without polling
+    /// [`MultiResourceLock::stats`]. Events are published by the Lua library functions, so
+    /// locks left to expire and weighted locks publish none, and events published while not
+    /// subscribed are missed. The stream ends when the subscription's connection closes.
+    ///
+    /// # Errors
+    ///
+    /// When connecting or subscribing errors.
+    #[inline]
+    pub async fn events(&self) -> RedisResult<impl Stream<Item = LockEvent> + Send> {
+        let mut pubsub = self.bounded(self.provider.get_async_pubsub()).await?;
+        self.bounded(pubsub.subscribe(self.key_formatter.events_channel()))
+            .await?;
+        let hash_tag = self.hash_tag.clone();
+        Ok(pubsub.into_on_message().filter_map(move |message| {
+            let event = message
+                .get_payload::<String>()
+                .ok()
+                .and_then(|payload| LockEvent::from_payload(&payload, &hash_tag));
+            std::future::ready(event)
+        }))
+    }
+}
+
+/// What an attempt to acquire the lock that was cut short or failed leaves behind.
+#[derive(Debug)]
+pub(crate) struct Abandoned {
+    /// The resources of the attempt.
+    resources: Vec<String>,
+    /// The identifier the attempt may have acquired the lock with.
+    lock_id: Option<String>,
+    /// The waiter the attempt may have queued.
+    queue: Option<Waiter>,
+}
+
+impl Abandoned {
+    /// Create what an attempt on `resources` leaves behind.
+    fn new(resources: &[String], lock_id: Option<String>, queue: Option<Waiter>) -> Self {
+        Abandoned {
+            resources: resources.to_vec(),
+            lock_id,
+            queue,
+        }
+    }
+}
+
+/// A waiter queued to acquire the lock.
+#[derive(Debug)]
+pub(crate) struct Waiter {
+    /// The identifier the lock is acquired with.
+    lock_id: String,
+    /// How long the waiter stays queued without making an attempt, waiters that miss a few
+    /// attempts are assumed to have died.
+    expiration: Duration,
+    /// The priority of the waiter.
+    priority: i8,
+}
+
+impl Waiter {
+    /// Create a waiter with `priority` under the identifier `lock_id`.
+    pub(crate) fn new(lock_id: String, priority: i8) -> Self {
+        Waiter {
+            lock_id,
+            expiration: Duration::from_secs(1),
+            priority,
+        }
+    }
+
+    /// Sets the waiter to wait `delay` before its next attempt.
+    pub(crate) fn wait(&mut self, delay: Duration) {
+        self.expiration = delay.saturating_mul(3).max(Duration::from_secs(1));
+    }
+}
+
+/// Builds the call to the `acquire_lock` function, or `acquire_lock_queued` when `queue` is
+/// `Some`, passing `resources` without copying them into intermediate strings.
+///
+/// `condition` is only checked by `acquire_lock`, so is ignored when `queue` is `Some`.
+pub(crate) fn acquire_cmd(
+    keys: &LockKeys,
+    lock_id: &str,
+    resources: &[String],
+    expiration: Duration,
+    metadata: Option<&str>,
+    queue: Option<&Waiter>,
+    condition: Option<&LuaCondition>,
+) -> Cmd {
+    let mut cmd = fcall(
+        if queue.is_some() {
+            "acquire_lock_queued"
+        } else {
+            "acquire_lock"
+        },
+        keys,
+    );
+    cmd.arg(lock_id)
+        .arg(expiration_millis(expiration))
+        .arg(metadata.unwrap_or_default());
+    if let Some(waiter) = queue {
+        cmd.arg(expiration_millis(waiter.expiration))
+            .arg(waiter.priority);
+    } else if let Some(check) = condition {
+        cmd.arg(check);
+    } else {
+        cmd.arg(("", "", ""));
+    }
+    cmd.arg(resources);
+    cmd
+}
+
+/// Randomly adds or subtracts up to `fraction` of `expiration`, leaving [`INDEFINITE`] unchanged.
+///
+/// The result is at least a millisecond, so a finite expiration never becomes [`INDEFINITE`].
+pub(crate) fn jitter(expiration: Duration, fraction: f64) -> Duration {
+    if expiration == INDEFINITE || fraction <= 0.0f64 {
+        return expiration;
+    }
+    let spread = expiration.mul_f64(fraction);
+    rand::thread_rng()
+        .gen_range(expiration.saturating_sub(spread)..=expiration.saturating_add(spread))
+        .max(Duration::from_millis(1))
+}
+
+/// Returns why `expiration` is unsafely short when waiting up to `timeout` to acquire the lock,
+/// sleeping `sleep` between attempts, or `None` when it is not.
+pub(crate) fn short_expiration(
+    expiration: Duration,
+    timeout: Option<Duration>,
+    sleep: Option<Duration>,
+) -> Option<&'static str> {
+    if expiration == INDEFINITE {
+        None
+    } else if sleep.is_some_and(|between| expiration < between) {
+        Some("Expiration shorter than the sleep between attempts")
+    } else if timeout.is_some_and(|limit| expiration < limit) {
+        Some("Expiration shorter than the timeout")
+    } else {
+        None
+    }
+}
+
+/// The most resources [`unique_resources`] checks for duplicates without a set.
+const SMALL_RESOURCES: usize = 16;
+
+/// Removes duplicates from `resources`, keeping the first of each, or errors on them when
+/// `strict`.
+///
+/// Up to [`SMALL_RESOURCES`] resources are compared pairwise rather than collected into a set.
+pub(crate) fn unique_resources(
+    resources: &[String],
+    strict: bool,
+) -> RedisResult<Cow<'_, [String]>> {
+    let mut seen = HashSet::new();
+    let found = if resources.len() <= SMALL_RESOURCES {
+        resources.iter().enumerate().find_map(|(index, resource)| {
+            resources
+                .iter()
+                .take(index)
+                .any(|earlier| earlier == resource)
+                .then_some(resource)
+        })
+    } else {
+        seen.reserve(resources.len());
+        resources
+            .iter()
+            .find(|resource| !seen.insert(resource.as_str()))
+    };
+    let Some(duplicate) = found else {
+        return Ok(Cow::Borrowed(resources));
+    };
+    if strict {
+        return Err(RedisError::from((
+            ErrorKind::ClientError,
+            "Duplicate resource",
+            duplicate.clone(),
+        )));
+    }
+    seen.clear();
+    Ok(Cow::Owned(
+        resources
+            .iter()
+            .filter(|resource| seen.insert(resource.as_str()))
+            .cloned()
+            .collect(),
+    ))
+}
+
+/// Builds the call to the `guarded_exec` function, the resources and each command are prefixed
+/// by their number of arguments.
+pub(crate) fn guarded_exec_cmd(
+    keys: &LockKeys,
+    lock_id: &str,
+    resources: &[String],
+    commands: &[Cmd],
+) -> Cmd {
+    let mut cmd = fcall("guarded_exec", keys);
+    cmd.arg(lock_id).arg(resources.len()).arg(resources);
+    for command in commands {
+        let simple = |arg| match arg {
+            Arg::Simple(bytes) => Some(bytes),
+            Arg::Cursor => None,
+        };
+        cmd.arg(command.args_iter().filter_map(simple).count());
+        for bytes in command.args_iter().filter_map(simple) {
+            cmd.arg(bytes);
+        }
+    }
+    cmd
+}
+
+/// The operations shared by [`MultiResourceLock`] and its test doubles.
+///
+/// Application code written against this trait can be tested with `mock::MockLock` (behind the
+/// `mock` feature) without a Redis instance, or run with locking disabled with
+/// [`null::NullLock`].
+///
+/// Like those of [`MultiResourceLock`], its methods take `&self`, so one lock can be shared
+/// between tasks, e.g. behind an [`Arc`].
+pub trait Lock {
+    /// Attempts to acquire the lock returning immediately if it cannot be immediately acquired.
+    ///
+    /// Returns the lock identifier when acquired.
+    fn try_acquire(
+        &self,
+        resources: &[String],
+        expiration: Duration,
+    ) -> impl Future<Output = RedisResult<Option<String>>> + Send;
+
+    /// Attempts to acquire the lock blocking until the lock can be acquired.
+    ///
+    /// Blocks up to `timeout` duration making attempts every `sleep` duration.
+    ///
+    /// Returns `None` when it times out.
+    fn acquire(
+        &self,
+        resources: &[String],
+        expiration: Duration,
+        timeout: Duration,
+        sleep: Duration,
+    ) -> impl Future<Output = RedisResult<Option<String>>> + Send;
+
+    /// Releases a held lock.
+    fn release(&self, lock_id: &str) -> impl Future<Output = RedisResult<usize>> + Send;
+}
+
+impl<C> Lock for MultiResourceLock<C>
+where
+    C: ConnectionProvider + Send + Sync,
+{
+    #[inline]
+    fn try_acquire(
+        &self,
+        resources: &[String],
+        expiration: Duration,
+    ) -> impl Future<Output = RedisResult<Option<String>>> + Send {
+        MultiResourceLock::try_acquire(self, resources, expiration)
+    }
+
+    #[inline]
+    fn acquire(
+        &self,
+        resources: &[String],
+        expiration: Duration,
+        timeout: Duration,
+        sleep: Duration,
+    ) -> impl Future<Output = RedisResult<Option<String>>> + Send {
+        MultiResourceLock::acquire(self, resources, expiration, timeout, sleep)
+    }
+
+    #[inline]
+    fn release(&self, lock_id: &str) -> impl Future<Output = RedisResult<usize>> + Send {
+        MultiResourceLock::release(self, lock_id)
+    }
+}
+
+/// The outcome of attempting to acquire the lock.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AcquireResult<T> {
+    /// The lock was acquired.
+    Acquired(T),
+    /// The lock could not be immediately acquired.
+    Contended {
+        /// The resources held by other locks, or waited on by queued waiters.
+        resources: Vec<String>,
+    },
+    /// The lock could not be acquired before timing out.
+    TimedOut,
+    /// The lock was not acquired as the condition checked alongside it failed, see
+    /// [`MultiResourceLock::try_acquire_if`].
+    PreconditionFailed,
+}
+
+impl<T> AcquireResult<T> {
+    /// Returns whether the lock was acquired.
+    #[inline]
+    #[must_use]
+    pub fn is_acquired(&self) -> bool {
+        matches!(*self, AcquireResult::Acquired(_))
+    }
+
+    /// Returns the acquired lock, or `None` when it was not acquired.
+    #[inline]
+    #[must_use]
+    pub fn acquired(self) -> Option<T> {
+        match self {
+            AcquireResult::Acquired(acquired) => Some(acquired),
+            AcquireResult::Contended { .. }
+            | AcquireResult::TimedOut
+            | AcquireResult::PreconditionFailed => None,
+        }
+    }
+
+    /// Maps the acquired lock with `f`.
+    #[inline]
+    pub fn map<U, F: FnOnce(T) -> U>(self, f: F) -> AcquireResult<U> {
+        match self {
+            AcquireResult::Acquired(acquired) => AcquireResult::Acquired(f(acquired)),
+            AcquireResult::Contended { resources } => AcquireResult::Contended { resources },
+            AcquireResult::TimedOut => AcquireResult::TimedOut,
+            AcquireResult::PreconditionFailed => AcquireResult::PreconditionFailed,
+        }
+    }
+}
+
+impl AcquireResult<String> {
+    /// Reads the reply of the `acquire_lock` and `acquire_lock_queued` functions, the lock
+    /// identifier when acquired, `0` when the condition failed or the contended resources, mapped
+    /// back by `hash_tag`, otherwise.
+    pub(crate) fn from_reply(reply: Value, hash_tag: &HashTag) -> RedisResult<Self> {
+        match reply {
+            Value::Int(_) => Ok(AcquireResult::PreconditionFailed),
+            Value::Array(_) => Ok(AcquireResult::Contended {
+                resources: hash_tag.resources(Vec::from_owned_redis_value(reply)?),
+            }),
+            _ => Ok(AcquireResult::Acquired(String::from_owned_redis_value(
+                reply,
+            )?)),
+        }
+    }
+}
+
+/// The outcome of releasing a lock.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReleaseOutcome {
+    /// The lock was released from this many resources.
+    Released(usize),
+    /// The lock expired before it was released, so the work done under it may have run
+    /// alongside another holder of its resources.
+    AlreadyExpired,
+    /// The lock was not held, e.g. it was already released.
+    ///
+    /// Locks that expired a while ago are reported as not held once
+    /// [`MultiResourceLock::stats`] prunes them from the index of held locks.
+    NotHeld,
+}
+
+impl ReleaseOutcome {
+    /// Returns the number of resources released.
+    #[inline]
+    #[must_use]
+    pub fn released(self) -> usize {
+        match self {
+            ReleaseOutcome::Released(count) => count,
+            ReleaseOutcome::AlreadyExpired | ReleaseOutcome::NotHeld => 0,
+        }
+    }
+
+    /// Reads the reply of the `release_lock` function, the number of resources released and
+    /// whether the lock expired before it was released.
+    pub(crate) fn from_reply((released, expired): (usize, bool)) -> Self {
+        if released > 0 {
+            ReleaseOutcome::Released(released)
+        } else if expired {
+            ReleaseOutcome::AlreadyExpired
+        } else {
+            ReleaseOutcome::NotHeld
+        }
+    }
+}
+
+/// The outcome of [`MultiResourceLock::guarded_move`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MoveOutcome {
+    /// The amount was moved.
+    Moved,
+    /// Nothing was moved as the source held less than the amount.
+    Insufficient,
+    /// Nothing was moved as the lock was not held.
+    NotHeld,
+}
+
+impl MoveOutcome {
+    /// Reads the reply of the `guarded_move` function, whether the amount was moved or `None`
+    /// when the lock was not held.
+    pub(crate) fn from_reply(reply: Option<bool>) -> Self {
+        match reply {
+            Some(true) => MoveOutcome::Moved,
+            Some(false) => MoveOutcome::Insufficient,
+            None => MoveOutcome::NotHeld,
+        }
+    }
+}
+
+/// Reads the reply of the `lock_ttls` function, each resource, mapped back by `hash_tag`, with
+/// its time to live in milliseconds or `-1` when it never expires.
+pub(crate) fn ttls_from_reply(
+    reply: Vec<(String, i64)>,
+    hash_tag: &HashTag,
+) -> Vec<(String, Duration)> {
+    let (keys, ttls): (Vec<String>, Vec<i64>) = reply.into_iter().unzip();
+    hash_tag
+        .resources(keys)
+        .into_iter()
+        .zip(
+            ttls.into_iter()
+                .map(|ttl| u64::try_from(ttl).map_or(INDEFINITE, Duration::from_millis)),
+        )
+        .collect()
+}
+
+/// Reports releasing the lock `lock_id` with `outcome` to `metrics`.
+pub(crate) fn report_release(metrics: &dyn Metrics, lock_id: &str, outcome: ReleaseOutcome) {
+    match outcome {
+        ReleaseOutcome::Released(_count) => {
+            #[cfg(feature = "tracing")]
+            tracing::debug!(released = _count);
+            metrics.on_release();
+        }
+        ReleaseOutcome::AlreadyExpired => {
+            #[cfg(feature = "tracing")]
+            tracing::warn!(lock_id, "lock expired before it was released");
+            metrics.on_release_expired();
+        }
+        ReleaseOutcome::NotHeld => {
+            #[cfg(feature = "tracing")]
+            tracing::debug!(released = 0usize);
+        }
+    }
+    #[cfg(not(feature = "tracing"))]
+    let _: &str = lock_id;
+}
+
+/// Reports failing to release the lock `lock_id` to `metrics`, where it would otherwise be lost,
+/// e.g. when a guard is dropped.
+pub(crate) fn report_release_error(metrics: &dyn Metrics, lock_id: &str, error: &RedisError) {
+    #[cfg(feature = "tracing")]
+    tracing::warn!(lock_id, %error, "failed to release lock");
+    #[cfg(not(feature = "tracing"))]
+    let _: &str = lock_id;
+    metrics.on_release_error(error);
+}
+
+/// Logs the `result` of an attempt to acquire the lock `lock_id` on `count` resources to `target`.
+#[cfg(feature = "log")]
+pub(crate) fn log_attempt(
+    target: &str,
+    lock_id: &str,
+    count: usize,
+    result: &AcquireResult<String>,
+) {
+    match result {
+        AcquireResult::Acquired(_) => {
+            log::debug!(target: target, "acquired lock {lock_id} on {count} resources");
+        }
+        AcquireResult::PreconditionFailed => {
+            log::debug!(target: target, "precondition failed for lock {lock_id} on {count} resources");
+        }
+        AcquireResult::Contended { .. } | AcquireResult::TimedOut => {
+            log::debug!(target: target, "lock {lock_id} contended on {count} resources");
+        }
+    }
+}
+
+/// Logs the `outcome` of releasing the lock `lock_id` to `target`.
+#[cfg(feature = "log")]
+pub(crate) fn log_release(target: &str, lock_id: &str, outcome: ReleaseOutcome) {
+    match outcome {
+        ReleaseOutcome::Released(count) => {
+            log::debug!(target: target, "released lock {lock_id} on {count} resources");
+        }
+        ReleaseOutcome::AlreadyExpired => {
+            log::debug!(target: target, "lock {lock_id} expired before it was released");
+        }
+        ReleaseOutcome::NotHeld => {
+            log::debug!(target: target, "lock {lock_id} was not held");
+        }
     }
 }
 
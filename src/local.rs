@@ -0,0 +1,81 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, PoisonError};
+use tokio::sync::futures::Notified;
+use tokio::sync::{Notify, OwnedMutexGuard};
+
+/// Queues the contenders for resources within this process, see
+/// [`MultiResourceLock::with_local_queue`](crate::MultiResourceLock::with_local_queue).
+#[derive(Debug, Default)]
+pub(crate) struct LocalQueue {
+    /// The gate of each resource with local contenders.
+    gates: Mutex<HashMap<String, Arc<tokio::sync::Mutex<()>>>>,
+    /// Notified when a lock is released locally.
+    released: Notify,
+}
+
+impl LocalQueue {
+    /// Waits until no other local contender is waiting on any of `resources`.
+    ///
+    /// The gates are entered in order so contenders on overlapping resources cannot deadlock.
+    pub(crate) async fn enter(self: &Arc<Self>, resources: &[String]) -> LocalPermit {
+        let mut sorted = resources.to_vec();
+        sorted.sort_unstable();
+        sorted.dedup();
+        let mut permit = LocalPermit {
+            queue: Arc::clone(self),
+            entered: Vec::with_capacity(sorted.len()),
+        };
+        for resource in sorted {
+            let gate = Arc::clone(
+                self.gates
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .entry(resource.clone())
+                    .or_default(),
+            );
+            let guard = gate.lock_owned().await;
+            permit.entered.push((resource, guard));
+        }
+        permit
+    }
+
+    /// Returns a future that completes on the next local release.
+    pub(crate) fn released(&self) -> Notified<'_> {
+        self.released.notified()
+    }
+
+    /// Wakes the local contenders waiting between attempts.
+    pub(crate) fn notify_released(&self) {
+        self.released.notify_waiters();
+    }
+}
+
+/// The gates entered by a local contender, left when dropped.
+#[derive(Debug)]
+pub(crate) struct LocalPermit {
+    /// The queue the gates belong to.
+    queue: Arc<LocalQueue>,
+    /// The entered gates by resource.
+    entered: Vec<(String, OwnedMutexGuard<()>)>,
+}
+
+impl Drop for LocalPermit {
+    /// Leaves the gates, removing those without other contenders.
+    #[inline]
+    fn drop(&mut self) {
+        let mut gates = self
+            .queue
+            .gates
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        for (resource, guard) in self.entered.drain(..) {
+            drop(guard);
+            if gates
+                .get(&resource)
+                .is_some_and(|gate| Arc::strong_count(gate) == 1)
+            {
+                gates.remove(&resource);
+            }
+        }
+    }
+}
@@ -0,0 +1,59 @@
+use redis::RedisError;
+use std::time::Duration;
+
+/// Callbacks invoked by the lock at points of interest.
+///
+/// All methods default to doing nothing so implementations only need to override the events they
+/// care about.
+///
+/// E.g. the contention rate is the number of attempts minus the number of acquisitions, and the
+/// number of active locks is the number of acquisitions minus the number of releases.
+pub trait Metrics: Send + Sync {
+    /// Called before each attempt to acquire a lock.
+    #[inline]
+    fn on_acquire_attempt(&self) {}
+    /// Called when a lock is acquired, with the time spent waiting for it.
+    #[inline]
+    fn on_acquired(&self, _wait: Duration) {}
+    /// Called when a lock is acquired, with its resources and the time spent waiting for it.
+    ///
+    /// E.g. to record wait times per resource to find the hot resources behind tail latency. By
+    /// default this calls [`Metrics::on_acquired`], which is not called when this is overridden.
+    #[inline]
+    fn on_acquired_resources(&self, _resources: &[String], wait: Duration) {
+        self.on_acquired(wait);
+    }
+    /// Called when acquiring a lock times out.
+    #[inline]
+    fn on_timeout(&self) {}
+    /// Called when a held lock is released.
+    #[inline]
+    fn on_release(&self) {}
+    /// Called when a lock is released after it expired.
+    ///
+    /// The work done under the lock overran its expiration, so may have run alongside another
+    /// holder of its resources.
+    #[inline]
+    fn on_release_expired(&self) {}
+    /// Called when a guard fails to release its lock when dropped.
+    ///
+    /// The lock remains held until it expires.
+    #[inline]
+    fn on_release_error(&self, _error: &RedisError) {}
+}
+
+/// [`Metrics`] that does nothing, used by default.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoopMetrics;
+
+impl Metrics for NoopMetrics {}
+
+/// A snapshot of the locks held in a Redis instance, see
+/// [`MultiResourceLock::stats`](crate::MultiResourceLock::stats).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct LockStats {
+    /// The number of held locks.
+    pub active_locks: usize,
+    /// The number of locked resources.
+    pub locked_resources: usize,
+}
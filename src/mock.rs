@@ -0,0 +1,198 @@
+use super::time::{self, Clock};
+use super::{Lock, INDEFINITE};
+use redis::{ErrorKind, RedisError, RedisResult, Value};
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+/// An in-memory lock emulating the semantics of [`MultiResourceLock`](crate::MultiResourceLock)
+/// without Redis.
+///
+/// Clones share the same locks, so a clone can be handed to each task that would otherwise have
+/// its own [`MultiResourceLock`](crate::MultiResourceLock). Locks are not shared between
+/// processes.
+#[derive(Clone)]
+pub struct MockLock {
+    /// The holders of each locked resource.
+    held: Arc<Mutex<HashMap<String, Held>>>,
+    /// The clock used to time expirations and attempts to acquire the lock.
+    clock: Arc<dyn Clock>,
+}
+
+/// The holder of a locked resource.
+#[derive(Debug)]
+struct Held {
+    /// The lock identifier.
+    lock_id: String,
+    /// When the lock expires, `None` when it is indefinite.
+    expires: Option<Instant>,
+}
+
+impl std::fmt::Debug for MockLock {
+    #[inline]
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MockLock")
+            .field("held", &self.held)
+            .field("clock", &"..")
+            .finish()
+    }
+}
+
+impl Default for MockLock {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MockLock {
+    /// Create a new instance of the lock with no resources locked.
+    #[inline]
+    #[must_use]
+    pub fn new() -> Self {
+        MockLock {
+            held: Arc::new(Mutex::new(HashMap::new())),
+            clock: time::default_clock(),
+        }
+    }
+
+    /// Sets the clock used to time expirations and attempts to acquire the lock.
+    #[inline]
+    #[must_use]
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Attempts to acquire the lock returning immediately if it cannot be immediately acquired.
+    ///
+    /// # Errors
+    ///
+    /// When `resources` is empty, with the error the Redis instance replies with to
+    /// [`MultiResourceLock::try_acquire`](crate::MultiResourceLock::try_acquire).
+    #[inline]
+    #[expect(
+        clippy::same_name_method,
+        reason = "The `Lock` implementation delegates to this."
+    )]
+    pub fn try_acquire(
+        &self,
+        resources: &[String],
+        expiration: Duration,
+    ) -> RedisResult<Option<String>> {
+        if resources.is_empty() {
+            return Err(no_resources());
+        }
+        let now = self.clock.now();
+        let mut held = self.held.lock().unwrap_or_else(PoisonError::into_inner);
+        held.retain(|_, holder| holder.expires.is_none_or(|expires| expires > now));
+        if resources.iter().any(|resource| held.contains_key(resource)) {
+            return Ok(None);
+        }
+        let lock_id = Uuid::new_v4().to_string();
+        let expires =
+            (expiration != INDEFINITE).then(|| now.checked_add(expiration).unwrap_or(now));
+        for resource in resources {
+            held.insert(
+                resource.clone(),
+                Held {
+                    lock_id: lock_id.clone(),
+                    expires,
+                },
+            );
+        }
+        Ok(Some(lock_id))
+    }
+
+    /// Attempts to acquire the lock blocking until the lock can be acquired.
+    ///
+    /// Blocks up to `timeout` duration making attempts every `sleep` duration.
+    ///
+    /// Returns `None` when it times out.
+    ///
+    /// # Errors
+    ///
+    /// When [`MockLock::try_acquire`] errors.
+    #[inline]
+    #[expect(
+        clippy::same_name_method,
+        reason = "The `Lock` implementation delegates to this."
+    )]
+    pub async fn acquire(
+        &self,
+        resources: &[String],
+        expiration: Duration,
+        timeout: Duration,
+        sleep: Duration,
+    ) -> RedisResult<Option<String>> {
+        let now = self.clock.now();
+        loop {
+            if self.clock.now().saturating_duration_since(now) > timeout {
+                return Ok(None);
+            }
+            if let Some(res) = self.try_acquire(resources, expiration)? {
+                break Ok(Some(res));
+            }
+            self.clock.sleep(sleep).await;
+        }
+    }
+
+    /// Releases a held lock.
+    ///
+    /// Returns the number of resources released.
+    ///
+    /// # Errors
+    ///
+    /// Never, this returns a result to match [`MultiResourceLock::release`](crate::MultiResourceLock::release).
+    #[inline]
+    #[expect(
+        clippy::same_name_method,
+        reason = "The `Lock` implementation delegates to this."
+    )]
+    pub fn release(&self, lock_id: &str) -> RedisResult<usize> {
+        let now = self.clock.now();
+        let mut held = self.held.lock().unwrap_or_else(PoisonError::into_inner);
+        held.retain(|_, holder| holder.expires.is_none_or(|expires| expires > now));
+        let before = held.len();
+        held.retain(|_, holder| holder.lock_id != lock_id);
+        Ok(before.saturating_sub(held.len()))
+    }
+}
+
+impl Lock for MockLock {
+    #[inline]
+    fn try_acquire(
+        &self,
+        resources: &[String],
+        expiration: Duration,
+    ) -> impl Future<Output = RedisResult<Option<String>>> + Send {
+        std::future::ready(MockLock::try_acquire(self, resources, expiration))
+    }
+
+    #[inline]
+    fn acquire(
+        &self,
+        resources: &[String],
+        expiration: Duration,
+        timeout: Duration,
+        sleep: Duration,
+    ) -> impl Future<Output = RedisResult<Option<String>>> + Send {
+        MockLock::acquire(self, resources, expiration, timeout, sleep)
+    }
+
+    #[inline]
+    fn release(&self, lock_id: &str) -> impl Future<Output = RedisResult<usize>> + Send {
+        std::future::ready(MockLock::release(self, lock_id))
+    }
+}
+
+/// The error the Lua library replies with when acquiring a lock on no resources, parsed as it
+/// would be from the reply.
+fn no_resources() -> RedisError {
+    redis::parse_redis_value(b"-No resources specified\r\n")
+        .and_then(Value::extract_error)
+        .err()
+        .unwrap_or_else(|| RedisError::from((ErrorKind::ResponseError, "No resources specified")))
+}
@@ -0,0 +1,157 @@
+use super::id::{IdGenerator as _, UuidV4};
+use super::{Lock, ReleaseOutcome};
+use redis::RedisResult;
+use std::future::Future;
+use std::time::Duration;
+
+/// A lock that never excludes, for running without Redis, e.g. in local development.
+///
+/// Every attempt acquires the lock immediately and releasing it does nothing, so code written
+/// against [`Lock`] keeps working with distributed locking disabled:
+///
+/// ```
+/// # use redis_lock::Lock;
+/// # use redis_lock::null::NullLock;
+/// async fn transfer(lock: &impl Lock) -> redis::RedisResult<()> {
+///     let resources = [String::from("account1"), String::from("account2")];
+///     let lock_id = lock
+///         .try_acquire(&resources, redis_lock::DEFAULT_EXPIRATION)
+///         .await?
+///         .expect("never contended");
+///     // ..
+///     lock.release(&lock_id).await?;
+///     Ok(())
+/// }
+/// # tokio::runtime::Runtime::new().unwrap().block_on(transfer(&NullLock)).unwrap();
+/// ```
+///
+/// Code holding guards, like those of [`sync::MultiResourceLock`](crate::sync::MultiResourceLock),
+/// can use [`NullLock::try_lock`] and [`NullLock::lock`] instead.
+///
+/// Nothing is locked, so this must not be used where the exclusion is required for correctness.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NullLock;
+
+impl NullLock {
+    /// Acquires the lock immediately, returning a guard that releases nothing when dropped.
+    ///
+    /// # Errors
+    ///
+    /// Never, this returns a result to match `sync::MultiResourceLock::try_lock`.
+    #[inline]
+    pub fn try_lock(
+        &self,
+        resources: &[String],
+        _expiration: Duration,
+    ) -> RedisResult<Option<NullGuard>> {
+        Ok(Some(NullGuard {
+            lock_id: UuidV4.generate(),
+            resources: resources.to_vec(),
+        }))
+    }
+
+    /// Acquires the lock immediately, returning a guard that releases nothing when dropped.
+    ///
+    /// # Errors
+    ///
+    /// Never, this returns a result to match `sync::MultiResourceLock::lock`.
+    #[inline]
+    pub fn lock(
+        &self,
+        resources: &[String],
+        expiration: Duration,
+        _timeout: Duration,
+        _sleep: Duration,
+    ) -> RedisResult<Option<NullGuard>> {
+        self.try_lock(resources, expiration)
+    }
+}
+
+impl Lock for NullLock {
+    /// Acquires the lock immediately, returning a new lock identifier.
+    #[inline]
+    fn try_acquire(
+        &self,
+        _resources: &[String],
+        _expiration: Duration,
+    ) -> impl Future<Output = RedisResult<Option<String>>> + Send {
+        std::future::ready(Ok(Some(UuidV4.generate())))
+    }
+
+    /// Acquires the lock immediately, returning a new lock identifier.
+    #[inline]
+    fn acquire(
+        &self,
+        resources: &[String],
+        expiration: Duration,
+        _timeout: Duration,
+        _sleep: Duration,
+    ) -> impl Future<Output = RedisResult<Option<String>>> + Send {
+        self.try_acquire(resources, expiration)
+    }
+
+    /// Does nothing, returning that no resources were released as none were locked.
+    #[inline]
+    fn release(&self, _lock_id: &str) -> impl Future<Output = RedisResult<usize>> + Send {
+        std::future::ready(Ok(0))
+    }
+}
+
+/// A guard of a [`NullLock`], holding nothing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NullGuard {
+    /// The lock identifier.
+    lock_id: String,
+    /// The resources the lock was acquired on.
+    resources: Vec<String>,
+}
+
+impl NullGuard {
+    /// Returns the identifier of the lock.
+    #[inline]
+    #[must_use]
+    pub fn lock_id(&self) -> &str {
+        &self.lock_id
+    }
+
+    /// Returns the resources the lock was acquired on.
+    #[inline]
+    #[must_use]
+    pub fn resources(&self) -> &[String] {
+        &self.resources
+    }
+
+    /// Returns `None`, a [`NullLock`] stores no metadata.
+    #[inline]
+    #[must_use]
+    pub fn metadata(&self) -> Option<&str> {
+        None
+    }
+
+    /// Returns `true`, a [`NullLock`] never expires.
+    ///
+    /// # Errors
+    ///
+    /// Never, this returns a result to match `sync::MultiResourceGuard::is_valid`.
+    #[inline]
+    pub fn is_valid(&self) -> RedisResult<bool> {
+        Ok(true)
+    }
+
+    /// Consumes the guard, returning the identifier of the lock.
+    #[inline]
+    #[must_use]
+    pub fn into_lock_id(self) -> String {
+        self.lock_id
+    }
+
+    /// Consumes the guard, releasing nothing.
+    ///
+    /// # Errors
+    ///
+    /// Never, this returns a result to match `sync::MultiResourceGuard::release`.
+    #[inline]
+    pub fn release(self) -> RedisResult<ReleaseOutcome> {
+        Ok(ReleaseOutcome::Released(0))
+    }
+}
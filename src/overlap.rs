@@ -0,0 +1,60 @@
+/// Returns whether a lock on `requested` is blocked by a lock held on `held`, i.e. whether they
+/// share a resource.
+///
+/// This mirrors the check the Lua library runs when acquiring a lock, so a lock on `[a, b]`
+/// blocks `[a]` and `[b, c]` but not `[c]`:
+///
+/// ```
+/// # use redis_lock::overlap::overlaps;
+/// let held = [String::from("a"), String::from("b")];
+/// assert!(overlaps(&held, &[String::from("a")]));
+/// assert!(overlaps(&held, &[String::from("b"), String::from("c")]));
+/// assert!(!overlaps(&held, &[String::from("c")]));
+/// ```
+///
+/// Resources are compared as given, so locks using different
+/// [`HashTag`](crate::hash_tag::HashTag)s or key prefixes cannot be compared.
+#[inline]
+#[must_use]
+pub fn overlaps(held: &[String], requested: &[String]) -> bool {
+    requested.iter().any(|resource| held.contains(resource))
+}
+
+/// Returns which of the `requested` resource sets could be granted while the `held` resource sets
+/// are locked, in order, as if each were passed to
+/// [`MultiResourceLock::try_acquire`](crate::MultiResourceLock::try_acquire) in turn.
+///
+/// A granted set is held by the sets requested after it, so of two overlapping requests only the
+/// first is granted:
+///
+/// ```
+/// # use redis_lock::overlap::plan;
+/// let held = [vec![String::from("a")]];
+/// let requested = [
+///     vec![String::from("a"), String::from("b")],
+///     vec![String::from("b"), String::from("c")],
+///     vec![String::from("c")],
+/// ];
+/// assert_eq!(plan(&held, &requested), [false, true, false]);
+/// ```
+///
+/// An empty set is never granted, as acquiring a lock on no resources errors.
+///
+/// This ignores expiration and waiters queued for the resources, see
+/// [`MultiResourceLock::with_fair_queue`](crate::MultiResourceLock::with_fair_queue), which may
+/// still block a set that does not overlap any held set.
+#[inline]
+#[must_use]
+pub fn plan(held: &[Vec<String>], requested: &[Vec<String>]) -> Vec<bool> {
+    let mut locked = held.iter().flatten().cloned().collect::<Vec<_>>();
+    requested
+        .iter()
+        .map(|resources| {
+            let granted = !resources.is_empty() && !overlaps(&locked, resources);
+            if granted {
+                locked.extend_from_slice(resources);
+            }
+            granted
+        })
+        .collect()
+}
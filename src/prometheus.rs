@@ -0,0 +1,161 @@
+use crate::metrics::Metrics;
+use ::prometheus::core::Collector as _;
+use ::prometheus::{
+    HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, Opts, Registry, TextEncoder,
+};
+use std::fmt;
+use std::time::Duration;
+
+/// The upper bounds in seconds of the buckets of `redis_lock_acquire_wait_seconds`, the default
+/// buckets of the Prometheus client libraries.
+pub const WAIT_BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// The most resources of each value of the `resources` label, a lock of more than the last is
+/// labelled `+Inf`.
+pub const RESOURCE_BUCKETS: [usize; 4] = [1, 4, 16, 64];
+
+/// [`Metrics`] exported with the [`prometheus`](::prometheus) crate.
+///
+/// | Metric | Type | Labels | Description |
+/// |---|---|---|---|
+/// | `redis_lock_acquire_total` | counter | `resources` | The number of locks acquired. |
+/// | `redis_lock_acquire_wait_seconds` | histogram | `resources` | The time spent waiting to acquire locks, see [`WAIT_BUCKETS`]. |
+/// | `redis_lock_timeouts_total` | counter | | The number of attempts to acquire a lock that timed out. |
+/// | `redis_lock_active` | gauge | | The number of locks acquired and not yet released. |
+///
+/// The `resources` label buckets the number of resources of a lock by [`RESOURCE_BUCKETS`], like
+/// the `le` label of a histogram, e.g. `4` for a lock on 2 to 4 resources.
+///
+/// The metrics are added to a Prometheus registry with [`PrometheusMetrics::register`], or
+/// rendered alone in the Prometheus text format with [`Display`](fmt::Display):
+///
+/// ```
+/// # use redis_lock::metrics::Metrics;
+/// # use redis_lock::prometheus::PrometheusMetrics;
+/// # use std::sync::Arc;
+/// # use std::time::Duration;
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let metrics = Arc::new(PrometheusMetrics::new()?);
+/// // metrics.register(prometheus::default_registry())?;
+/// // let lock = MultiResourceLock::new(client)?.with_metrics(metrics.clone());
+/// # metrics.on_acquired_resources(&[String::from("a")], Duration::from_millis(20));
+/// let body = metrics.to_string();
+/// assert!(body.contains("redis_lock_acquire_total{resources=\"1\"} 1\n"));
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct PrometheusMetrics {
+    /// The number of locks acquired.
+    acquired: IntCounterVec,
+    /// The time spent waiting to acquire locks.
+    wait: HistogramVec,
+    /// The number of acquisitions that timed out.
+    timeouts: IntCounter,
+    /// The number of locks acquired and not yet released.
+    active: IntGauge,
+}
+
+impl PrometheusMetrics {
+    /// Create metrics with every count at zero.
+    ///
+    /// # Errors
+    ///
+    /// When the metrics cannot be created, which should not happen.
+    #[inline]
+    pub fn new() -> ::prometheus::Result<Self> {
+        Ok(Self {
+            acquired: IntCounterVec::new(
+                Opts::new("redis_lock_acquire_total", "The number of locks acquired."),
+                &["resources"],
+            )?,
+            wait: HistogramVec::new(
+                HistogramOpts::new(
+                    "redis_lock_acquire_wait_seconds",
+                    "The time spent waiting to acquire locks.",
+                )
+                .buckets(WAIT_BUCKETS.to_vec()),
+                &["resources"],
+            )?,
+            timeouts: IntCounter::new(
+                "redis_lock_timeouts_total",
+                "The number of attempts to acquire a lock that timed out.",
+            )?,
+            active: IntGauge::new(
+                "redis_lock_active",
+                "The number of locks acquired and not yet released.",
+            )?,
+        })
+    }
+
+    /// Registers the metrics with `registry`, e.g.
+    /// [`prometheus::default_registry`](::prometheus::default_registry).
+    ///
+    /// # Errors
+    ///
+    /// When `registry` already has metrics of the same names.
+    #[inline]
+    pub fn register(&self, registry: &Registry) -> ::prometheus::Result<()> {
+        registry.register(Box::new(self.acquired.clone()))?;
+        registry.register(Box::new(self.wait.clone()))?;
+        registry.register(Box::new(self.timeouts.clone()))?;
+        registry.register(Box::new(self.active.clone()))
+    }
+}
+
+/// The value of the `resources` label of a lock on `count` resources, see [`RESOURCE_BUCKETS`].
+fn resource_bucket(count: usize) -> String {
+    RESOURCE_BUCKETS
+        .iter()
+        .find(|bound| count <= **bound)
+        .map_or_else(|| String::from("+Inf"), ToString::to_string)
+}
+
+impl Metrics for PrometheusMetrics {
+    #[inline]
+    fn on_acquired_resources(&self, resources: &[String], wait: Duration) {
+        let bucket = resource_bucket(resources.len());
+        self.acquired.with_label_values(&[&bucket]).inc();
+        self.wait
+            .with_label_values(&[&bucket])
+            .observe(wait.as_secs_f64());
+        self.active.inc();
+    }
+
+    #[inline]
+    fn on_timeout(&self) {
+        self.timeouts.inc();
+    }
+
+    #[inline]
+    fn on_release(&self) {
+        self.active.dec();
+    }
+
+    #[inline]
+    fn on_release_expired(&self) {
+        self.active.dec();
+    }
+}
+
+impl fmt::Display for PrometheusMetrics {
+    /// Renders the metrics in the Prometheus text format.
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Sorted by name, as a registry gathers them.
+        let families = [
+            self.acquired.collect(),
+            self.wait.collect(),
+            self.active.collect(),
+            self.timeouts.collect(),
+        ]
+        .concat();
+        let mut rendered = String::new();
+        TextEncoder::new()
+            .encode_utf8(&families, &mut rendered)
+            .map_err(|_err| fmt::Error)?; // `fmt::Error` carries no details
+        f.write_str(&rendered)
+    }
+}
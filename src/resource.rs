@@ -0,0 +1,141 @@
+use std::fmt;
+
+/// A resource owned by a namespace, see
+/// [`MultiResourceLock::try_acquire_resources`](crate::MultiResourceLock::try_acquire_resources).
+///
+/// A resource is locked under the name `<namespace>:<name>` with any `\` or `:` in the namespace
+/// escaped with `\`, so resources of the same name in different namespaces never collide and one
+/// lock can span several namespaces.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Resource {
+    /// The namespace owning the resource.
+    pub namespace: String,
+    /// The name of the resource within its namespace.
+    pub name: String,
+}
+
+impl Resource {
+    /// Create the resource `name` in `namespace`.
+    #[inline]
+    #[must_use]
+    pub fn new(namespace: String, name: String) -> Self {
+        Resource { namespace, name }
+    }
+
+    /// Returns the name the resource is locked under.
+    #[inline]
+    #[must_use]
+    pub fn key(&self) -> String {
+        self.to_string()
+    }
+
+    /// Returns the names `resources` are locked under.
+    #[inline]
+    #[must_use]
+    pub fn keys(resources: &[Resource]) -> Vec<String> {
+        resources.iter().map(Resource::key).collect()
+    }
+
+    /// Parses the name a resource is locked under, `None` when it has no namespace.
+    #[inline]
+    #[must_use]
+    pub fn parse(key: &str) -> Option<Self> {
+        let mut namespace = String::new();
+        let mut chars = key.char_indices();
+        while let Some((index, char)) = chars.next() {
+            match char {
+                '\\' => namespace.push(chars.next()?.1),
+                ':' => {
+                    return Some(Resource {
+                        namespace,
+                        name: key.get(index.checked_add(1)?..)?.to_owned(),
+                    })
+                }
+                _ => namespace.push(char),
+            }
+        }
+        None
+    }
+}
+
+impl fmt::Display for Resource {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for char in self.namespace.chars() {
+            if matches!(char, '\\' | ':') {
+                f.write_str("\\")?;
+            }
+            write!(f, "{char}")?;
+        }
+        write!(f, ":{}", self.name)
+    }
+}
+
+/// Types locked as resources, e.g. domain identifiers, see
+/// [`MultiResourceLock::try_acquire_keys`](crate::MultiResourceLock::try_acquire_keys).
+///
+/// The key a value is locked under must be the same in every process and across restarts and
+/// upgrades, or holders will not exclude each other. So derive it from stable data rather than
+/// e.g. [`Hash`](std::hash::Hash), whose output is not guaranteed to be stable, and include a
+/// namespace so different types do not collide, e.g. with a [`Resource`]:
+///
+/// ```
+/// # use redis_lock::resource::{Resource, ResourceKey};
+/// struct AccountId(u64);
+/// impl ResourceKey for AccountId {
+///     fn resource_key(&self) -> String {
+///         Resource::new(String::from("account"), self.0.to_string()).key()
+///     }
+/// }
+/// assert_eq!(AccountId(7).resource_key(), "account:7");
+/// ```
+///
+/// The keys of the implementations in this crate are stable.
+pub trait ResourceKey {
+    /// Returns the name the value is locked under.
+    fn resource_key(&self) -> String;
+}
+
+impl ResourceKey for Resource {
+    #[inline]
+    fn resource_key(&self) -> String {
+        self.key()
+    }
+}
+
+impl ResourceKey for str {
+    #[inline]
+    fn resource_key(&self) -> String {
+        self.to_owned()
+    }
+}
+
+impl ResourceKey for String {
+    #[inline]
+    fn resource_key(&self) -> String {
+        self.clone()
+    }
+}
+
+impl<T: ResourceKey + ?Sized> ResourceKey for &T {
+    #[inline]
+    fn resource_key(&self) -> String {
+        (**self).resource_key()
+    }
+}
+
+/// Implements [`ResourceKey`] for integers as their decimal representation.
+macro_rules! integer_keys {
+    ($($integer:ty),*) => {
+        $(
+            impl ResourceKey for $integer {
+                #[inline]
+                fn resource_key(&self) -> String {
+                    self.to_string()
+                }
+            }
+        )*
+    };
+}
+
+integer_keys!(u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize);
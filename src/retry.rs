@@ -0,0 +1,165 @@
+use rand::Rng as _;
+use std::time::Duration;
+
+/// Decides how long [`MultiResourceLock::acquire_with_retry`](crate::MultiResourceLock::acquire_with_retry)
+/// waits between attempts to acquire the lock, and when it gives up.
+pub trait RetryStrategy: Send {
+    /// Returns how long to wait after attempt number `attempt` (starting at `1`) should it fail,
+    /// or `None` to give up after it.
+    ///
+    /// This is called as each attempt is made, `elapsed` after the first attempt.
+    fn next_delay(&mut self, attempt: u32, elapsed: Duration) -> Option<Duration>;
+
+    /// Returns the longest time to wait in total, attempts are not made after it and each
+    /// attempt is bounded by the time remaining. By default this is `None`, leaving
+    /// [`RetryStrategy::next_delay`] to decide when to give up.
+    #[inline]
+    fn timeout(&self) -> Option<Duration> {
+        None
+    }
+}
+
+/// Waits the same delay between every attempt, giving up after a timeout.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Constant {
+    /// The delay between attempts.
+    delay: Duration,
+    /// How long to wait in total.
+    timeout: Duration,
+}
+
+impl Constant {
+    /// Create a strategy waiting `delay` between attempts for up to `timeout` in total.
+    #[inline]
+    #[must_use]
+    pub fn new(delay: Duration, timeout: Duration) -> Self {
+        Constant { delay, timeout }
+    }
+}
+
+impl RetryStrategy for Constant {
+    #[inline]
+    fn next_delay(&mut self, _attempt: u32, elapsed: Duration) -> Option<Duration> {
+        (elapsed <= self.timeout).then_some(self.delay)
+    }
+
+    #[inline]
+    fn timeout(&self) -> Option<Duration> {
+        Some(self.timeout)
+    }
+}
+
+/// Waits the same delay between a fixed number of attempts, regardless of how long they take.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Attempts {
+    /// The number of attempts to make.
+    attempts: u32,
+    /// The delay between attempts.
+    delay: Duration,
+}
+
+impl Attempts {
+    /// Create a strategy making up to `attempts` attempts, at least one, waiting `delay` between
+    /// them.
+    #[inline]
+    #[must_use]
+    pub fn new(attempts: u32, delay: Duration) -> Self {
+        Attempts { attempts, delay }
+    }
+}
+
+impl RetryStrategy for Attempts {
+    #[inline]
+    fn next_delay(&mut self, attempt: u32, _elapsed: Duration) -> Option<Duration> {
+        (attempt < self.attempts).then_some(self.delay)
+    }
+}
+
+/// Doubles the delay after every attempt up to a maximum, giving up after a timeout.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Exponential {
+    /// The delay after the first attempt.
+    initial: Duration,
+    /// The longest delay between attempts.
+    max: Duration,
+    /// How long to wait in total.
+    timeout: Duration,
+}
+
+impl Exponential {
+    /// Create a strategy waiting `initial` after the first attempt, doubling up to `max`, for up
+    /// to `timeout` in total.
+    #[inline]
+    #[must_use]
+    pub fn new(initial: Duration, max: Duration, timeout: Duration) -> Self {
+        Exponential {
+            initial,
+            max,
+            timeout,
+        }
+    }
+}
+
+impl RetryStrategy for Exponential {
+    #[inline]
+    fn next_delay(&mut self, attempt: u32, elapsed: Duration) -> Option<Duration> {
+        let factor = 2u32.saturating_pow(attempt.saturating_sub(1));
+        (elapsed <= self.timeout).then(|| self.initial.saturating_mul(factor).min(self.max))
+    }
+
+    #[inline]
+    fn timeout(&self) -> Option<Duration> {
+        Some(self.timeout)
+    }
+}
+
+/// Waits a random delay between a base and three times the previous delay, up to a maximum,
+/// giving up after a timeout.
+///
+/// This spreads out waiters that started together so they do not retry in lockstep, see
+/// <https://aws.amazon.com/blogs/architecture/exponential-backoff-and-jitter/>.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecorrelatedJitter {
+    /// The shortest delay between attempts.
+    base: Duration,
+    /// The longest delay between attempts.
+    max: Duration,
+    /// How long to wait in total.
+    timeout: Duration,
+    /// The previous delay.
+    previous: Duration,
+}
+
+impl DecorrelatedJitter {
+    /// Create a strategy waiting between `base` and `max` between attempts for up to `timeout`
+    /// in total.
+    #[inline]
+    #[must_use]
+    pub fn new(base: Duration, max: Duration, timeout: Duration) -> Self {
+        DecorrelatedJitter {
+            base,
+            max,
+            timeout,
+            previous: base,
+        }
+    }
+}
+
+impl RetryStrategy for DecorrelatedJitter {
+    #[inline]
+    fn next_delay(&mut self, _attempt: u32, elapsed: Duration) -> Option<Duration> {
+        if elapsed > self.timeout {
+            return None;
+        }
+        let upper = self.previous.saturating_mul(3).max(self.base);
+        self.previous = rand::thread_rng()
+            .gen_range(self.base..=upper)
+            .min(self.max);
+        Some(self.previous)
+    }
+
+    #[inline]
+    fn timeout(&self) -> Option<Duration> {
+        Some(self.timeout)
+    }
+}
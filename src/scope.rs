@@ -0,0 +1,117 @@
+use super::connection::ConnectionProvider;
+use super::MultiResourceLock;
+use redis::{Client, RedisResult};
+use std::time::Duration;
+
+/// Locks acquired at different points of a unit of work, released together in reverse order, see
+/// [`MultiResourceLock::scope`].
+///
+/// Since locks cannot be released when dropped in an async context,
+/// [`LockScope::release_all`] must be called once the work is done, including on early returns
+/// and errors. Locks left in a dropped scope stay held until they expire, which is logged as a
+/// warning when the `tracing` feature is enabled.
+#[derive(Debug)]
+pub struct LockScope<'a, C = Client> {
+    /// The lock instance.
+    lock: &'a MultiResourceLock<C>,
+    /// The identifiers of the held locks, in the order they were acquired.
+    lock_ids: Vec<String>,
+}
+
+impl<'a, C: ConnectionProvider> LockScope<'a, C> {
+    /// Create an empty scope acquiring locks with `lock`.
+    pub(crate) fn new(lock: &'a MultiResourceLock<C>) -> Self {
+        LockScope {
+            lock,
+            lock_ids: Vec::new(),
+        }
+    }
+
+    /// Returns the identifiers of the locks held by the scope, in the order they were acquired.
+    #[inline]
+    #[must_use]
+    pub fn lock_ids(&self) -> &[String] {
+        &self.lock_ids
+    }
+
+    /// Calls [`MultiResourceLock::try_acquire`], adding the acquired lock to the scope.
+    ///
+    /// # Errors
+    ///
+    /// When [`MultiResourceLock::try_acquire`] errors.
+    #[inline]
+    pub async fn try_acquire(
+        &mut self,
+        resources: &[String],
+        expiration: Duration,
+    ) -> RedisResult<Option<String>> {
+        let result = self.lock.try_acquire(resources, expiration).await?;
+        Ok(self.add(result))
+    }
+
+    /// Calls [`MultiResourceLock::acquire`], adding the acquired lock to the scope.
+    ///
+    /// # Errors
+    ///
+    /// When [`MultiResourceLock::acquire`] errors.
+    #[inline]
+    pub async fn acquire(
+        &mut self,
+        resources: &[String],
+        expiration: Duration,
+        timeout: Duration,
+        sleep: Duration,
+    ) -> RedisResult<Option<String>> {
+        let result = self
+            .lock
+            .acquire(resources, expiration, timeout, sleep)
+            .await?;
+        Ok(self.add(result))
+    }
+
+    /// Adds the lock `acquired`, when it was acquired, to the scope.
+    fn add(&mut self, acquired: Option<String>) -> Option<String> {
+        if let Some(lock_id) = &acquired {
+            self.lock_ids.push(lock_id.clone());
+        }
+        acquired
+    }
+
+    /// Releases every lock of the scope, the most recently acquired first.
+    ///
+    /// Every lock is released even when releasing an earlier one errors.
+    ///
+    /// Returns the number of resources released.
+    ///
+    /// # Errors
+    ///
+    /// The first error of [`MultiResourceLock::release`].
+    #[inline]
+    pub async fn release_all(mut self) -> RedisResult<usize> {
+        let mut released = 0usize;
+        let mut first_error = None;
+        while let Some(lock_id) = self.lock_ids.pop() {
+            match self.lock.release(&lock_id).await {
+                Ok(count) => released = released.saturating_add(count),
+                Err(err) => {
+                    first_error.get_or_insert(err);
+                }
+            }
+        }
+        first_error.map_or(Ok(released), Err)
+    }
+}
+
+#[cfg(feature = "tracing")]
+impl<C> Drop for LockScope<'_, C> {
+    /// Warns when the scope still holds locks, which are left held until they expire.
+    #[inline]
+    fn drop(&mut self) {
+        if !self.lock_ids.is_empty() {
+            tracing::warn!(
+                lock_ids = ?self.lock_ids,
+                "scope dropped without release_all, locks left held until they expire"
+            );
+        }
+    }
+}
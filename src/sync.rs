@@ -1,24 +1,130 @@
-use super::{DEFAULT_EXPIRATION, DEFAULT_SLEEP, DEFAULT_TIMEOUT};
-use redis::{Client, Connection, RedisResult};
+use super::condition::LuaCondition;
+use super::hash_tag::HashTag;
+use super::id::{IdGenerator, UuidV4};
+use super::keys::{DefaultKeys, KeyFormatter, LockKeys};
+use super::metrics::{LockStats, Metrics, NoopMetrics};
+use super::resource::{Resource, ResourceKey};
+use super::retry::{Attempts, Constant, RetryStrategy};
+use super::token::LockToken;
+use super::{
+    acquire_cmd, check_loaded, expiration_millis, fcall, guarded_exec_cmd, is_missing_function,
+    is_transient, jitter, library_name, load_outcome, poison_args, report_release,
+    report_release_error, short_expiration, ttls_from_reply, unique_resources, AcquireResult,
+    MoveOutcome, ReleaseOutcome, SetupOutcome, Waiter, DEFAULT_EXPIRATION, DEFAULT_POISON_TTL,
+    DEFAULT_PRIORITY, DEFAULT_SLEEP, DEFAULT_TIMEOUT, LIBRARY_CODE, RELEASE_RETRY_SLEEP,
+};
+#[cfg(feature = "tokio")]
+use super::{command_timeout, load_library as load_library_async};
+#[cfg(feature = "log")]
+use super::{log_attempt, log_release};
+use redis::{
+    Client, Cmd, Connection, ConnectionLike as _, ErrorKind, FromRedisValue, RedisError,
+    RedisResult, Value,
+};
+use std::borrow::Cow;
+use std::collections::HashMap;
 use std::error::Error;
-use std::time::Duration;
-use uuid::Uuid;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
 
 /// A distributed mutual exclusion lock backed by Redis.
 ///
 /// Supports exclusion based on multiple resources and partial overlaps.
 ///
 /// E.g. a lock on resources `["a", "b"]` will block a lock on `["a"]` or `["b", "c"]`.
+///
+/// Methods take `&self`, so the lock can be shared between threads without a `Mutex`, each
+/// concurrent call taking its own connection from a set of idle connections.
+#[expect(
+    clippy::struct_excessive_bools,
+    reason = "Each is an independent option set by its own builder method."
+)]
 pub struct MultiResourceLock {
-    /// The Redis connection.
-    conn: Connection,
+    /// The Redis client, used to open connections.
+    client: Client,
+    /// The idle connections, taken by each call and returned once it completes.
+    idle: Mutex<Vec<Connection>>,
+    /// The metrics hooks.
+    metrics: Arc<dyn Metrics>,
+    /// The fraction of the expiration randomly added to or subtracted from it.
+    expiration_jitter: f64,
+    /// Whether waiters queue to acquire the lock in order.
+    fair: bool,
+    /// Whether duplicate resources are an error rather than ignored.
+    strict_resources: bool,
+    /// Whether an expiration shorter than the wait to acquire the lock is an error rather than
+    /// logged.
+    strict_expiration: bool,
+    /// Whether the Lua library is loaded when a function is found to be missing.
+    auto_setup: bool,
+    /// Whether transient errors while waiting to acquire the lock are retried.
+    retry_transient: bool,
+    /// How many times releasing a lock is retried after a transient error.
+    release_retries: u32,
+    /// How long each Redis call may take, unbounded when `None`.
+    command_timeout: Option<Duration>,
+    /// How resources map to the names they are locked under.
+    hash_tag: HashTag,
+    /// Names the Redis keys locks are stored under.
+    key_formatter: Arc<LockKeys>,
+    /// Generates the identifiers of locks.
+    id_generator: Arc<dyn IdGenerator>,
+    /// How guards release the lock when dropped.
+    drop_policy: DropPolicy,
+    /// How long resources stay poisoned after a guard is dropped while panicking.
+    poison_ttl: Duration,
+    /// The target lock lifecycle events are logged to.
+    #[cfg(feature = "log")]
+    log_target: &'static str,
+}
+
+/// How a [`MultiResourceGuard`] releases the lock when it is dropped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DropPolicy {
+    /// Releases the lock on the lock's connection, blocking the dropping thread until Redis
+    /// replies.
+    ///
+    /// When dropped on a current-thread tokio runtime the lock is instead released from a task
+    /// spawned on it, as blocking its only thread would stall every other task. The lock may then
+    /// still be held briefly after the guard is dropped, or until it expires if the runtime shuts
+    /// down first.
+    #[default]
+    BlockOnHandle,
+    /// Releases the lock on a new connection from a detached thread, so dropping never blocks.
+    ///
+    /// The lock may still be held briefly after the guard is dropped.
+    SpawnDetached,
+    /// Leaves the lock to expire, logging it when the `tracing` feature is enabled.
+    LogAndSkip,
+    /// Does nothing, the lock is only released by [`MultiResourceGuard::release`] or
+    /// [`MultiResourceLock::release`].
+    ///
+    /// The caller is responsible for releasing every lock, locks that are not released stay
+    /// held until they expire.
+    Manual,
 }
 
 impl std::fmt::Debug for MultiResourceLock {
     #[inline]
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("MultiResourceLock")
-            .field("conn", &"..")
+            .field("client", &"..")
+            .field("idle", &"..")
+            .field("metrics", &"..")
+            .field("expiration_jitter", &"..")
+            .field("fair", &"..")
+            .field("strict_resources", &"..")
+            .field("strict_expiration", &"..")
+            .field("auto_setup", &"..")
+            .field("retry_transient", &"..")
+            .field("release_retries", &"..")
+            .field("command_timeout", &"..")
+            .field("hash_tag", &"..")
+            .field("key_formatter", &"..")
+            .field("id_generator", &"..")
+            .field("drop_policy", &"..")
+            .field("poison_ttl", &"..")
+            .field("log_target", &"..")
             .finish()
     }
 }
@@ -27,26 +133,41 @@ impl std::fmt::Debug for MultiResourceLock {
 ///
 /// This only needs to be done once per Redis instance, although re-doing it should be fine.
 ///
+/// See [`crate::setup`] for how the library is versioned and what it returns.
+///
 /// # Errors
 ///
 /// - When [`Client::get_connection`] errors.
 /// - When the Lua library functions cannot be loaded into Redis.
 #[inline]
-pub fn setup(client: &Client) -> Result<(), Box<dyn Error>> {
+pub fn setup(client: &Client) -> Result<SetupOutcome, Box<dyn Error>> {
     // Connect to Redis
     let mut con = client.get_connection()?;
+    Ok(load_library(&mut con)?)
+}
 
-    // Define your Lua library
-    let lua_library = include_str!("functions.lua");
+/// Loads the Lua library into Redis on `con`, unless this version is already loaded.
+fn load_library(con: &mut Connection) -> RedisResult<SetupOutcome> {
+    // Check whether this version of the Lua library is already loaded
+    let loaded: Vec<HashMap<String, Value>> = redis::cmd("FUNCTION")
+        .arg("LIST")
+        .arg("LIBRARYNAME")
+        .arg(library_name())
+        .arg("WITHCODE")
+        .query(con)?;
+    let outcome = load_outcome(&loaded)?;
+    if outcome == SetupOutcome::AlreadyCurrent {
+        return Ok(outcome);
+    }
 
     // Load the Lua library into Redis
     let _result: String = redis::cmd("FUNCTION")
         .arg("LOAD")
         .arg("REPLACE")
-        .arg(lua_library)
-        .query(&mut con)?;
+        .arg(LIBRARY_CODE)
+        .query(con)?;
 
-    Ok(())
+    Ok(outcome)
 }
 
 impl MultiResourceLock {
@@ -58,7 +179,242 @@ impl MultiResourceLock {
     #[inline]
     pub fn new(client: &Client) -> RedisResult<Self> {
         let conn = client.get_connection()?;
-        Ok(MultiResourceLock { conn })
+        Ok(MultiResourceLock {
+            client: client.clone(),
+            idle: Mutex::new(vec![conn]),
+            metrics: Arc::new(NoopMetrics),
+            expiration_jitter: 0.0,
+            fair: false,
+            strict_resources: false,
+            strict_expiration: false,
+            auto_setup: false,
+            retry_transient: false,
+            release_retries: 0,
+            command_timeout: None,
+            hash_tag: HashTag::Disabled,
+            key_formatter: LockKeys::new(Arc::new(DefaultKeys), true),
+            id_generator: Arc::new(UuidV4),
+            drop_policy: DropPolicy::default(),
+            poison_ttl: DEFAULT_POISON_TTL,
+            #[cfg(feature = "log")]
+            log_target: "redis_lock",
+        })
+    }
+
+    /// Sets the hooks called to export metrics, by default these are [`NoopMetrics`].
+    #[inline]
+    #[must_use]
+    pub fn with_metrics(mut self, metrics: Arc<dyn Metrics>) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    /// Randomly adds or subtracts up to `jitter` of the expiration each time the lock is
+    /// acquired, e.g. `0.1` for ±10%, by default this is `0.0`.
+    ///
+    /// This prevents locks acquired together with the same expiration from expiring together.
+    ///
+    /// # Panics
+    ///
+    /// When `jitter` is not within `0.0..=1.0`.
+    #[inline]
+    #[must_use]
+    pub fn with_expiration_jitter(mut self, jitter: f64) -> Self {
+        assert!(
+            (0.0f64..=1.0f64).contains(&jitter),
+            "expiration jitter must be within 0.0..=1.0"
+        );
+        self.expiration_jitter = jitter;
+        self
+    }
+
+    /// Sets whether waiters queue to acquire the lock in the order they started waiting, by
+    /// default this is `false` and waiters retry in no particular order.
+    ///
+    /// A fair waiter joins a queue for each resource and may only acquire the lock once it is at
+    /// the head of every queue, locks acquired without waiting also give way to queued waiters.
+    /// This prevents starvation under heavy contention at the cost of an extra key per waiter and
+    /// queue per contended resource, a few extra commands per attempt, and waiting behind a
+    /// waiter that died until it misses a few attempts.
+    #[inline]
+    #[must_use]
+    pub fn with_fair_queue(mut self, fair: bool) -> Self {
+        self.fair = fair;
+        self
+    }
+
+    /// Sets whether acquiring the lock on duplicate resources, e.g. `["a", "a", "b"]`, errors,
+    /// by default this is `false` and duplicates are ignored.
+    ///
+    /// This surfaces bugs in the code deriving the resources to lock.
+    #[inline]
+    #[must_use]
+    pub fn with_strict_resources(mut self, strict: bool) -> Self {
+        self.strict_resources = strict;
+        self
+    }
+
+    /// Sets whether waiting to acquire a lock with an expiration shorter than the sleep between
+    /// attempts or the timeout errors, by default this is `false` and it is logged when the
+    /// `tracing` feature is enabled.
+    ///
+    /// E.g. a `500ms` expiration with a `60s` timeout, where the lock is likely to expire before
+    /// the holder expects or before another waiter next attempts to acquire it. Locks that
+    /// never expire are always allowed.
+    #[inline]
+    #[must_use]
+    pub fn with_strict_expiration(mut self, strict: bool) -> Self {
+        self.strict_expiration = strict;
+        self
+    }
+
+    /// Sets whether a call to a Lua library function that is missing from the Redis instance
+    /// loads the library, as by [`setup`], and is retried once, by default this is `false`
+    /// and the call errors.
+    ///
+    /// This recovers from a fresh Redis instance coming up without the library loaded, or the
+    /// library being removed, e.g. by `FUNCTION FLUSH`, while locks are held, including when
+    /// guards release their locks.
+    #[inline]
+    #[must_use]
+    pub fn with_auto_setup(mut self, auto_setup: bool) -> Self {
+        self.auto_setup = auto_setup;
+        self
+    }
+
+    /// Sets whether transient errors, see [`is_transient`](crate::is_transient), while waiting
+    /// to acquire the lock are retried like a contended attempt, by default this is `false` and
+    /// they error.
+    ///
+    /// This rides out brief outages, e.g. a failover. Other errors, e.g. a missing function,
+    /// still error immediately. An attempt interrupted by the error may still have acquired the
+    /// lock, which is left to expire.
+    #[inline]
+    #[must_use]
+    pub fn with_retry_transient(mut self, retry_transient: bool) -> Self {
+        self.retry_transient = retry_transient;
+        self
+    }
+
+    /// Sets how many times releasing a lock, including when a guard is dropped, is retried after
+    /// a transient error, see [`is_transient`](crate::is_transient), by default this is `0` and
+    /// they error.
+    ///
+    /// A lock that fails to release blocks other holders until it expires, so a brief outage is
+    /// worth riding out. A release interrupted by the error may still have released the lock, in
+    /// which case the retry reports it as [`ReleaseOutcome::NotHeld`].
+    #[inline]
+    #[must_use]
+    pub fn with_release_retries(mut self, release_retries: u32) -> Self {
+        self.release_retries = release_retries;
+        self
+    }
+
+    /// Sets how long each Redis call, including connecting, may take before it errors, by default
+    /// calls are unbounded.
+    ///
+    /// This is separate from the timeout to acquire the lock, e.g. to wait 30 seconds for a
+    /// contended lock but fail within 2 seconds when Redis is unreachable. A call that times out
+    /// errors with [`RedisError::is_timeout`], so is retried when
+    /// [`MultiResourceLock::with_retry_transient`] is set.
+    ///
+    /// # Errors
+    ///
+    /// When the timeout cannot be set on the connection, e.g. when it is zero.
+    #[inline]
+    pub fn with_command_timeout(mut self, command_timeout: Duration) -> RedisResult<Self> {
+        for conn in self.idle.get_mut().unwrap_or_else(PoisonError::into_inner) {
+            conn.set_read_timeout(Some(command_timeout))?;
+            conn.set_write_timeout(Some(command_timeout))?;
+        }
+        self.command_timeout = Some(command_timeout);
+        Ok(self)
+    }
+
+    /// Sets how resources map to the names they are locked under, by default this is
+    /// [`HashTag::Disabled`].
+    ///
+    /// [`HashTag::Group`] keeps the lock keys of every resource in one hash slot, while
+    /// [`HashTag::Existing`] errors on resources of a lock whose keys hash to different slots.
+    /// Locking is still not supported on Redis Cluster, see [`HashTag`].
+    #[inline]
+    #[must_use]
+    pub fn with_hash_tag(mut self, hash_tag: HashTag) -> Self {
+        self.hash_tag = hash_tag;
+        self
+    }
+
+    /// Sets the names of the Redis keys locks are stored under, by default these are
+    /// [`DefaultKeys`].
+    ///
+    /// Every user of a Redis instance must use the same names.
+    #[inline]
+    #[must_use]
+    pub fn with_key_formatter(mut self, key_formatter: Arc<dyn KeyFormatter>) -> Self {
+        self.key_formatter = LockKeys::new(key_formatter, self.key_formatter.indexed());
+        self
+    }
+
+    /// Sets whether the `index:locks` and `index:resources` sets of held locks and locked
+    /// resources, and the `index:attempts` sets of contended attempts, are maintained, by default
+    /// this is `true`.
+    ///
+    /// Not maintaining them saves their writes on every acquisition and release, but
+    /// [`MultiResourceLock::stats`] then counts nothing, [`MultiResourceLock::waiter_count`]
+    /// counts only queued waiters, and releasing an expired lock reports
+    /// [`ReleaseOutcome::NotHeld`] rather than [`ReleaseOutcome::AlreadyExpired`]. Users of a
+    /// Redis instance that do maintain them may see locks acquired by users that do not as
+    /// expired.
+    #[inline]
+    #[must_use]
+    pub fn with_index(mut self, indexed: bool) -> Self {
+        self.key_formatter = LockKeys::new(Arc::clone(self.key_formatter.formatter()), indexed);
+        self
+    }
+
+    /// Sets how the identifiers of locks are generated, by default this is [`UuidV4`].
+    ///
+    /// E.g. to generate time sortable identifiers, or to embed the host in identifiers to find
+    /// the holder of a lock.
+    #[inline]
+    #[must_use]
+    pub fn with_id_generator(mut self, id_generator: Arc<dyn IdGenerator>) -> Self {
+        self.id_generator = id_generator;
+        self
+    }
+
+    /// Sets how guards release the lock when dropped, by default this is
+    /// [`DropPolicy::BlockOnHandle`].
+    #[inline]
+    #[must_use]
+    pub fn with_drop_policy(mut self, drop_policy: DropPolicy) -> Self {
+        self.drop_policy = drop_policy;
+        self
+    }
+
+    /// Sets the target lock lifecycle events are logged to, by default this is `redis_lock`.
+    ///
+    /// Attempts to acquire the lock, timeouts and releases are logged at debug level with the lock
+    /// identifier and the number of resources.
+    #[cfg(feature = "log")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "log")))]
+    #[inline]
+    #[must_use]
+    pub fn with_log_target(mut self, log_target: &'static str) -> Self {
+        self.log_target = log_target;
+        self
+    }
+
+    /// Sets how long resources stay poisoned after a guard releasing them is dropped while
+    /// panicking, by default this is [`DEFAULT_POISON_TTL`].
+    ///
+    /// Resources stay poisoned until cleared when it is [`INDEFINITE`](crate::INDEFINITE), see
+    /// [`MultiResourceLock::release_poisoned`].
+    #[inline]
+    #[must_use]
+    pub fn with_poison_ttl(mut self, poison_ttl: Duration) -> Self {
+        self.poison_ttl = poison_ttl;
+        self
     }
 
     /// Calls [`MultiResourceLock::acquire`] with [`DEFAULT_EXPIRATION`], [`DEFAULT_TIMEOUT`] and [`DEFAULT_SLEEP`].
@@ -67,7 +423,7 @@ impl MultiResourceLock {
     ///
     /// When [`MultiResourceLock::acquire`] errors.
     #[inline]
-    pub fn acquire_default(&mut self, resources: &[String]) -> RedisResult<Option<String>> {
+    pub fn acquire_default(&self, resources: &[String]) -> RedisResult<Option<String>> {
         self.acquire(
             resources,
             DEFAULT_EXPIRATION,
@@ -82,26 +438,280 @@ impl MultiResourceLock {
     ///
     /// Returns `None` when it times out.
     ///
+    /// The lock expires after `expiration`, or never when it is [`INDEFINITE`](crate::INDEFINITE).
+    ///
     /// # Errors
     ///
     /// When [`MultiResourceLock::try_acquire`] errors.
     #[inline]
     pub fn acquire(
-        &mut self,
+        &self,
+        resources: &[String],
+        expiration: Duration,
+        timeout: Duration,
+        sleep: Duration,
+    ) -> RedisResult<Option<String>> {
+        self.acquire_with_metadata(resources, expiration, timeout, sleep, None)
+    }
+
+    /// Attempts to acquire the lock blocking until the lock can be acquired, storing `metadata`
+    /// alongside it.
+    ///
+    /// Blocks up to `timeout` duration making attempts every `sleep` duration.
+    ///
+    /// Returns `None` when it times out.
+    ///
+    /// # Errors
+    ///
+    /// When [`MultiResourceLock::acquire_outcome`] errors.
+    #[inline]
+    pub fn acquire_with_metadata(
+        &self,
+        resources: &[String],
+        expiration: Duration,
+        timeout: Duration,
+        sleep: Duration,
+        metadata: Option<&str>,
+    ) -> RedisResult<Option<String>> {
+        Ok(self
+            .acquire_outcome(resources, expiration, timeout, sleep, metadata)?
+            .acquired())
+    }
+
+    /// Attempts to acquire the lock blocking until the lock can be acquired, storing `metadata`
+    /// alongside it.
+    ///
+    /// Blocks up to `timeout` duration making attempts every `sleep` duration.
+    ///
+    /// Returns [`AcquireResult::TimedOut`] when it times out.
+    ///
+    /// # Errors
+    ///
+    /// When [`MultiResourceLock::try_acquire_outcome`] errors.
+    #[inline]
+    pub fn acquire_outcome(
+        &self,
+        resources: &[String],
+        expiration: Duration,
+        timeout: Duration,
+        sleep: Duration,
+        metadata: Option<&str>,
+    ) -> RedisResult<AcquireResult<String>> {
+        let priority = self.fair.then_some(DEFAULT_PRIORITY);
+        let mut retry = Constant::new(sleep, timeout);
+        self.acquire_queued(resources, expiration, &mut retry, metadata, priority, None)
+    }
+
+    /// Attempts to acquire the lock blocking until the lock can be acquired, calling `on_wait`
+    /// with the number of attempts made and the time elapsed after each failed attempt.
+    ///
+    /// E.g. to log progress during long waits.
+    ///
+    /// Blocks up to `timeout` duration making attempts every `sleep` duration.
+    ///
+    /// Returns `None` when it times out.
+    ///
+    /// # Errors
+    ///
+    /// When [`MultiResourceLock::acquire_with_metadata`] would error.
+    #[inline]
+    pub fn acquire_with_progress<F>(
+        &self,
+        resources: &[String],
+        expiration: Duration,
+        timeout: Duration,
+        sleep: Duration,
+        mut on_wait: F,
+    ) -> RedisResult<Option<String>>
+    where
+        F: FnMut(u32, Duration) + Send,
+    {
+        let priority = self.fair.then_some(DEFAULT_PRIORITY);
+        let mut retry = Constant::new(sleep, timeout);
+        Ok(self
+            .acquire_queued(
+                resources,
+                expiration,
+                &mut retry,
+                None,
+                priority,
+                Some(&mut on_wait),
+            )?
+            .acquired())
+    }
+
+    /// Attempts to acquire the lock blocking until the lock can be acquired, waiting between
+    /// attempts as decided by `retry`.
+    ///
+    /// Returns `None` when `retry` gives up.
+    ///
+    /// # Errors
+    ///
+    /// When [`MultiResourceLock::acquire_with_metadata`] would error.
+    #[inline]
+    pub fn acquire_with_retry(
+        &self,
+        resources: &[String],
+        expiration: Duration,
+        retry: &mut dyn RetryStrategy,
+    ) -> RedisResult<Option<String>> {
+        let priority = self.fair.then_some(DEFAULT_PRIORITY);
+        Ok(self
+            .acquire_queued(resources, expiration, retry, None, priority, None)?
+            .acquired())
+    }
+
+    /// Attempts to acquire the lock blocking until the lock can be acquired, queueing with
+    /// `priority` regardless of [`MultiResourceLock::with_fair_queue`].
+    ///
+    /// Among waiters on a resource those with a higher priority acquire the lock first, then
+    /// those that started waiting first. Waiters with the same priority that started waiting in
+    /// the same millisecond acquire it in an arbitrary order. Fair waiters have
+    /// [`DEFAULT_PRIORITY`].
+    ///
+    /// Blocks up to `timeout` duration making attempts every `sleep` duration.
+    ///
+    /// Returns `None` when it times out.
+    ///
+    /// # Errors
+    ///
+    /// When [`MultiResourceLock::acquire_with_metadata`] would error.
+    #[inline]
+    pub fn acquire_with_priority(
+        &self,
         resources: &[String],
         expiration: Duration,
         timeout: Duration,
         sleep: Duration,
+        priority: i8,
     ) -> RedisResult<Option<String>> {
-        let now = std::time::Instant::now();
+        let mut retry = Constant::new(sleep, timeout);
+        Ok(self
+            .acquire_queued(
+                resources,
+                expiration,
+                &mut retry,
+                None,
+                Some(priority),
+                None,
+            )?
+            .acquired())
+    }
+
+    /// Attempts to acquire the lock blocking until the lock can be acquired, waiting between
+    /// attempts as decided by `retry`, queueing with `priority` and calling `on_wait` after each
+    /// failed attempt when they are `Some`.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            name = "acquire_with_metadata",
+            level = "debug",
+            skip(self, retry, on_wait),
+            err
+        )
+    )]
+    fn acquire_queued(
+        &self,
+        resources: &[String],
+        expiration: Duration,
+        retry: &mut dyn RetryStrategy,
+        metadata: Option<&str>,
+        priority: Option<i8>,
+        mut on_wait: Option<&mut (dyn FnMut(u32, Duration) + Send)>,
+    ) -> RedisResult<AcquireResult<String>> {
+        let now = Instant::now();
+        let timeout = retry.timeout();
+        let mut queue = priority.map(|level| Waiter::new(self.id_generator.generate(), level));
+        let mut attempt = 0u32;
         loop {
-            if now.elapsed() > timeout {
-                return Ok(None);
+            let elapsed = now.elapsed();
+            if timeout.is_some_and(|limit| elapsed > limit) {
+                #[cfg(feature = "tracing")]
+                tracing::debug!(?elapsed, outcome = "timeout");
+                return self.give_up(resources, queue.as_ref());
+            }
+            attempt = attempt.saturating_add(1);
+            let delay = retry.next_delay(attempt, elapsed);
+            if attempt == 1 {
+                if let Some(reason) = short_expiration(expiration, timeout, delay) {
+                    if self.strict_expiration {
+                        return Err(RedisError::from((ErrorKind::ClientError, reason)));
+                    }
+                    #[cfg(feature = "tracing")]
+                    tracing::warn!(?expiration, ?timeout, sleep = ?delay, reason);
+                }
+            }
+            if let Some(waiter) = &mut queue {
+                waiter.wait(delay.unwrap_or_default());
+            }
+            match self.attempt_acquire(resources, expiration, metadata, now, queue.as_ref(), None) {
+                Ok(AcquireResult::Acquired(res)) => {
+                    #[cfg(feature = "tracing")]
+                    tracing::debug!(lock_id = res, elapsed = ?now.elapsed(), outcome = "acquired");
+                    break Ok(AcquireResult::Acquired(res));
+                }
+                Err(err) if !(self.retry_transient && is_transient(&err)) => {
+                    self.leave_queue(resources, queue.as_ref());
+                    return Err(err);
+                }
+                #[cfg_attr(
+                    not(feature = "tracing"),
+                    expect(unused_variables, reason = "Only traced.")
+                )]
+                Err(err) => {
+                    #[cfg(feature = "tracing")]
+                    tracing::debug!(elapsed = ?now.elapsed(), %err, outcome = "transient error");
+                }
+                Ok(_) => {}
             }
-            match self.try_acquire(resources, expiration)? {
-                Some(res) => break Ok(Some(res)),
-                None => std::thread::sleep(sleep),
+            let Some(wait) = delay else {
+                #[cfg(feature = "tracing")]
+                tracing::debug!(?elapsed, outcome = "gave up");
+                return self.give_up(resources, queue.as_ref());
+            };
+            #[cfg(feature = "tracing")]
+            tracing::trace!(elapsed = ?now.elapsed(), outcome = "contended");
+            if let Some(callback) = on_wait.as_mut() {
+                callback(attempt, now.elapsed());
             }
+            std::thread::sleep(wait);
+        }
+    }
+
+    /// Reports giving up on acquiring the lock, leaving the queue when `queue` is `Some`.
+    fn give_up(
+        &self,
+        resources: &[String],
+        queue: Option<&Waiter>,
+    ) -> RedisResult<AcquireResult<String>> {
+        self.metrics.on_timeout();
+        #[cfg(feature = "log")]
+        log::debug!(
+            target: self.log_target,
+            "timed out acquiring lock on {} resources",
+            resources.len()
+        );
+        if let Some(waiter) = queue {
+            self.dequeue(&waiter.lock_id, resources)?;
+        }
+        Ok(AcquireResult::TimedOut)
+    }
+
+    /// Leaves the queue when `queue` is `Some` after an attempt to acquire the lock errored,
+    /// logging rather than returning a failure to leave as the attempt's error is returned.
+    fn leave_queue(&self, resources: &[String], queue: Option<&Waiter>) {
+        let Some(waiter) = queue else {
+            return;
+        };
+        if let Err(_err) = self.dequeue(&waiter.lock_id, resources) {
+            #[cfg(feature = "tracing")]
+            tracing::warn!(%_err, "failed to leave the queue, left until the waiter expires");
+            #[cfg(feature = "log")]
+            log::warn!(
+                target: self.log_target,
+                "failed to leave the queue, left until waiter {} expires: {_err}",
+                waiter.lock_id
+            );
         }
     }
 
@@ -111,148 +721,1982 @@ impl MultiResourceLock {
     ///
     /// When [`MultiResourceLock::try_acquire`] errors.
     #[inline]
-    pub fn try_acquire_default(&mut self, resources: &[String]) -> RedisResult<Option<String>> {
+    pub fn try_acquire_default(&self, resources: &[String]) -> RedisResult<Option<String>> {
         self.try_acquire(resources, DEFAULT_EXPIRATION)
     }
 
     /// Attempts to acquire the lock returning immediately if it cannot be immediately acquired.
     ///
+    /// The lock expires after `expiration`, or never when it is [`INDEFINITE`](crate::INDEFINITE).
+    ///
     /// # Errors
     ///
-    /// - When the `acquire_lock` function is missing from the Redis instance.
+    /// When [`MultiResourceLock::try_acquire_with_metadata`] errors.
     #[inline]
     pub fn try_acquire(
-        &mut self,
+        &self,
         resources: &[String],
         expiration: Duration,
     ) -> RedisResult<Option<String>> {
-        let lock_id = Uuid::new_v4().to_string();
-        let mut args = vec![lock_id.clone(), expiration.as_millis().to_string()];
-        args.extend(resources.iter().cloned());
-
-        let result: Option<String> = redis::cmd("FCALL")
-            .arg("acquire_lock")
-            .arg(0i32)
-            .arg(&args)
-            .query(&mut self.conn)?;
-
-        Ok(result)
+        self.try_acquire_with_metadata(resources, expiration, None)
     }
 
-    /// Releases a held lock.
+    /// Attempts to acquire the lock on resources across namespaces returning immediately if it
+    /// cannot be immediately acquired.
+    ///
+    /// The resources are locked under their [`Resource::key`].
     ///
     /// # Errors
     ///
-    /// - When the `release_lock` function is missing from the Redis instance.
-    /// - When `lock_id` does not refer to a held lock.
+    /// When [`MultiResourceLock::try_acquire`] errors.
     #[inline]
-    pub fn release(&mut self, lock_id: &str) -> RedisResult<usize> {
-        let result: usize = redis::cmd("FCALL")
-            .arg("release_lock")
-            .arg(0i32)
-            .arg(lock_id)
-            .query(&mut self.conn)?;
+    pub fn try_acquire_resources(
+        &self,
+        resources: &[Resource],
+        expiration: Duration,
+    ) -> RedisResult<Option<String>> {
+        self.try_acquire(&Resource::keys(resources), expiration)
+    }
 
-        Ok(result)
+    /// Attempts to acquire the lock on `keys`, e.g. domain identifiers, returning immediately if
+    /// it cannot be immediately acquired.
+    ///
+    /// Each key is locked under its [`ResourceKey::resource_key`].
+    ///
+    /// # Errors
+    ///
+    /// When [`MultiResourceLock::try_acquire`] errors.
+    #[inline]
+    pub fn try_acquire_keys<K: ResourceKey>(
+        &self,
+        keys: &[K],
+        expiration: Duration,
+    ) -> RedisResult<Option<String>> {
+        let resources = keys
+            .iter()
+            .map(ResourceKey::resource_key)
+            .collect::<Vec<_>>();
+        self.try_acquire(&resources, expiration)
     }
 
-    /// Calls [`MultiResourceLock::try_lock`] with [`DEFAULT_EXPIRATION`].
+    /// Attempts to acquire the lock returning immediately if it cannot be immediately acquired,
+    /// storing `metadata` alongside it.
+    ///
+    /// The metadata is written in the same transaction as the lock so it never outlives it, it
+    /// can be read back with [`MultiResourceLock::lock_metadata`] or
+    /// [`MultiResourceLock::resource_metadata`].
     ///
     /// # Errors
     ///
-    /// When [`MultiResourceLock::try_lock`] errors.
+    /// When [`MultiResourceLock::try_acquire_outcome`] errors.
     #[inline]
-    pub fn try_lock_default(
-        &mut self,
+    pub fn try_acquire_with_metadata(
+        &self,
         resources: &[String],
-    ) -> RedisResult<Option<MultiResourceGuard>> {
-        self.try_lock(resources, DEFAULT_EXPIRATION)
+        expiration: Duration,
+        metadata: Option<&str>,
+    ) -> RedisResult<Option<String>> {
+        Ok(self
+            .try_acquire_outcome(resources, expiration, metadata)?
+            .acquired())
     }
 
-    /// Attempts to acquire the lock returning immediately if it cannot be immediately acquired.
+    /// Attempts to acquire the lock returning immediately if it cannot be immediately acquired,
+    /// storing `metadata` alongside it.
     ///
-    /// Wraps the result in a guard that releases the lock when dropped.
+    /// Returns [`AcquireResult::Contended`] with the resources held by other locks, or waited on
+    /// by queued waiters, when it cannot be acquired.
     ///
     /// # Errors
     ///
-    /// When [`MultiResourceLock::try_acquire`] errors.
+    /// - When the `acquire_lock` function is missing from the Redis instance.
+    /// - When `resources` contain duplicates and [`MultiResourceLock::with_strict_resources`] is
+    ///   set.
     #[inline]
-    pub fn try_lock(
-        &mut self,
+    pub fn try_acquire_outcome(
+        &self,
         resources: &[String],
         expiration: Duration,
-    ) -> RedisResult<Option<MultiResourceGuard<'_>>> {
-        self.try_acquire(resources, expiration).map(|result| {
-            result.map(|lock_id| MultiResourceGuard {
-                lock: self,
-                lock_id,
-            })
-        })
+        metadata: Option<&str>,
+    ) -> RedisResult<AcquireResult<String>> {
+        self.attempt_acquire(resources, expiration, metadata, Instant::now(), None, None)
     }
 
-    /// Calls [`MultiResourceLock::lock`] with [`DEFAULT_EXPIRATION`], [`DEFAULT_TIMEOUT`] and [`DEFAULT_SLEEP`].
+    /// Attempts to acquire the lock returning immediately if it cannot be immediately acquired,
+    /// only acquiring it when `check` holds.
+    ///
+    /// `check` is evaluated in the same call as the acquisition, so nothing can change the key it
+    /// reads in between. Returns [`AcquireResult::PreconditionFailed`], locking nothing, when it
+    /// fails.
     ///
     /// # Errors
     ///
-    /// When [`MultiResourceLock::lock`] errors.
+    /// When [`MultiResourceLock::try_acquire_outcome`] errors.
     #[inline]
-    pub fn lock_default(
-        &mut self,
+    pub fn try_acquire_if(
+        &self,
         resources: &[String],
-    ) -> RedisResult<Option<MultiResourceGuard<'_>>> {
-        self.lock(
+        expiration: Duration,
+        check: &LuaCondition,
+    ) -> RedisResult<AcquireResult<String>> {
+        self.attempt_acquire(
             resources,
-            DEFAULT_EXPIRATION,
-            DEFAULT_TIMEOUT,
-            DEFAULT_SLEEP,
+            expiration,
+            None,
+            Instant::now(),
+            None,
+            Some(check),
         )
     }
 
-    /// Attempts to acquire the lock blocking until the lock can be acquired.
+    /// Attempts to acquire the lock returning immediately if it cannot be immediately acquired,
+    /// returning a [`LockToken`] that can be passed to another process.
     ///
-    /// Blocks up to `timeout` duration making attempts every `sleep` duration.
+    /// # Errors
     ///
-    /// Returns `None` when it times out.
+    /// When [`MultiResourceLock::try_acquire`] errors.
+    #[inline]
+    pub fn try_acquire_token(
+        &self,
+        resources: &[String],
+        expiration: Duration,
+    ) -> RedisResult<Option<LockToken>> {
+        Ok(self
+            .try_acquire(resources, expiration)?
+            .map(|lock_id| LockToken::new(lock_id, resources.to_vec(), expiration)))
+    }
+
+    /// Attempts to acquire the lock returning immediately if it cannot be immediately acquired,
+    /// then runs `commands` under it.
     ///
-    /// Wraps the result in a guard that releases the lock when dropped.
+    /// The attempt and the commands are pipelined so share a single round trip, the commands
+    /// only run when the lock is acquired, as with [`MultiResourceLock::guarded_exec`].
+    ///
+    /// Returns the lock identifier and the reply of each command, or `None` when the lock cannot
+    /// be acquired.
     ///
     /// # Errors
     ///
-    /// When [`MultiResourceLock::acquire`] errors.
+    /// - When the `acquire_lock` or `guarded_exec` functions are missing from the Redis instance.
+    /// - When `resources` contain duplicates and [`MultiResourceLock::with_strict_resources`] is
+    ///   set.
+    /// - When a command errors, the lock is released and commands before it will have been run.
+    ///   Failing to release the lock is reported to [`Metrics::on_release_error`] rather than
+    ///   replacing the error.
     #[inline]
-    pub fn lock(
-        &mut self,
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip(self, commands), fields(lock_id), err)
+    )]
+    pub fn try_acquire_and_exec(
+        &self,
         resources: &[String],
         expiration: Duration,
-        timeout: Duration,
-        sleep: Duration,
-    ) -> RedisResult<Option<MultiResourceGuard<'_>>> {
-        self.acquire(resources, expiration, timeout, sleep)
-            .map(|result| {
-                result.map(|lock_id| MultiResourceGuard {
-                    lock: self,
-                    lock_id,
-                })
-            })
+        commands: &[Cmd],
+    ) -> RedisResult<Option<(String, Vec<Value>)>> {
+        let start = Instant::now();
+        self.metrics.on_acquire_attempt();
+        let lock_id = self.id_generator.generate();
+        #[cfg(feature = "tracing")]
+        tracing::Span::current().record("lock_id", &lock_id);
+        let unique = self.hash_tag.keys(
+            unique_resources(resources, self.strict_resources)?,
+            &self.key_formatter,
+        )?;
+        let jittered = jitter(expiration, self.expiration_jitter);
+        let mut pipe = redis::pipe();
+        pipe.add_command(acquire_cmd(
+            &self.key_formatter,
+            &lock_id,
+            &unique,
+            jittered,
+            None,
+            None,
+            None,
+        ))
+        .add_command(guarded_exec_cmd(
+            &self.key_formatter,
+            &lock_id,
+            &unique,
+            commands,
+        ));
+        let reply: RedisResult<(Value, Option<Vec<Value>>)> =
+            self.with_conn(|conn| match pipe.query(conn) {
+                Err(err) if self.auto_setup && is_missing_function(&err) => {
+                    load_library(conn)?;
+                    pipe.query(conn)
+                }
+                reply => reply,
+            });
+        let (acquired, replies) = match reply {
+            Ok((acquire_reply, replies)) => (
+                AcquireResult::from_reply(acquire_reply, &self.hash_tag)?.acquired(),
+                replies,
+            ),
+            Err(err) => {
+                if let Err(error) = self.release(&lock_id) {
+                    report_release_error(&*self.metrics, &lock_id, &error);
+                }
+                return Err(err);
+            }
+        };
+
+        #[cfg(feature = "tracing")]
+        tracing::debug!(
+            outcome = if acquired.is_some() {
+                "acquired"
+            } else {
+                "contended"
+            }
+        );
+        if acquired.is_some() {
+            self.metrics
+                .on_acquired_resources(resources, start.elapsed());
+        }
+        Ok(acquired.zip(replies))
     }
-}
 
-/// A guard that releases the lock when it is dropped.
-#[derive(Debug)]
-pub struct MultiResourceGuard<'a> {
-    /// The lock instance.
-    lock: &'a mut MultiResourceLock,
-    /// The lock identifier.
-    lock_id: String,
+    /// Attempts to acquire the lock up to `attempts` times, at least once, waiting `sleep`
+    /// between attempts.
+    ///
+    /// Unlike [`MultiResourceLock::acquire`] this is bounded by the number of attempts rather
+    /// than by time.
+    ///
+    /// Returns `None` when every attempt fails.
+    ///
+    /// # Errors
+    ///
+    /// When [`MultiResourceLock::try_acquire`] errors.
+    #[inline]
+    pub fn try_acquire_n(
+        &self,
+        resources: &[String],
+        expiration: Duration,
+        attempts: u32,
+        sleep: Duration,
+    ) -> RedisResult<Option<String>> {
+        let priority = self.fair.then_some(DEFAULT_PRIORITY);
+        let mut retry = Attempts::new(attempts, sleep);
+        Ok(self
+            .acquire_queued(resources, expiration, &mut retry, None, priority, None)?
+            .acquired())
+    }
+
+    /// Makes a single attempt to acquire the lock, reporting the wait since `start` to the
+    /// metrics hooks when acquired.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            name = "try_acquire",
+            level = "debug",
+            skip(self, start),
+            fields(lock_id),
+            err
+        )
+    )]
+    fn attempt_acquire(
+        &self,
+        resources: &[String],
+        expiration: Duration,
+        metadata: Option<&str>,
+        start: Instant,
+        queue: Option<&Waiter>,
+        condition: Option<&LuaCondition>,
+    ) -> RedisResult<AcquireResult<String>> {
+        self.metrics.on_acquire_attempt();
+        let lock_id = queue.map_or_else(
+            || self.id_generator.generate(),
+            |waiter| waiter.lock_id.clone(),
+        );
+        #[cfg(feature = "tracing")]
+        tracing::Span::current().record("lock_id", &lock_id);
+        let unique = self.hash_tag.keys(
+            unique_resources(resources, self.strict_resources)?,
+            &self.key_formatter,
+        )?;
+        #[cfg(feature = "log")]
+        log::debug!(
+            target: self.log_target,
+            "attempting to acquire lock {lock_id} on {} resources",
+            unique.len()
+        );
+        let jittered = jitter(expiration, self.expiration_jitter);
+        let result = AcquireResult::from_reply(
+            self.query(&acquire_cmd(
+                &self.key_formatter,
+                &lock_id,
+                &unique,
+                jittered,
+                metadata,
+                queue,
+                condition,
+            ))?,
+            &self.hash_tag,
+        )?;
+
+        #[cfg(feature = "log")]
+        log_attempt(self.log_target, &lock_id, unique.len(), &result);
+        #[cfg(feature = "tracing")]
+        tracing::debug!(
+            outcome = match result {
+                AcquireResult::Acquired(_) => "acquired",
+                AcquireResult::PreconditionFailed => "precondition failed",
+                AcquireResult::Contended { .. } | AcquireResult::TimedOut => "contended",
+            }
+        );
+        if result.is_acquired() {
+            self.metrics
+                .on_acquired_resources(resources, start.elapsed());
+        }
+        Ok(result)
+    }
+
+    /// Removes the waiter `lock_id` from the queue of each of `resources`.
+    fn dequeue(&self, lock_id: &str, resources: &[String]) -> RedisResult<()> {
+        self.query(
+            fcall("dequeue", &self.key_formatter).arg(lock_id).arg(
+                &*self
+                    .hash_tag
+                    .keys(Cow::Borrowed(resources), &self.key_formatter)?,
+            ),
+        )
+    }
+
+    /// Attempts to acquire a lock on each of `sets` returning immediately if they cannot all be
+    /// immediately acquired.
+    ///
+    /// Either every set is locked or none are, returning the lock identifier of each set in
+    /// order. Each lock is independent and must be released separately.
+    ///
+    /// # Errors
+    ///
+    /// - When the `acquire_locks` function is missing from the Redis instance.
+    /// - When a set is empty or `sets` overlap.
+    /// - When a set contains duplicates and [`MultiResourceLock::with_strict_resources`] is set.
+    #[inline]
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip(self), err)
+    )]
+    pub fn try_acquire_many(
+        &self,
+        sets: &[Vec<String>],
+        expiration: Duration,
+    ) -> RedisResult<Option<Vec<String>>> {
+        let start = Instant::now();
+        for _ in sets {
+            self.metrics.on_acquire_attempt();
+        }
+        let mut cmd = fcall("acquire_locks", &self.key_formatter);
+        cmd.arg(expiration_millis(jitter(
+            expiration,
+            self.expiration_jitter,
+        )));
+        for set in sets {
+            let unique = self.hash_tag.keys(
+                unique_resources(set, self.strict_resources)?,
+                &self.key_formatter,
+            )?;
+            cmd.arg(self.id_generator.generate())
+                .arg(unique.len())
+                .arg(&*unique);
+        }
+
+        let result: Option<Vec<String>> = self.query(&cmd)?;
+
+        #[cfg(feature = "tracing")]
+        tracing::debug!(
+            outcome = if result.is_some() {
+                "acquired"
+            } else {
+                "contended"
+            }
+        );
+        if result.is_some() {
+            for set in sets {
+                self.metrics.on_acquired_resources(set, start.elapsed());
+            }
+        }
+        Ok(result)
+    }
+
+    /// Attempts to acquire the lock on the first of `sets` that is not contended, returning
+    /// immediately if every set is contended.
+    ///
+    /// The sets are tried in order in a single call, returning the index of the acquired set and
+    /// the lock identifier, e.g. to lock whichever of several shards is free.
+    ///
+    /// # Errors
+    ///
+    /// - When the `acquire_any` function is missing from the Redis instance.
+    /// - When a set is empty.
+    /// - When a set contains duplicates and [`MultiResourceLock::with_strict_resources`] is set.
+    #[inline]
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip(self), err)
+    )]
+    pub fn try_acquire_any(
+        &self,
+        sets: &[Vec<String>],
+        expiration: Duration,
+    ) -> RedisResult<Option<(usize, String)>> {
+        let start = Instant::now();
+        self.metrics.on_acquire_attempt();
+        let mut cmd = fcall("acquire_any", &self.key_formatter);
+        cmd.arg(self.id_generator.generate())
+            .arg(expiration_millis(jitter(
+                expiration,
+                self.expiration_jitter,
+            )));
+        for set in sets {
+            let unique = self.hash_tag.keys(
+                unique_resources(set, self.strict_resources)?,
+                &self.key_formatter,
+            )?;
+            cmd.arg(unique.len()).arg(&*unique);
+        }
+
+        let result: Option<(usize, String)> = self.query(&cmd)?;
+
+        #[cfg(feature = "tracing")]
+        tracing::debug!(
+            outcome = if result.is_some() {
+                "acquired"
+            } else {
+                "contended"
+            }
+        );
+        if let Some(set) = result.as_ref().and_then(|(index, _)| sets.get(*index)) {
+            self.metrics.on_acquired_resources(set, start.elapsed());
+        }
+        Ok(result)
+    }
+
+    /// Attempts to acquire the lock on each resource of `resources` with its own expiration,
+    /// returning immediately if the lock cannot be immediately acquired.
+    ///
+    /// Each resource is released when its expiration elapses, or never when it is
+    /// [`INDEFINITE`](crate::INDEFINITE), so the lock is no longer held in full once its
+    /// shortest-lived resource expires. Extending the lock gives every resource the same
+    /// expiration.
+    ///
+    /// # Errors
+    ///
+    /// - When the `acquire_lock_expiring` function is missing from the Redis instance.
+    /// - When `resources` is empty or contains duplicates.
+    #[inline]
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip(self), err)
+    )]
+    pub fn try_acquire_expiring(
+        &self,
+        resources: &[(String, Duration)],
+    ) -> RedisResult<Option<String>> {
+        let names = resources
+            .iter()
+            .map(|(resource, _)| resource.clone())
+            .collect::<Vec<_>>();
+        let keys = self
+            .hash_tag
+            .keys(unique_resources(&names, true)?, &self.key_formatter)?;
+        let start = Instant::now();
+        self.metrics.on_acquire_attempt();
+        let mut cmd = fcall("acquire_lock_expiring", &self.key_formatter);
+        cmd.arg(self.id_generator.generate());
+        for (key, (_, expiration)) in keys.iter().zip(resources) {
+            cmd.arg(key).arg(expiration_millis(jitter(
+                *expiration,
+                self.expiration_jitter,
+            )));
+        }
+
+        let result: Option<String> = self.query(&cmd)?;
+
+        #[cfg(feature = "tracing")]
+        tracing::debug!(
+            outcome = if result.is_some() {
+                "acquired"
+            } else {
+                "contended"
+            }
+        );
+        if result.is_some() {
+            self.metrics.on_acquired_resources(&names, start.elapsed());
+        }
+        Ok(result)
+    }
+
+    /// Attempts to acquire `weight` units of `resource`, which has `capacity` units in total,
+    /// returning immediately if they cannot be immediately acquired.
+    ///
+    /// Succeeds while the units held by other locks plus `weight` do not exceed `capacity`,
+    /// units held by locks that expired without being released are reclaimed. Weighted locks
+    /// are independent of the exclusive locks acquired by [`MultiResourceLock::try_acquire`]
+    /// and must be released with [`MultiResourceLock::release_weighted`].
+    ///
+    /// # Errors
+    ///
+    /// - When the `acquire_weighted` function is missing from the Redis instance.
+    #[inline]
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip(self), err)
+    )]
+    pub fn acquire_weighted(
+        &self,
+        resource: &str,
+        weight: u32,
+        capacity: u32,
+        expiration: Duration,
+    ) -> RedisResult<Option<String>> {
+        let start = Instant::now();
+        self.metrics.on_acquire_attempt();
+        let result: Option<String> = self.query(
+            fcall("acquire_weighted", &self.key_formatter)
+                .arg(self.id_generator.generate())
+                .arg(expiration_millis(jitter(
+                    expiration,
+                    self.expiration_jitter,
+                )))
+                .arg(weight)
+                .arg(capacity)
+                .arg(&*self.hash_tag.key(resource)),
+        )?;
+
+        #[cfg(feature = "tracing")]
+        tracing::debug!(
+            outcome = if result.is_some() {
+                "acquired"
+            } else {
+                "contended"
+            }
+        );
+        if result.is_some() {
+            self.metrics
+                .on_acquired_resources(&[resource.to_owned()], start.elapsed());
+        }
+        Ok(result)
+    }
+
+    /// Runs `cmd`, loading the Lua library and retrying once when a function is missing and
+    /// [`MultiResourceLock::with_auto_setup`] is set.
+    fn query<T: FromRedisValue>(&self, cmd: &Cmd) -> RedisResult<T> {
+        self.with_conn(|conn| query_on(conn, cmd, self.auto_setup))
+    }
+
+    /// Runs `call` on an idle connection, or a new connection when none are idle, so concurrent
+    /// calls each use their own connection.
+    fn with_conn<T, F: FnOnce(&mut Connection) -> RedisResult<T>>(
+        &self,
+        call: F,
+    ) -> RedisResult<T> {
+        let mut conn = self.checkout()?;
+        let result = call(&mut conn);
+        self.checkin(conn);
+        result
+    }
+
+    /// Takes an idle connection, or opens a new connection when none are idle.
+    fn checkout(&self) -> RedisResult<Connection> {
+        let idle = self
+            .idle
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .pop();
+        match idle {
+            Some(conn) => Ok(conn),
+            None => connect(&self.client, self.command_timeout),
+        }
+    }
+
+    /// Returns `conn` to the idle connections, unless it was closed, e.g. by an IO error.
+    fn checkin(&self, conn: Connection) {
+        if conn.is_open() {
+            self.idle
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .push(conn);
+        }
+    }
+
+    /// Checks Redis is reachable and the Lua library functions required for locking are loaded
+    /// by [`setup`], e.g. for a readiness probe.
+    ///
+    /// # Errors
+    ///
+    /// - When Redis cannot be reached.
+    /// - When the Lua library functions of [`LIBRARY_VERSION`](crate::LIBRARY_VERSION) are
+    ///   missing from the Redis instance, or differ from those of this crate.
+    #[inline]
+    pub fn healthcheck(&self) -> RedisResult<()> {
+        let loaded: Vec<HashMap<String, Value>> = self.with_conn(|conn| {
+            redis::cmd("PING").exec(conn)?;
+            redis::cmd("FUNCTION")
+                .arg("LIST")
+                .arg("LIBRARYNAME")
+                .arg(library_name())
+                .arg("WITHCODE")
+                .query(conn)
+        })?;
+        check_loaded(&loaded)
+    }
+
+    /// Counts the held locks and locked resources across all users of the Redis instance.
+    ///
+    /// This reads an index maintained as locks are acquired and released, rather than scanning
+    /// the locks, so is cheap enough for a health check.
+    ///
+    /// # Errors
+    ///
+    /// - When the `lock_stats` function is missing from the Redis instance.
+    #[inline]
+    pub fn stats(&self) -> RedisResult<LockStats> {
+        let (active_locks, locked_resources): (usize, usize) =
+            self.query(&fcall("lock_stats", &self.key_formatter))?;
+
+        Ok(LockStats {
+            active_locks,
+            locked_resources,
+        })
+    }
+
+    /// Removes the locks whose keys no longer exist from the index read by
+    /// [`MultiResourceLock::stats`], along with the resources no longer locked.
+    ///
+    /// Entries are normally removed as locks are released or expire, but can be left behind when
+    /// lock keys are removed by other means, e.g. eviction or `DEL`. This scans every lock key, so
+    /// is meant for occasional maintenance rather than every call.
+    ///
+    /// Returns the number of locks removed.
+    ///
+    /// # Errors
+    ///
+    /// - When the `compact_index` function is missing from the Redis instance.
+    #[inline]
+    pub fn compact_index(&self) -> RedisResult<usize> {
+        self.query(&fcall("compact_index", &self.key_formatter))
+    }
+
+    /// Checks whether the lock `lock_id` is still held.
+    ///
+    /// E.g. to confirm a lock identifier persisted before a restart still refers to a held lock
+    /// before resuming work under it.
+    ///
+    /// # Errors
+    ///
+    /// - When the `lock_held` function is missing from the Redis instance.
+    #[inline]
+    pub fn is_held(&self, lock_id: &str) -> RedisResult<bool> {
+        let result: bool = self.query(fcall("lock_held", &self.key_formatter).arg(lock_id))?;
+
+        Ok(result)
+    }
+
+    /// Checks whether the lock `lock_id` still holds every one of `resources`.
+    ///
+    /// E.g. to confirm the lock has not expired, say during a long GC pause, just before a
+    /// critical write. Unlike [`MultiResourceLock::is_held`] this only reads the listed resources
+    /// so is cheap, but the lock may still expire straight after it returns.
+    ///
+    /// # Errors
+    ///
+    /// - When the `lock_owns` function is missing from the Redis instance.
+    #[inline]
+    pub fn owns(&self, lock_id: &str, resources: &[String]) -> RedisResult<bool> {
+        let result: bool = self.query(
+            fcall("lock_owns", &self.key_formatter).arg(lock_id).arg(
+                &*self
+                    .hash_tag
+                    .keys(Cow::Borrowed(resources), &self.key_formatter)?,
+            ),
+        )?;
+
+        Ok(result)
+    }
+
+    /// Counts the waiters on `resource`, e.g. as a contention metric for autoscaling.
+    ///
+    /// Waiters queued by [`MultiResourceLock::with_fair_queue`] or
+    /// [`MultiResourceLock::acquire_with_priority`] are counted exactly, waiters that died are not
+    /// counted. Waiters retrying without queueing are approximated by the contended attempts on
+    /// `resource` within the last [`DEFAULT_SLEEP`], so are over counted when retrying more
+    /// often and under counted when retrying less often.
+    ///
+    /// # Errors
+    ///
+    /// - When the `waiter_count` function is missing from the Redis instance.
+    #[inline]
+    pub fn waiter_count(&self, resource: &str) -> RedisResult<usize> {
+        let result: usize = self
+            .query(fcall("waiter_count", &self.key_formatter).arg(&*self.hash_tag.key(resource)))?;
+
+        Ok(result)
+    }
+
+    /// Gets the position of the waiter `lock_id` in the queue for `resource`, where `0` is the
+    /// head of the queue.
+    ///
+    /// Waiters queue when [`MultiResourceLock::with_fair_queue`] is set or when acquiring with
+    /// [`MultiResourceLock::acquire_with_priority`], waiters that died are not counted.
+    ///
+    /// Returns `None` when `lock_id` is not waiting on `resource`.
+    ///
+    /// # Errors
+    ///
+    /// - When the `queue_position` function is missing from the Redis instance.
+    #[inline]
+    pub fn queue_position(&self, resource: &str, lock_id: &str) -> RedisResult<Option<usize>> {
+        let result: Option<usize> = self.query(
+            fcall("queue_position", &self.key_formatter)
+                .arg(&*self.hash_tag.key(resource))
+                .arg(lock_id),
+        )?;
+
+        Ok(result)
+    }
+
+    /// Runs `commands` only if the lock `lock_id` still holds every one of `resources`.
+    ///
+    /// The ownership check and the commands run atomically in Redis, so the lock cannot expire
+    /// between them. E.g. `redis::cmd("DECRBY").arg("account1").arg(100)` and
+    /// `redis::cmd("INCRBY").arg("account2").arg(100)` to transfer between the accounts
+    /// `account1` and `account2`.
+    ///
+    /// Returns the reply of each command, or `None` when a resource is not held.
+    ///
+    /// # Errors
+    ///
+    /// - When the `guarded_exec` function is missing from the Redis instance.
+    /// - When `resources` is empty.
+    /// - When a command errors, commands before it will have been run.
+    #[inline]
+    pub fn guarded_exec(
+        &self,
+        lock_id: &str,
+        resources: &[String],
+        commands: &[Cmd],
+    ) -> RedisResult<Option<Vec<Value>>> {
+        let keys = self
+            .hash_tag
+            .keys(Cow::Borrowed(resources), &self.key_formatter)?;
+        let result: Option<Vec<Value>> = self.query(&guarded_exec_cmd(
+            &self.key_formatter,
+            lock_id,
+            &keys,
+            commands,
+        ))?;
+
+        Ok(result)
+    }
+
+    /// Moves `amount` from the integer at `from` to the integer at `to`, only if the lock
+    /// `lock_id` still holds both as resources and `from` holds at least `amount`.
+    ///
+    /// The ownership check, the balance check and both updates run atomically in Redis in a
+    /// single round trip, e.g. to transfer between accounts locked by `lock_id`. A missing key
+    /// holds `0`. Nothing is moved when either key is not a resource of the lock.
+    ///
+    /// # Errors
+    ///
+    /// - When the `guarded_move` function is missing from the Redis instance.
+    /// - When `amount` is negative or `from` does not hold an integer.
+    #[inline]
+    pub fn guarded_move(
+        &self,
+        lock_id: &str,
+        from: &str,
+        to: &str,
+        amount: i64,
+    ) -> RedisResult<MoveOutcome> {
+        let result: Option<bool> = self.query(
+            fcall("guarded_move", &self.key_formatter)
+                .arg(lock_id)
+                .arg(from)
+                .arg(to)
+                .arg(amount)
+                .arg(&*self.hash_tag.key(from))
+                .arg(&*self.hash_tag.key(to)),
+        )?;
+
+        Ok(MoveOutcome::from_reply(result))
+    }
+
+    /// Gets the resources held by the lock `lock_id`, sorted.
+    ///
+    /// Returns an empty list when `lock_id` is not held.
+    ///
+    /// # Errors
+    ///
+    /// - When the `lock_resources` function is missing from the Redis instance.
+    #[inline]
+    pub fn lock_resources(&self, lock_id: &str) -> RedisResult<Vec<String>> {
+        let result: Vec<String> =
+            self.query(fcall("lock_resources", &self.key_formatter).arg(lock_id))?;
+
+        Ok(self.hash_tag.resources(result))
+    }
+
+    /// Gets the remaining time to live of the lock `lock_id` on each resource it holds, sorted
+    /// by resource.
+    ///
+    /// Resources that never expire have [`INDEFINITE`](crate::INDEFINITE). Returns an empty list when `lock_id`
+    /// is not held.
+    ///
+    /// # Errors
+    ///
+    /// - When the `lock_ttls` function is missing from the Redis instance.
+    #[inline]
+    pub fn ttls(&self, lock_id: &str) -> RedisResult<Vec<(String, Duration)>> {
+        let result: Vec<(String, i64)> =
+            self.query(fcall("lock_ttls", &self.key_formatter).arg(lock_id))?;
+
+        Ok(ttls_from_reply(result, &self.hash_tag))
+    }
+
+    /// Gets the metadata stored alongside the lock `lock_id`.
+    ///
+    /// Returns `None` when `lock_id` is not held or was acquired without metadata.
+    ///
+    /// # Errors
+    ///
+    /// - When the `lock_metadata` function is missing from the Redis instance.
+    #[inline]
+    pub fn lock_metadata(&self, lock_id: &str) -> RedisResult<Option<String>> {
+        let result: Option<String> =
+            self.query(fcall("lock_metadata", &self.key_formatter).arg(lock_id))?;
+
+        Ok(result)
+    }
+
+    /// Gets the metadata stored alongside the lock holding `resource`.
+    ///
+    /// Returns `None` when `resource` is not locked or was locked without metadata.
+    ///
+    /// # Errors
+    ///
+    /// - When the `resource_metadata` function is missing from the Redis instance.
+    #[inline]
+    pub fn resource_metadata(&self, resource: &str) -> RedisResult<Option<String>> {
+        let result: Option<String> = self.query(
+            fcall("resource_metadata", &self.key_formatter).arg(&*self.hash_tag.key(resource)),
+        )?;
+
+        Ok(result)
+    }
+
+    /// Checks which of `resources` are held, by a lock or a weighted lock, without acquiring
+    /// anything.
+    ///
+    /// Returns each resource alongside whether it is held. Nothing is written, so the result may
+    /// be stale by the time it is acted on.
+    ///
+    /// # Errors
+    ///
+    /// - When the `probe` function is missing from the Redis instance.
+    /// - When the resources hash to different slots with [`HashTag::Existing`].
+    #[inline]
+    pub fn probe(&self, resources: &[String]) -> RedisResult<Vec<(String, bool)>> {
+        let held: Vec<bool> = self.query(
+            fcall("probe", &self.key_formatter).arg(
+                &*self
+                    .hash_tag
+                    .keys(Cow::Borrowed(resources), &self.key_formatter)?,
+            ),
+        )?;
+
+        Ok(resources.iter().cloned().zip(held).collect())
+    }
+
+    /// Resets the expiration of the lock `lock_id` on `resources` to `expiration` from now, or to
+    /// never expire when it is [`INDEFINITE`](crate::INDEFINITE).
+    ///
+    /// Returns `false`, leaving the lock unchanged, when any of `resources` is no longer held by
+    /// `lock_id`. An expired lock is never recreated, as another lock may have since acquired
+    /// its resources.
+    ///
+    /// # Errors
+    ///
+    /// - When the `extend_lock` function is missing from the Redis instance.
+    #[inline]
+    pub fn try_extend(
+        &self,
+        lock_id: &str,
+        resources: &[String],
+        expiration: Duration,
+    ) -> RedisResult<bool> {
+        let result: bool = self.query(
+            fcall("extend_lock", &self.key_formatter)
+                .arg(lock_id)
+                .arg(expiration_millis(expiration))
+                .arg(false)
+                .arg(
+                    &*self
+                        .hash_tag
+                        .keys(Cow::Borrowed(resources), &self.key_formatter)?,
+                ),
+        )?;
+
+        Ok(result)
+    }
+
+    /// Extends the lock `lock_id` on each resource it holds to expire no sooner than `minimum`
+    /// from now, or never when it is [`INDEFINITE`](crate::INDEFINITE).
+    ///
+    /// Unlike [`MultiResourceLock::try_extend`] an expiration already later than `minimum` is
+    /// kept, so concurrent renewers never shorten the lock. Returns `false` when `lock_id` is not
+    /// held.
+    ///
+    /// # Errors
+    ///
+    /// - When the `extend_lock_at_least` function is missing from the Redis instance.
+    #[inline]
+    pub fn extend_at_least(&self, lock_id: &str, minimum: Duration) -> RedisResult<bool> {
+        let result: bool = self.query(
+            fcall("extend_lock_at_least", &self.key_formatter)
+                .arg(lock_id)
+                .arg(expiration_millis(minimum)),
+        )?;
+
+        Ok(result)
+    }
+
+    /// Releases a held lock.
+    ///
+    /// Returns the number of resources released, see [`MultiResourceLock::release_outcome`] to
+    /// tell whether the lock expired before it was released.
+    ///
+    /// # Errors
+    ///
+    /// When [`MultiResourceLock::release_outcome`] errors.
+    #[inline]
+    pub fn release(&self, lock_id: &str) -> RedisResult<usize> {
+        Ok(self.release_outcome(lock_id)?.released())
+    }
+
+    /// Releases a held lock, reporting whether it expired before it was released.
+    ///
+    /// A lock released after it expired is reported to [`Metrics::on_release_expired`], and
+    /// logged as a warning when the `tracing` feature is enabled.
+    ///
+    /// # Errors
+    ///
+    /// - When the `release_lock` function is missing from the Redis instance.
+    #[inline]
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip(self), err)
+    )]
+    pub fn release_outcome(&self, lock_id: &str) -> RedisResult<ReleaseOutcome> {
+        self.release_marking(lock_id, None)
+    }
+
+    /// Releases a held lock, marking its resources as poisoned for `ttl`, or until cleared when it
+    /// is [`INDEFINITE`](crate::INDEFINITE).
+    ///
+    /// Like a [`std::sync::Mutex`] poisoned by a panic, this records that the resources were
+    /// released in a potentially inconsistent state, so the next holder can check
+    /// [`MultiResourceLock::is_poisoned`] and run recovery before
+    /// [`MultiResourceLock::clear_poison`]. Guards dropped while panicking release this way.
+    ///
+    /// # Errors
+    ///
+    /// - When the `release_lock` function is missing from the Redis instance.
+    #[inline]
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip(self), err)
+    )]
+    pub fn release_poisoned(&self, lock_id: &str, ttl: Duration) -> RedisResult<ReleaseOutcome> {
+        self.release_marking(lock_id, Some(ttl))
+    }
+
+    /// Releases the lock `lock_id`, marking its resources as poisoned for `poison` when set.
+    fn release_marking(
+        &self,
+        lock_id: &str,
+        poison: Option<Duration>,
+    ) -> RedisResult<ReleaseOutcome> {
+        let outcome = retry_release(self.release_retries, lock_id, || {
+            self.with_conn(|conn| {
+                release_on(
+                    conn,
+                    &*self.metrics,
+                    &self.key_formatter,
+                    self.auto_setup,
+                    lock_id,
+                    poison,
+                )
+            })
+        })?;
+        #[cfg(feature = "log")]
+        log_release(self.log_target, lock_id, outcome);
+        Ok(outcome)
+    }
+
+    /// Checks whether `resource` was released poisoned, see
+    /// [`MultiResourceLock::release_poisoned`].
+    ///
+    /// # Errors
+    ///
+    /// - When the `poisoned` function is missing from the Redis instance.
+    #[inline]
+    pub fn is_poisoned(&self, resource: &str) -> RedisResult<bool> {
+        let result: bool =
+            self.query(fcall("poisoned", &self.key_formatter).arg(&*self.hash_tag.key(resource)))?;
+
+        Ok(result)
+    }
+
+    /// Clears the poison on `resource`, e.g. once recovery has run.
+    ///
+    /// Returns `false` when `resource` was not poisoned.
+    ///
+    /// # Errors
+    ///
+    /// - When the `clear_poison` function is missing from the Redis instance.
+    #[inline]
+    pub fn clear_poison(&self, resource: &str) -> RedisResult<bool> {
+        let result: bool = self
+            .query(fcall("clear_poison", &self.key_formatter).arg(&*self.hash_tag.key(resource)))?;
+
+        Ok(result)
+    }
+
+    /// Releases the units of `resource` held by the weighted lock `lock_id`.
+    ///
+    /// Returns `false` when `lock_id` does not hold units of `resource`.
+    ///
+    /// # Errors
+    ///
+    /// - When the `release_weighted` function is missing from the Redis instance.
+    #[inline]
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip(self), err)
+    )]
+    pub fn release_weighted(&self, resource: &str, lock_id: &str) -> RedisResult<bool> {
+        let result: bool = self.query(
+            fcall("release_weighted", &self.key_formatter)
+                .arg(&*self.hash_tag.key(resource))
+                .arg(lock_id),
+        )?;
+
+        if result {
+            self.metrics.on_release();
+        }
+        Ok(result)
+    }
+
+    /// Releases several held locks at once.
+    ///
+    /// Returns the total number of keys cleared, lock identifiers which do not refer to a held
+    /// lock are ignored.
+    ///
+    /// # Errors
+    ///
+    /// - When the `release_locks` function is missing from the Redis instance.
+    #[inline]
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip(self), err)
+    )]
+    pub fn release_many(&self, lock_ids: &[String]) -> RedisResult<usize> {
+        let (cleared, released): (usize, usize) =
+            self.query(fcall("release_locks", &self.key_formatter).arg(lock_ids))?;
+
+        #[cfg(feature = "tracing")]
+        tracing::debug!(released = cleared);
+        for _ in 0..released {
+            self.metrics.on_release();
+        }
+        Ok(cleared)
+    }
+
+    /// Forcibly releases `resource` regardless of which lock holds it.
+    ///
+    /// This is an escape hatch for breaking locks left behind by dead workers and should not be
+    /// used in place of [`MultiResourceLock::release`]. Other resources held by the same lock
+    /// remain held.
+    ///
+    /// Returns the number of keys cleared.
+    ///
+    /// # Errors
+    ///
+    /// - When the `force_release` function is missing from the Redis instance.
+    #[inline]
+    pub fn force_release(&self, resource: &str) -> RedisResult<usize> {
+        let result: usize = self.query(
+            fcall("force_release", &self.key_formatter).arg(&*self.hash_tag.key(resource)),
+        )?;
+
+        Ok(result)
+    }
+
+    /// Forcibly releases `resources` regardless of which locks hold them.
+    ///
+    /// Unlike [`MultiResourceLock::release`] this needs no lock id and checks no ownership, so it
+    /// is an escape hatch for recovering resources whose holder is unknown, e.g. when the lock id
+    /// was not persisted. Other resources held by the same locks remain held. Each lock broken is
+    /// logged at warn level for auditing.
+    ///
+    /// Returns the number of keys cleared.
+    ///
+    /// # Errors
+    ///
+    /// - When the `force_release_resources` function is missing from the Redis instance.
+    #[inline]
+    pub fn release_by_resources(&self, resources: &[String]) -> RedisResult<usize> {
+        let (cleared, lock_ids): (usize, Vec<String>) = self.query(
+            fcall("force_release_resources", &self.key_formatter).arg(
+                &*self
+                    .hash_tag
+                    .keys(Cow::Borrowed(resources), &self.key_formatter)?,
+            ),
+        )?;
+
+        #[cfg(any(feature = "tracing", feature = "log"))]
+        for lock_id in &lock_ids {
+            #[cfg(feature = "tracing")]
+            tracing::warn!(lock_id, "forcibly released resources of lock");
+            #[cfg(feature = "log")]
+            log::warn!(target: self.log_target, "forcibly released resources of lock {lock_id}");
+        }
+        #[cfg(not(any(feature = "tracing", feature = "log")))]
+        let _: Vec<String> = lock_ids;
+        Ok(cleared)
+    }
+
+    /// Forcibly releases the lock `lock_id` ignoring ownership checks.
+    ///
+    /// This is an escape hatch for breaking locks left behind by dead workers and should not be
+    /// used in place of [`MultiResourceLock::release`].
+    ///
+    /// Returns the number of keys cleared.
+    ///
+    /// # Errors
+    ///
+    /// - When the `force_release_lock` function is missing from the Redis instance.
+    #[inline]
+    pub fn force_release_lock(&self, lock_id: &str) -> RedisResult<usize> {
+        let result: usize =
+            self.query(fcall("force_release_lock", &self.key_formatter).arg(lock_id))?;
+
+        Ok(result)
+    }
+
+    /// Calls [`MultiResourceLock::try_lock`] with [`DEFAULT_EXPIRATION`].
+    ///
+    /// # Errors
+    ///
+    /// When [`MultiResourceLock::try_lock`] errors.
+    #[inline]
+    pub fn try_lock_default(
+        &self,
+        resources: &[String],
+    ) -> RedisResult<Option<MultiResourceGuard<'_>>> {
+        self.try_lock(resources, DEFAULT_EXPIRATION)
+    }
+
+    /// Attempts to acquire the lock returning immediately if it cannot be immediately acquired.
+    ///
+    /// Wraps the result in a guard that releases the lock when dropped.
+    ///
+    /// # Errors
+    ///
+    /// When [`MultiResourceLock::try_acquire`] errors.
+    #[inline]
+    pub fn try_lock(
+        &self,
+        resources: &[String],
+        expiration: Duration,
+    ) -> RedisResult<Option<MultiResourceGuard<'_>>> {
+        // Duplicates are allowed when not strict, the guard holds each resource once.
+        let unique = unique_resources(resources, false)?;
+        self.try_acquire(resources, expiration).map(|result| {
+            result.map(|lock_id| MultiResourceGuard {
+                lock: self,
+                lock_id,
+                resources: unique.into_owned(),
+                conn: None,
+                metadata: None,
+                closed: false,
+            })
+        })
+    }
+
+    /// Attempts to acquire the lock returning immediately if it cannot be immediately acquired,
+    /// storing `metadata` alongside it, e.g. the host and request holding it.
+    ///
+    /// The metadata is written with the lock and removed when it is released, it can be read
+    /// back with [`MultiResourceGuard::metadata`] or by other workers with
+    /// [`MultiResourceLock::resource_metadata`].
+    ///
+    /// Wraps the result in a guard that releases the lock when dropped.
+    ///
+    /// # Errors
+    ///
+    /// When [`MultiResourceLock::try_acquire_with_metadata`] errors.
+    #[inline]
+    pub fn try_lock_with_metadata(
+        &self,
+        resources: &[String],
+        expiration: Duration,
+        metadata: &str,
+    ) -> RedisResult<Option<MultiResourceGuard<'_>>> {
+        let unique = unique_resources(resources, false)?;
+        self.try_acquire_with_metadata(resources, expiration, Some(metadata))
+            .map(|result| {
+                result.map(|lock_id| MultiResourceGuard {
+                    lock: self,
+                    lock_id,
+                    resources: unique.into_owned(),
+                    conn: None,
+                    metadata: Some(metadata.to_owned()),
+                    closed: false,
+                })
+            })
+    }
+
+    /// Calls [`MultiResourceLock::lock`] with [`DEFAULT_EXPIRATION`], [`DEFAULT_TIMEOUT`] and [`DEFAULT_SLEEP`].
+    ///
+    /// # Errors
+    ///
+    /// When [`MultiResourceLock::lock`] errors.
+    #[inline]
+    pub fn lock_default(
+        &self,
+        resources: &[String],
+    ) -> RedisResult<Option<MultiResourceGuard<'_>>> {
+        self.lock(
+            resources,
+            DEFAULT_EXPIRATION,
+            DEFAULT_TIMEOUT,
+            DEFAULT_SLEEP,
+        )
+    }
+
+    /// Attempts to acquire the lock blocking until the lock can be acquired.
+    ///
+    /// Blocks up to `timeout` duration making attempts every `sleep` duration.
+    ///
+    /// Returns `None` when it times out.
+    ///
+    /// Wraps the result in a guard that releases the lock when dropped.
+    ///
+    /// # Errors
+    ///
+    /// When [`MultiResourceLock::lock_outcome`] errors.
+    #[inline]
+    pub fn lock(
+        &self,
+        resources: &[String],
+        expiration: Duration,
+        timeout: Duration,
+        sleep: Duration,
+    ) -> RedisResult<Option<MultiResourceGuard<'_>>> {
+        Ok(self
+            .lock_outcome(resources, expiration, timeout, sleep)?
+            .acquired())
+    }
+
+    /// Attempts to acquire the lock blocking until the lock can be acquired, storing `metadata`
+    /// alongside it, e.g. the host and request holding it.
+    ///
+    /// Blocks up to `timeout` duration making attempts every `sleep` duration.
+    ///
+    /// Returns `None` when it times out.
+    ///
+    /// The metadata is written with the lock and removed when it is released, it can be read
+    /// back with [`MultiResourceGuard::metadata`] or by other workers with
+    /// [`MultiResourceLock::resource_metadata`].
+    ///
+    /// Wraps the result in a guard that releases the lock when dropped.
+    ///
+    /// # Errors
+    ///
+    /// When [`MultiResourceLock::acquire_with_metadata`] errors.
+    #[inline]
+    pub fn lock_with_metadata(
+        &self,
+        resources: &[String],
+        expiration: Duration,
+        timeout: Duration,
+        sleep: Duration,
+        metadata: &str,
+    ) -> RedisResult<Option<MultiResourceGuard<'_>>> {
+        let unique = unique_resources(resources, false)?;
+        self.acquire_with_metadata(resources, expiration, timeout, sleep, Some(metadata))
+            .map(|result| {
+                result.map(|lock_id| MultiResourceGuard {
+                    lock: self,
+                    lock_id,
+                    resources: unique.into_owned(),
+                    conn: None,
+                    metadata: Some(metadata.to_owned()),
+                    closed: false,
+                })
+            })
+    }
+
+    /// Calls [`MultiResourceLock::lock`], returning the time spent waiting for the lock
+    /// alongside its guard.
+    ///
+    /// # Errors
+    ///
+    /// When [`MultiResourceLock::lock`] errors.
+    #[inline]
+    pub fn lock_timed(
+        &self,
+        resources: &[String],
+        expiration: Duration,
+        timeout: Duration,
+        sleep: Duration,
+    ) -> RedisResult<Option<(MultiResourceGuard<'_>, Duration)>> {
+        let start = Instant::now();
+        Ok(self
+            .lock(resources, expiration, timeout, sleep)?
+            .map(|guard| (guard, start.elapsed())))
+    }
+
+    /// Attempts to acquire the lock blocking until the lock can be acquired.
+    ///
+    /// Blocks up to `timeout` duration making attempts every `sleep` duration.
+    ///
+    /// Returns [`AcquireResult::TimedOut`] when it times out.
+    ///
+    /// Wraps the result in a guard that releases the lock when dropped.
+    ///
+    /// # Errors
+    ///
+    /// When [`MultiResourceLock::acquire_outcome`] errors.
+    #[inline]
+    pub fn lock_outcome(
+        &self,
+        resources: &[String],
+        expiration: Duration,
+        timeout: Duration,
+        sleep: Duration,
+    ) -> RedisResult<AcquireResult<MultiResourceGuard<'_>>> {
+        let unique = unique_resources(resources, false)?;
+        self.acquire_outcome(resources, expiration, timeout, sleep, None)
+            .map(|result| {
+                result.map(|lock_id| MultiResourceGuard {
+                    lock: self,
+                    lock_id,
+                    resources: unique.into_owned(),
+                    conn: None,
+                    metadata: None,
+                    closed: false,
+                })
+            })
+    }
+
+    /// Adopts the lock `lock_id` held on `resources`, wrapping it in a guard that releases the
+    /// lock when dropped.
+    ///
+    /// This does not check the lock is still held on `resources`, see
+    /// [`MultiResourceLock::try_guard_from_id`].
+    #[inline]
+    pub fn guard_from_id(&self, lock_id: String, resources: Vec<String>) -> MultiResourceGuard<'_> {
+        MultiResourceGuard {
+            lock: self,
+            lock_id,
+            resources,
+            conn: None,
+            metadata: None,
+            closed: false,
+        }
+    }
+
+    /// Adopts the lock `lock_id` if it is still held, wrapping it in a guard that releases the
+    /// lock when dropped.
+    ///
+    /// Returns `None` when the lock is no longer held.
+    ///
+    /// # Errors
+    ///
+    /// When [`MultiResourceLock::lock_resources`] errors.
+    #[inline]
+    pub fn try_guard_from_id(
+        &self,
+        lock_id: String,
+    ) -> RedisResult<Option<MultiResourceGuard<'_>>> {
+        let resources = self.lock_resources(&lock_id)?;
+        Ok((!resources.is_empty()).then(|| self.guard_from_id(lock_id, resources)))
+    }
+
+    /// Creates a scope holding the guards of the locks acquired through it, released together,
+    /// the most recently acquired first, when it is dropped.
+    ///
+    /// E.g. for the locks acquired at different steps of a saga.
+    #[inline]
+    #[must_use]
+    pub fn scope(&self) -> LockScope<'_> {
+        LockScope {
+            lock: self,
+            guards: Vec::new(),
+        }
+    }
+}
+
+/// Guards acquired at different points of a unit of work, released together in reverse order
+/// when dropped, see [`MultiResourceLock::scope`].
+#[derive(Debug)]
+pub struct LockScope<'a> {
+    /// The lock instance.
+    lock: &'a MultiResourceLock,
+    /// The guards of the held locks, in the order they were acquired.
+    guards: Vec<MultiResourceGuard<'a>>,
+}
+
+impl<'a> LockScope<'a> {
+    /// Returns the guards held by the scope, in the order they were acquired.
+    #[inline]
+    #[must_use]
+    pub fn guards(&self) -> &[MultiResourceGuard<'a>] {
+        &self.guards
+    }
+
+    /// Calls [`MultiResourceLock::try_lock`], adding the acquired guard to the scope.
+    ///
+    /// Returns the identifier of the acquired lock.
+    ///
+    /// # Errors
+    ///
+    /// When [`MultiResourceLock::try_lock`] errors.
+    #[inline]
+    pub fn try_lock(
+        &mut self,
+        resources: &[String],
+        expiration: Duration,
+    ) -> RedisResult<Option<String>> {
+        let guard = self.lock.try_lock(resources, expiration)?;
+        Ok(self.add(guard))
+    }
+
+    /// Calls [`MultiResourceLock::lock`], adding the acquired guard to the scope.
+    ///
+    /// Returns the identifier of the acquired lock.
+    ///
+    /// # Errors
+    ///
+    /// When [`MultiResourceLock::lock`] errors.
+    #[inline]
+    pub fn lock(
+        &mut self,
+        resources: &[String],
+        expiration: Duration,
+        timeout: Duration,
+        sleep: Duration,
+    ) -> RedisResult<Option<String>> {
+        let guard = self.lock.lock(resources, expiration, timeout, sleep)?;
+        Ok(self.add(guard))
+    }
+
+    /// Adds the guard `acquired`, when the lock was acquired, to the scope.
+    fn add(&mut self, acquired: Option<MultiResourceGuard<'a>>) -> Option<String> {
+        let guard = acquired?;
+        let lock_id = guard.lock_id().to_owned();
+        self.guards.push(guard);
+        Some(lock_id)
+    }
+
+    /// Releases every lock of the scope, the most recently acquired first, regardless of the
+    /// [`DropPolicy`].
+    ///
+    /// Every lock is released even when releasing an earlier one errors.
+    ///
+    /// Returns the number of resources released.
+    ///
+    /// # Errors
+    ///
+    /// The first error of [`MultiResourceGuard::release`].
+    #[inline]
+    pub fn release_all(mut self) -> RedisResult<usize> {
+        let mut released = 0usize;
+        let mut first_error = None;
+        while let Some(guard) = self.guards.pop() {
+            match guard.release() {
+                Ok(outcome) => released = released.saturating_add(outcome.released()),
+                Err(err) => {
+                    first_error.get_or_insert(err);
+                }
+            }
+        }
+        first_error.map_or(Ok(released), Err)
+    }
+}
+
+impl Drop for LockScope<'_> {
+    /// Drops the guards the most recently acquired first, releasing them following the lock's
+    /// [`DropPolicy`].
+    #[inline]
+    fn drop(&mut self) {
+        while let Some(guard) = self.guards.pop() {
+            drop(guard);
+        }
+    }
+}
+
+/// A guard that releases the lock when it is dropped, or when closed with
+/// [`MultiResourceGuard::release`].
+pub struct MultiResourceGuard<'a> {
+    /// The lock instance.
+    lock: &'a MultiResourceLock,
+    /// The lock identifier.
+    lock_id: String,
+    /// The resources held by the lock.
+    resources: Vec<String>,
+    /// The connection taken by [`MultiResourceGuard::conn`], returned to the lock when dropped.
+    conn: Option<Connection>,
+    /// The metadata stored alongside the lock when it was acquired.
+    metadata: Option<String>,
+    /// Whether the guard was closed by [`MultiResourceGuard::release`] or
+    /// [`MultiResourceGuard::into_lock_id`], rather than dropped.
+    closed: bool,
+}
+
+impl std::fmt::Debug for MultiResourceGuard<'_> {
+    #[inline]
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MultiResourceGuard")
+            .field("lock", &self.lock)
+            .field("lock_id", &self.lock_id)
+            .field("resources", &self.resources)
+            .field("conn", &"..")
+            .field("metadata", &self.metadata)
+            .field("closed", &self.closed)
+            .finish()
+    }
+}
+
+impl MultiResourceGuard<'_> {
+    /// Returns the identifier of the held lock.
+    #[inline]
+    #[must_use]
+    pub fn lock_id(&self) -> &str {
+        &self.lock_id
+    }
+
+    /// Returns the resources held by the lock, each once.
+    #[inline]
+    #[must_use]
+    pub fn resources(&self) -> &[String] {
+        &self.resources
+    }
+
+    /// Returns the metadata stored alongside the lock, see
+    /// [`MultiResourceLock::lock_with_metadata`].
+    ///
+    /// This is read locally, so is `None` for guards adopted with
+    /// [`MultiResourceLock::guard_from_id`], see [`MultiResourceLock::lock_metadata`].
+    #[inline]
+    #[must_use]
+    pub fn metadata(&self) -> Option<&str> {
+        self.metadata.as_deref()
+    }
+
+    /// Checks whether the lock still holds every resource of the guard, see
+    /// [`MultiResourceLock::owns`].
+    ///
+    /// # Errors
+    ///
+    /// When [`MultiResourceLock::owns`] errors.
+    #[inline]
+    pub fn is_valid(&self) -> RedisResult<bool> {
+        self.lock.owns(&self.lock_id, &self.resources)
+    }
+
+    /// Renews the lock to expire `expiration` from now, erroring when it has been lost.
+    ///
+    /// Call this at the checkpoints of long work under the lock, to keep it held and to stop
+    /// before doing more work on a lock that expired, e.g. `guard.checkpoint(expiration)?;`.
+    ///
+    /// # Errors
+    ///
+    /// - When [`MultiResourceLock::try_extend`] errors.
+    /// - When the lock is no longer held on every resource.
+    #[inline]
+    pub fn checkpoint(&self, expiration: Duration) -> RedisResult<()> {
+        if self
+            .lock
+            .try_extend(&self.lock_id, &self.resources, expiration)?
+        {
+            return Ok(());
+        }
+        Err(RedisError::from((
+            ErrorKind::ClientError,
+            "Lock lost",
+            self.lock_id.clone(),
+        )))
+    }
+
+    /// Attempts to extend the lock to the `extra` resources, returning immediately if they cannot
+    /// be immediately acquired.
+    ///
+    /// The resources already held stay held throughout, so there is no window where another lock
+    /// can acquire them, and the expiration of the lock is unchanged.
+    ///
+    /// Returns `false` when an extra resource is held by another lock, or waited on by a queued
+    /// waiter.
+    ///
+    /// # Errors
+    ///
+    /// - When the `add_resources` function is missing from the Redis instance.
+    /// - When the lock is no longer held.
+    #[inline]
+    pub fn add_resources(&mut self, extra: &[String]) -> RedisResult<bool> {
+        let held = unique_resources(&self.resources, false)?.len();
+        let mut resources = self.resources.clone();
+        resources.extend_from_slice(extra);
+        let unique = unique_resources(&resources, false)?.into_owned();
+        let keys = self
+            .lock
+            .hash_tag
+            .keys(Cow::Borrowed(&unique), &self.lock.key_formatter)?;
+        let added: bool = self.lock.query(
+            fcall("add_resources", &self.lock.key_formatter)
+                .arg(&self.lock_id)
+                .arg(held)
+                .arg(&*keys),
+        )?;
+
+        if added {
+            self.resources = unique;
+        }
+        Ok(added)
+    }
+
+    /// Releases the `subset` of the held resources early, keeping the rest held until the guard
+    /// is dropped.
+    ///
+    /// Returns the number of resources released.
+    ///
+    /// # Errors
+    ///
+    /// - When the `release_resources` function is missing from the Redis instance.
+    /// - When a resource in `subset` is not held by the lock.
+    #[inline]
+    pub fn release_resources(&mut self, subset: &[String]) -> RedisResult<usize> {
+        let released = unique_resources(subset, false)?;
+        let remaining = self
+            .resources
+            .iter()
+            .filter(|resource| !released.contains(resource))
+            .cloned()
+            .collect::<Vec<_>>();
+        let mut resources = released.to_vec();
+        resources.extend_from_slice(&remaining);
+        let keys = self
+            .lock
+            .hash_tag
+            .keys(Cow::Owned(resources), &self.lock.key_formatter)?;
+        let cleared: usize = self.lock.query(
+            fcall("release_resources", &self.lock.key_formatter)
+                .arg(&self.lock_id)
+                .arg(released.len())
+                .arg(&*keys),
+        )?;
+
+        self.resources = remaining;
+        Ok(cleared)
+    }
+
+    /// Returns a connection to the Redis instance the lock is held on, taken from the idle
+    /// connections of the lock, so work under the lock does not open another connection.
+    ///
+    /// The connection is returned to the lock and the lock released on it when the guard is
+    /// dropped, so it must be left ready for further commands, e.g. not subscribed.
+    ///
+    /// # Errors
+    ///
+    /// When no connection is idle and opening one errors.
+    #[inline]
+    pub fn conn(&mut self) -> RedisResult<&mut Connection> {
+        let conn = match self.conn.take() {
+            Some(conn) => conn,
+            None => self.lock.checkout()?,
+        };
+        Ok(self.conn.insert(conn))
+    }
+
+    /// Consumes the guard without releasing the lock, returning the identifier of the held lock.
+    ///
+    /// The lock must then be released with [`MultiResourceLock::release`] or adopted again with
+    /// [`MultiResourceLock::guard_from_id`].
+    #[inline]
+    #[must_use]
+    pub fn into_lock_id(mut self) -> String {
+        self.closed = true;
+        std::mem::take(&mut self.lock_id)
+    }
+
+    /// Consumes the guard releasing the lock, regardless of the [`DropPolicy`].
+    ///
+    /// This closes the guard, releasing the lock deterministically and returning the outcome
+    /// where dropping it can only report errors to [`Metrics::on_release_error`]. In debug builds
+    /// a guard dropped rather than closed by this or [`MultiResourceGuard::into_lock_id`] logs a
+    /// warning with the `tracing` or `log` feature, to catch forgotten releases, while the
+    /// [`DropPolicy`] still applies.
+    ///
+    /// # Errors
+    ///
+    /// When [`MultiResourceLock::release_outcome`] errors.
+    #[inline]
+    pub fn release(mut self) -> RedisResult<ReleaseOutcome> {
+        self.closed = true;
+        if let Some(conn) = self.conn.take() {
+            self.lock.checkin(conn);
+        }
+        self.lock.release_outcome(&self.lock_id)
+    }
+
+    /// Takes what is needed to release the lock once the guard is gone, marking its resources as
+    /// poisoned for `poison` when set.
+    fn detached(&mut self, poison: Option<Duration>) -> DetachedRelease {
+        DetachedRelease {
+            client: self.lock.client.clone(),
+            metrics: Arc::clone(&self.lock.metrics),
+            keys: Arc::clone(&self.lock.key_formatter),
+            command_timeout: self.lock.command_timeout,
+            auto_setup: self.lock.auto_setup,
+            release_retries: self.lock.release_retries,
+            #[cfg(feature = "log")]
+            log_target: self.lock.log_target,
+            lock_id: std::mem::take(&mut self.lock_id),
+            poison,
+        }
+    }
 }
 
-#[expect(
-    clippy::unwrap_used,
-    reason = "You can't propagate errors in a `Drop` implementation."
-)]
 impl Drop for MultiResourceGuard<'_> {
+    /// Releases the lock following the lock's [`DropPolicy`], errors are reported to
+    /// [`Metrics::on_release_error`] rather than panicking, leaving the lock to expire.
+    ///
+    /// When dropped while panicking the resources are released poisoned, see
+    /// [`MultiResourceLock::release_poisoned`].
     #[inline]
     fn drop(&mut self) {
-        self.lock.release(&self.lock_id).unwrap();
+        // Returned first so the lock is released on it.
+        if let Some(conn) = self.conn.take() {
+            self.lock.checkin(conn);
+        }
+        if self.closed {
+            return;
+        }
+        #[cfg(all(debug_assertions, feature = "tracing"))]
+        tracing::warn!(lock_id = self.lock_id, "guard dropped without release");
+        #[cfg(all(debug_assertions, feature = "log"))]
+        log::warn!(
+            target: self.lock.log_target,
+            "guard of lock {} dropped without release",
+            self.lock_id
+        );
+        let poison = std::thread::panicking().then_some(self.lock.poison_ttl);
+        match self.lock.drop_policy {
+            DropPolicy::BlockOnHandle => {
+                #[cfg(feature = "tokio")]
+                if let Some(runtime) = current_thread_runtime() {
+                    runtime.spawn(self.detached(poison).release_async());
+                    return;
+                }
+                if let Err(error) = self.lock.release_marking(&self.lock_id, poison) {
+                    report_release_error(&*self.lock.metrics, &self.lock_id, &error);
+                }
+            }
+            DropPolicy::SpawnDetached => {
+                let detached = self.detached(poison);
+                std::thread::spawn(move || detached.release());
+            }
+            DropPolicy::LogAndSkip => {
+                #[cfg(feature = "tracing")]
+                tracing::warn!(lock_id = self.lock_id, "lock left to expire");
+            }
+            DropPolicy::Manual => {}
+        }
+    }
+}
+
+/// Returns the runtime the current thread is running when it is a current-thread tokio runtime.
+///
+/// Blocking its only thread on Redis would stall every other task on it, so guards dropped on it
+/// release their locks from a task instead.
+#[cfg(feature = "tokio")]
+fn current_thread_runtime() -> Option<tokio::runtime::Handle> {
+    tokio::runtime::Handle::try_current()
+        .ok()
+        .filter(|runtime| runtime.runtime_flavor() == tokio::runtime::RuntimeFlavor::CurrentThread)
+}
+
+/// Releases a lock after its guard is dropped, off the dropping thread.
+struct DetachedRelease {
+    /// The Redis client, used to open a connection.
+    client: Client,
+    /// The metrics hooks.
+    metrics: Arc<dyn Metrics>,
+    /// The naming of the Redis keys.
+    keys: Arc<LockKeys>,
+    /// The limit on each Redis call.
+    command_timeout: Option<Duration>,
+    /// Whether to load the Lua library when a function is missing.
+    auto_setup: bool,
+    /// How many times releasing is retried after a transient error.
+    release_retries: u32,
+    /// The target lock lifecycle events are logged to.
+    #[cfg(feature = "log")]
+    log_target: &'static str,
+    /// The lock to release.
+    lock_id: String,
+    /// How long to mark the resources as poisoned for, when set.
+    poison: Option<Duration>,
+}
+
+impl DetachedRelease {
+    /// Releases the lock on a new connection, blocking until Redis replies.
+    fn release(self) {
+        let released = retry_release(self.release_retries, &self.lock_id, || {
+            let mut conn = connect(&self.client, self.command_timeout)?;
+            release_on(
+                &mut conn,
+                &*self.metrics,
+                &self.keys,
+                self.auto_setup,
+                &self.lock_id,
+                self.poison,
+            )
+        });
+        self.report(released);
+    }
+
+    /// Releases the lock on a new async connection.
+    #[cfg(feature = "tokio")]
+    async fn release_async(self) {
+        let release_once = || async {
+            let mut conn = self.client.get_multiplexed_async_connection().await?;
+            let mut cmd = fcall("release_lock", &self.keys);
+            cmd.arg(&self.lock_id).arg(poison_args(self.poison));
+            let reply: (usize, bool) = match cmd.query_async(&mut conn).await {
+                Err(err) if self.auto_setup && is_missing_function(&err) => {
+                    load_library_async(&mut conn).await?;
+                    cmd.query_async(&mut conn).await?
+                }
+                reply => reply?,
+            };
+            let outcome = ReleaseOutcome::from_reply(reply);
+            report_release(&*self.metrics, &self.lock_id, outcome);
+            Ok(outcome)
+        };
+        let mut attempt = 0u32;
+        let released = loop {
+            let attempted = match self.command_timeout {
+                Some(limit) => tokio::time::timeout(limit, release_once())
+                    .await
+                    .unwrap_or_else(|_elapsed| Err(command_timeout())),
+                None => release_once().await,
+            };
+            match attempted {
+                Err(err) if attempt < self.release_retries && is_transient(&err) => {
+                    #[cfg(feature = "tracing")]
+                    tracing::debug!(lock_id = self.lock_id, %err, "retrying release after transient error");
+                    attempt = attempt.saturating_add(1);
+                    tokio::time::sleep(RELEASE_RETRY_SLEEP).await;
+                }
+                result => break result,
+            }
+        };
+        self.report(released);
+    }
+
+    /// Reports the result of releasing the lock.
+    fn report(&self, released: RedisResult<ReleaseOutcome>) {
+        match released {
+            #[cfg_attr(
+                not(feature = "log"),
+                expect(unused_variables, reason = "Only logged.")
+            )]
+            Ok(outcome) => {
+                #[cfg(feature = "log")]
+                log_release(self.log_target, &self.lock_id, outcome);
+            }
+            Err(error) => report_release_error(&*self.metrics, &self.lock_id, &error),
+        }
+    }
+}
+
+/// Opens a connection to `client` with calls bounded by `command_timeout` when set.
+fn connect(client: &Client, command_timeout: Option<Duration>) -> RedisResult<Connection> {
+    let Some(timeout) = command_timeout else {
+        return client.get_connection();
+    };
+    let conn = client.get_connection_with_timeout(timeout)?;
+    conn.set_read_timeout(Some(timeout))?;
+    conn.set_write_timeout(Some(timeout))?;
+    Ok(conn)
+}
+
+/// Runs `cmd` on `conn`, loading the Lua library and retrying once when a function is missing and
+/// `auto_setup` is set.
+fn query_on<T: FromRedisValue>(
+    conn: &mut Connection,
+    cmd: &Cmd,
+    auto_setup: bool,
+) -> RedisResult<T> {
+    match cmd.query(conn) {
+        Err(err) if auto_setup && is_missing_function(&err) => {
+            #[cfg(feature = "tracing")]
+            tracing::warn!(library = library_name(), "loading missing Lua library");
+            load_library(conn)?;
+            cmd.query(conn)
+        }
+        result => result,
+    }
+}
+
+/// Releases the lock `lock_id` on `conn`, reporting it to `metrics`, marking its resources as
+/// poisoned for `poison` when set.
+fn release_on(
+    conn: &mut Connection,
+    metrics: &dyn Metrics,
+    keys: &LockKeys,
+    auto_setup: bool,
+    lock_id: &str,
+    poison: Option<Duration>,
+) -> RedisResult<ReleaseOutcome> {
+    let reply: (usize, bool) = query_on(
+        conn,
+        fcall("release_lock", keys)
+            .arg(lock_id)
+            .arg(poison_args(poison)),
+        auto_setup,
+    )?;
+    let outcome = ReleaseOutcome::from_reply(reply);
+    report_release(metrics, lock_id, outcome);
+    Ok(outcome)
+}
+
+/// Calls `release` to release the lock `lock_id`, calling it again up to `retries` times after a
+/// transient error.
+#[cfg_attr(
+    not(feature = "tracing"),
+    expect(unused_variables, reason = "Only traced.")
+)]
+fn retry_release<F: FnMut() -> RedisResult<ReleaseOutcome>>(
+    retries: u32,
+    lock_id: &str,
+    mut release: F,
+) -> RedisResult<ReleaseOutcome> {
+    let mut attempt = 0u32;
+    loop {
+        match release() {
+            Err(err) if attempt < retries && is_transient(&err) => {
+                #[cfg(feature = "tracing")]
+                tracing::debug!(lock_id, %err, "retrying release after transient error");
+                attempt = attempt.saturating_add(1);
+                std::thread::sleep(RELEASE_RETRY_SLEEP);
+            }
+            released => return released,
+        }
     }
 }
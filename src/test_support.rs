@@ -0,0 +1,93 @@
+use super::{setup, MultiResourceLock};
+use redis::Client;
+use std::error::Error;
+use testcontainers::core::{IntoContainerPort as _, WaitFor};
+use testcontainers::runners::SyncRunner as _;
+use testcontainers::{Container, GenericImage};
+
+/// The Redis image [`TestRedis::start`] runs.
+const IMAGE: (&str, &str) = ("redis", "7.4-alpine");
+
+/// The port Redis listens on within the container.
+const PORT: u16 = 6379;
+
+/// An ephemeral Redis server for integration tests, removed when dropped.
+///
+/// Runs Redis in a container with [`testcontainers`], so each test can have its own server rather
+/// than sharing one at `redis://127.0.0.1/`. Like [`Container`] it is started and dropped outside
+/// of an async runtime.
+///
+/// ```no_run
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let redis = redis_lock::test_support::TestRedis::start()?;
+/// tokio::runtime::Runtime::new()?.block_on(async {
+///     let lock = redis.lock().await?;
+///     let lock_id = lock.try_acquire_default(&[String::from("a")]).await?;
+///     # Ok::<_, Box<dyn std::error::Error>>(())
+/// })?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct TestRedis {
+    /// The container running the server, removed when dropped.
+    #[expect(dead_code, reason = "It is only held to be dropped with the server.")]
+    container: Container<GenericImage>,
+    /// The URL of the server.
+    url: String,
+    /// A client connecting to the server.
+    client: Client,
+}
+
+impl TestRedis {
+    /// Starts a server, waiting until it accepts connections.
+    ///
+    /// # Errors
+    ///
+    /// - When the container cannot be started, e.g. Docker is not running.
+    /// - When the port of the server cannot be found.
+    #[inline]
+    pub fn start() -> Result<Self, Box<dyn Error>> {
+        let (name, tag) = IMAGE;
+        let container = GenericImage::new(name, tag)
+            .with_exposed_port(PORT.tcp())
+            .with_wait_for(WaitFor::message_on_stdout("Ready to accept connections"))
+            .start()?;
+        let url = format!(
+            "redis://{}:{}/",
+            container.get_host()?,
+            container.get_host_port_ipv4(PORT)?
+        );
+        let client = Client::open(url.as_str())?;
+        Ok(TestRedis {
+            container,
+            url,
+            client,
+        })
+    }
+
+    /// Returns the URL of the server, e.g. for other processes to connect to it.
+    #[inline]
+    #[must_use]
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Returns a client connecting to the server.
+    #[inline]
+    #[must_use]
+    pub fn client(&self) -> &Client {
+        &self.client
+    }
+
+    /// Loads the Lua library, as by [`setup`], returning a lock on the server.
+    ///
+    /// # Errors
+    ///
+    /// When [`setup`] or [`MultiResourceLock::new`] error.
+    #[inline]
+    pub async fn lock(&self) -> Result<MultiResourceLock, Box<dyn Error>> {
+        setup(&self.client).await?;
+        Ok(MultiResourceLock::new(self.client.clone())?)
+    }
+}
@@ -0,0 +1,218 @@
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Condvar, Mutex, OnceLock, PoisonError};
+use std::task::{Context, Poll, Waker};
+use std::time::{Duration, Instant};
+
+/// The clock used by [`MultiResourceLock::acquire`](crate::MultiResourceLock::acquire) to measure
+/// the timeout and wait between attempts.
+///
+/// This allows the lock to be used on any executor, and a mock clock to be used to test timeouts
+/// without waiting for them.
+pub trait Clock: Send + Sync {
+    /// Returns the current time.
+    fn now(&self) -> Instant;
+    /// Returns a future that completes after `duration`.
+    fn sleep(&self, duration: Duration) -> Pin<Box<dyn Future<Output = ()> + Send>>;
+}
+
+/// Returns the clock used by default, [`TokioClock`] when the `tokio` feature is enabled and
+/// [`ThreadClock`] otherwise.
+pub(crate) fn default_clock() -> Arc<dyn Clock> {
+    #[cfg(feature = "tokio")]
+    return Arc::new(TokioClock);
+    #[cfg(not(feature = "tokio"))]
+    return Arc::new(ThreadClock);
+}
+
+/// Uses tokio's clock, used by default when the `tokio` feature is enabled.
+///
+/// This respects [`tokio::time::pause`].
+#[cfg(feature = "tokio")]
+#[cfg_attr(docsrs, doc(cfg(feature = "tokio")))]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TokioClock;
+
+#[cfg(feature = "tokio")]
+impl Clock for TokioClock {
+    #[inline]
+    fn now(&self) -> Instant {
+        tokio::time::Instant::now().into_std()
+    }
+
+    #[inline]
+    fn sleep(&self, duration: Duration) -> Pin<Box<dyn Future<Output = ()> + Send>> {
+        Box::pin(tokio::time::sleep(duration))
+    }
+}
+
+/// Uses the system clock and sleeps by waking the future from a helper thread, used by default
+/// when the `tokio` feature is disabled.
+///
+/// This works on any executor, every sleep is woken by one helper thread shared by the process,
+/// prefer an implementation of [`Clock`] for your executor's own timer.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ThreadClock;
+
+impl Clock for ThreadClock {
+    #[inline]
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    #[inline]
+    fn sleep(&self, duration: Duration) -> Pin<Box<dyn Future<Output = ()> + Send>> {
+        Box::pin(ThreadSleepFuture {
+            deadline: Instant::now().checked_add(duration),
+            id: None,
+        })
+    }
+}
+
+/// The sleeps waiting on the timer thread, see [`timers`].
+#[derive(Debug, Default)]
+struct Timers {
+    /// The wakers of the pending sleeps, by deadline and identifier.
+    pending: Mutex<TimerState>,
+    /// Notified when a sleep is added so the timer thread rechecks the earliest deadline.
+    added: Condvar,
+}
+
+/// The state of [`Timers`].
+#[derive(Debug, Default)]
+struct TimerState {
+    /// The identifier of the next sleep.
+    next_id: u64,
+    /// The wakers of the pending sleeps, by deadline and identifier.
+    wakers: BTreeMap<(Instant, u64), Waker>,
+}
+
+/// Returns the sleeps of [`ThreadClock`], starting the thread waking them on first use.
+fn timers() -> &'static Timers {
+    static TIMERS: OnceLock<&'static Timers> = OnceLock::new();
+    TIMERS.get_or_init(|| {
+        let timers: &'static Timers = Box::leak(Box::default());
+        std::thread::spawn(move || timers.run());
+        timers
+    })
+}
+
+impl Timers {
+    /// Wakes each sleep once its deadline passes, forever.
+    fn run(&self) -> ! {
+        let mut state = self.pending.lock().unwrap_or_else(PoisonError::into_inner);
+        loop {
+            let now = Instant::now();
+            while let Some(entry) = state.wakers.first_entry() {
+                if entry.key().0 > now {
+                    break;
+                }
+                entry.remove().wake();
+            }
+            state = match state.wakers.keys().next() {
+                Some(&(deadline, _)) => {
+                    self.added
+                        .wait_timeout(state, deadline.saturating_duration_since(now))
+                        .unwrap_or_else(PoisonError::into_inner)
+                        .0
+                }
+                None => self
+                    .added
+                    .wait(state)
+                    .unwrap_or_else(PoisonError::into_inner),
+            };
+        }
+    }
+}
+
+/// The future returned by [`ThreadClock::sleep`].
+#[derive(Debug)]
+struct ThreadSleepFuture {
+    /// When the sleep completes, never when the duration overflows.
+    deadline: Option<Instant>,
+    /// The identifier of the sleep with the timer thread, set once registered.
+    id: Option<u64>,
+}
+
+impl Future for ThreadSleepFuture {
+    type Output = ();
+
+    #[inline]
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let Some(deadline) = self.deadline else {
+            return Poll::Pending;
+        };
+        if Instant::now() >= deadline {
+            return Poll::Ready(());
+        }
+        let timers = timers();
+        let mut state = timers
+            .pending
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        let id = *self.id.get_or_insert_with(|| {
+            let id = state.next_id;
+            state.next_id = id.wrapping_add(1);
+            id
+        });
+        state.wakers.insert((deadline, id), cx.waker().clone());
+        timers.added.notify_one();
+        Poll::Pending
+    }
+}
+
+impl Drop for ThreadSleepFuture {
+    /// Removes the sleep from the timer thread, so cancelled sleeps are not held until they would
+    /// have completed.
+    #[inline]
+    fn drop(&mut self) {
+        if let (Some(deadline), Some(id)) = (self.deadline, self.id) {
+            timers()
+                .pending
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .wakers
+                .remove(&(deadline, id));
+        }
+    }
+}
+
+/// A limit in real time on the default clock, started once and shared by every step it bounds.
+///
+/// The default clock is used rather than a configured one as mock clocks complete sleeps
+/// immediately.
+pub(crate) struct Deadline {
+    /// The sleep completing at the limit, `None` when there is no limit.
+    sleep: Option<Pin<Box<dyn Future<Output = ()> + Send>>>,
+}
+
+impl Deadline {
+    /// Starts a deadline `duration` from now, never passing when `None`.
+    pub(crate) fn after(duration: Option<Duration>) -> Self {
+        Deadline {
+            sleep: duration.map(|limit| default_clock().sleep(limit)),
+        }
+    }
+
+    /// Runs `future` until the deadline, returning `None` when it does not complete in time.
+    pub(crate) async fn run<F: Future>(&mut self, future: F) -> Option<F::Output> {
+        let mut pinned = std::pin::pin!(future);
+        std::future::poll_fn(|cx| {
+            if let Poll::Ready(output) = pinned.as_mut().poll(cx) {
+                return Poll::Ready(Some(output));
+            }
+            match &mut self.sleep {
+                Some(sleep) => sleep.as_mut().poll(cx).map(|()| None),
+                None => Poll::Pending,
+            }
+        })
+        .await
+    }
+}
+
+/// Runs `future` for up to `duration` of real time on the default clock, returning `None` when it
+/// does not complete in time.
+pub(crate) async fn timeout<F: Future>(duration: Duration, future: F) -> Option<F::Output> {
+    Deadline::after(Some(duration)).run(future).await
+}
@@ -0,0 +1,55 @@
+use super::INDEFINITE;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, SystemTime};
+
+/// A held lock that can be passed between processes.
+///
+/// E.g. a lock acquired by a coordinator can be serialized (behind the `serde` feature) and sent
+/// to a worker which calls [`MultiResourceLock::release`](crate::MultiResourceLock::release) with
+/// [`LockToken::lock_id`] once done.
+///
+/// The expiry is recorded with the system clock so [`LockToken::remaining`] is only as accurate
+/// as the clocks of the sending and receiving machines are in sync, use
+/// [`MultiResourceLock::is_held`](crate::MultiResourceLock::is_held) to confirm the lock is
+/// still held.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct LockToken {
+    /// The lock identifier.
+    pub lock_id: String,
+    /// The locked resources.
+    pub resources: Vec<String>,
+    /// When the lock expires, `None` when it is [`INDEFINITE`].
+    pub expires_at: Option<SystemTime>,
+}
+
+impl LockToken {
+    /// Create a token for the lock `lock_id` on `resources` acquired now with `expiration`.
+    #[inline]
+    #[must_use]
+    pub fn new(lock_id: String, resources: Vec<String>, expiration: Duration) -> Self {
+        let now = SystemTime::now();
+        LockToken {
+            lock_id,
+            resources,
+            expires_at: (expiration != INDEFINITE)
+                .then(|| now.checked_add(expiration).unwrap_or(now)),
+        }
+    }
+
+    /// Returns how long until the lock expires, `None` when it has already expired.
+    ///
+    /// Returns [`Duration::MAX`] when the lock is [`INDEFINITE`].
+    #[inline]
+    #[must_use]
+    pub fn remaining(&self) -> Option<Duration> {
+        let Some(expires_at) = self.expires_at else {
+            return Some(Duration::MAX);
+        };
+        expires_at
+            .duration_since(SystemTime::now())
+            .ok()
+            .filter(|remaining| !remaining.is_zero())
+    }
+}
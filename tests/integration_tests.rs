@@ -26,13 +26,12 @@ const FIVE: &str = env!("CARGO_BIN_EXE_five");
 #[test]
 #[serial]
 fn two() -> Result<(), Box<dyn Error>> {
+    let redis = redis_lock::test_support::TestRedis::start()?;
     tokio::runtime::Runtime::new()?.block_on(async {
         const N: usize = 10;
-        let redis_url = "redis://127.0.0.1/";
-        let client = Client::open(redis_url)?;
+        let client = redis.client().clone();
         let mut conn = client.get_multiplexed_async_connection().await?;
         // Initialize account balances.
-        redis::cmd("FLUSHALL").exec_async(&mut conn).await?;
         conn.set::<_, _, ()>("account1", 1000i32).await?;
         conn.set::<_, _, ()>("account2", 1000i32).await?;
         conn.set::<_, _, ()>("account3", 1000i32).await?;
@@ -42,6 +41,7 @@ fn two() -> Result<(), Box<dyn Error>> {
         let threes = (0..N)
             .map(|_| {
                 tokio::process::Command::new(THREE)
+                    .env("REDIS_URL", redis.url())
                     .stdout(Stdio::piped())
                     .stderr(Stdio::piped())
                     .spawn()
@@ -50,6 +50,7 @@ fn two() -> Result<(), Box<dyn Error>> {
         let fours = (0..N)
             .map(|_| {
                 tokio::process::Command::new(FOUR)
+                    .env("REDIS_URL", redis.url())
                     .stdout(Stdio::piped())
                     .stderr(Stdio::piped())
                     .spawn()
@@ -58,6 +59,7 @@ fn two() -> Result<(), Box<dyn Error>> {
         let fives = (0..N)
             .map(|_| {
                 tokio::process::Command::new(FIVE)
+                    .env("REDIS_URL", redis.url())
                     .stdout(Stdio::piped())
                     .stderr(Stdio::piped())
                     .spawn()
@@ -105,11 +107,10 @@ fn two() -> Result<(), Box<dyn Error>> {
 fn one() -> Result<(), Box<dyn Error>> {
     const N: usize = 10;
 
-    let redis_url = "redis://127.0.0.1/";
-    let client = Client::open(redis_url)?;
+    let redis = redis_lock::test_support::TestRedis::start()?;
+    let client = redis.client().clone();
     let mut conn = client.get_connection()?;
     // Initialize account balances.
-    redis::cmd("FLUSHALL").exec(&mut conn)?;
     conn.set::<_, _, ()>("account1", 1000i32)?;
     conn.set::<_, _, ()>("account2", 1000i32)?;
     conn.set::<_, _, ()>("account3", 1000i32)?;
@@ -119,6 +120,7 @@ fn one() -> Result<(), Box<dyn Error>> {
     let ones = (0..N)
         .map(|_| {
             std::process::Command::new(ONE)
+                .env("REDIS_URL", redis.url())
                 .stdout(Stdio::piped())
                 .stderr(Stdio::piped())
                 .spawn()
@@ -127,6 +129,7 @@ fn one() -> Result<(), Box<dyn Error>> {
     let twos = (0..N)
         .map(|_| {
             std::process::Command::new(TWO)
+                .env("REDIS_URL", redis.url())
                 .stdout(Stdio::piped())
                 .stderr(Stdio::piped())
                 .spawn()
@@ -154,3 +157,3671 @@ fn one() -> Result<(), Box<dyn Error>> {
         Err("Total balance is not 3000".into())
     }
 }
+
+#[expect(
+    clippy::panic_in_result_fn,
+    reason = "It's annoying to handle the error here."
+)]
+#[expect(clippy::tests_outside_test_module, reason = "`#[serial]` breaks it")] // TODO Fix this.
+#[test]
+#[serial]
+fn force_release() -> Result<(), Box<dyn Error>> {
+    let redis = redis_lock::test_support::TestRedis::start()?;
+    tokio::runtime::Runtime::new()?.block_on(async {
+        let lock = redis.lock().await?;
+
+        let resources = vec![String::from("a"), String::from("b")];
+        let lock_id = lock
+            .try_acquire_default(&resources)
+            .await?
+            .ok_or("contended")?;
+        // Breaking a single resource leaves the rest of the lock held.
+        assert_eq!(lock.force_release("a").await?, 1);
+        assert_eq!(lock.force_release("a").await?, 0);
+        assert!(lock.try_acquire_default(&resources).await?.is_none());
+        // Breaking the lock clears its remaining keys.
+        assert_eq!(lock.force_release_lock(&lock_id).await?, 1);
+        assert!(lock.try_acquire_default(&resources).await?.is_some());
+        Ok(())
+    })
+}
+
+#[expect(
+    clippy::panic_in_result_fn,
+    reason = "It's annoying to handle the error here."
+)]
+#[expect(clippy::tests_outside_test_module, reason = "`#[serial]` breaks it")] // TODO Fix this.
+#[test]
+#[serial]
+fn release_by_resources() -> Result<(), Box<dyn Error>> {
+    let redis = redis_lock::test_support::TestRedis::start()?;
+    tokio::runtime::Runtime::new()?.block_on(async {
+        let lock = redis.lock().await?;
+
+        lock.try_acquire_default(&[String::from("a"), String::from("b")])
+            .await?
+            .ok_or("contended")?;
+        let other = lock
+            .try_acquire_default(&[String::from("c")])
+            .await?
+            .ok_or("contended")?;
+        // Clears the resources of both locks without their ids, skipping unheld resources.
+        let resources = [String::from("a"), String::from("c"), String::from("d")];
+        assert_eq!(lock.release_by_resources(&resources).await?, 2);
+        assert_eq!(lock.release_by_resources(&resources).await?, 0);
+        assert!(!lock.is_held(&other).await?);
+        // Resources outside the set remain held.
+        assert!(lock
+            .try_acquire_default(&[String::from("b")])
+            .await?
+            .is_none());
+        assert!(lock.try_acquire_default(&resources).await?.is_some());
+        Ok(())
+    })
+}
+
+#[expect(
+    clippy::panic_in_result_fn,
+    reason = "It's annoying to handle the error here."
+)]
+#[expect(clippy::tests_outside_test_module, reason = "`#[serial]` breaks it")] // TODO Fix this.
+#[test]
+#[serial]
+fn compact_index() -> Result<(), Box<dyn Error>> {
+    use redis_lock::metrics::LockStats;
+
+    let redis = redis_lock::test_support::TestRedis::start()?;
+    tokio::runtime::Runtime::new()?.block_on(async {
+        let client = redis.client().clone();
+        let mut conn = client.get_multiplexed_async_connection().await?;
+        let lock = redis.lock().await?;
+
+        lock.try_acquire_default(&[String::from("a"), String::from("b")])
+            .await?
+            .ok_or("contended")?;
+        lock.try_acquire_default(&[String::from("c")])
+            .await?
+            .ok_or("contended")?;
+        // Keys removed behind the lock's back leave their entries in the index.
+        conn.del::<_, ()>(&["lock:a", "lock:b"]).await?;
+        assert_eq!(lock.stats().await?.active_locks, 2);
+        assert_eq!(lock.compact_index().await?, 1);
+        assert_eq!(lock.compact_index().await?, 0);
+        assert_eq!(
+            lock.stats().await?,
+            LockStats {
+                active_locks: 1,
+                locked_resources: 1,
+            }
+        );
+
+        // Locks acquired without maintaining the index are not counted.
+        let unindexed = redis_lock::MultiResourceLock::new(client.clone())?.with_index(false);
+        let lock_id = unindexed
+            .try_acquire_default(&[String::from("d")])
+            .await?
+            .ok_or("contended")?;
+        assert_eq!(lock.stats().await?.active_locks, 1);
+        // Nor are their contended attempts.
+        assert_eq!(
+            unindexed.try_acquire_default(&[String::from("d")]).await?,
+            None
+        );
+        assert!(!conn.exists::<_, bool>("index:attempts:d").await?);
+        assert_eq!(unindexed.release(&lock_id).await?, 1);
+        Ok(())
+    })
+}
+
+#[expect(
+    clippy::panic_in_result_fn,
+    reason = "It's annoying to handle the error here."
+)]
+#[expect(clippy::tests_outside_test_module, reason = "`#[serial]` breaks it")] // TODO Fix this.
+#[test]
+#[serial]
+fn lock_scope() -> Result<(), Box<dyn Error>> {
+    let redis = redis_lock::test_support::TestRedis::start()?;
+    tokio::runtime::Runtime::new()?.block_on(async {
+        let client = redis.client().clone();
+        let mut conn = client.get_multiplexed_async_connection().await?;
+        let lock = redis.lock().await?;
+
+        let mut scope = lock.scope();
+        let first = scope
+            .try_acquire(
+                &[String::from("a"), String::from("b")],
+                redis_lock::DEFAULT_EXPIRATION,
+            )
+            .await?
+            .ok_or("contended")?;
+        let second = scope
+            .try_acquire(&[String::from("c")], redis_lock::DEFAULT_EXPIRATION)
+            .await?
+            .ok_or("contended")?;
+        assert_eq!(scope.lock_ids(), [first, second]);
+        assert_eq!(scope.release_all().await?, 3);
+        assert!(!conn.exists::<_, bool>("lock:a").await?);
+        assert!(!conn.exists::<_, bool>("lock:c").await?);
+        Ok(())
+    })
+}
+
+#[expect(
+    clippy::panic_in_result_fn,
+    reason = "It's annoying to handle the error here."
+)]
+#[expect(clippy::tests_outside_test_module, reason = "`#[serial]` breaks it")] // TODO Fix this.
+#[test]
+#[serial]
+fn overlap_matches_lua() -> Result<(), Box<dyn Error>> {
+    use rand::rngs::StdRng;
+    use rand::seq::SliceRandom as _;
+    use rand::{Rng as _, SeedableRng as _};
+    use redis_lock::overlap::plan;
+
+    const RESOURCES: [&str; 6] = ["a", "b", "c", "d", "e", "f"];
+
+    /// Picks between one and three distinct resources.
+    fn resource_set(rng: &mut StdRng) -> Vec<String> {
+        let count = rng.gen_range(1usize..=3usize);
+        RESOURCES
+            .choose_multiple(rng, count)
+            .map(|resource| String::from(*resource))
+            .collect()
+    }
+
+    let redis = redis_lock::test_support::TestRedis::start()?;
+    tokio::runtime::Runtime::new()?.block_on(async {
+        let client = redis.client().clone();
+        let mut conn = client.get_multiplexed_async_connection().await?;
+        let lock = redis_lock::MultiResourceLock::new(client.clone())?;
+        let mut rng = StdRng::seed_from_u64(365);
+
+        for _ in 0u32..50u32 {
+            redis::cmd("FLUSHALL").exec_async(&mut conn).await?;
+            redis_lock::setup(&client).await?;
+            let mut held = Vec::new();
+            for _ in 0u32..rng.gen_range(0u32..3u32) {
+                let resources = resource_set(&mut rng);
+                if lock.try_acquire_default(&resources).await?.is_some() {
+                    held.push(resources);
+                }
+            }
+            let requested = (0u32..rng.gen_range(1u32..5u32))
+                .map(|_| resource_set(&mut rng))
+                .collect::<Vec<_>>();
+
+            let mut granted = Vec::new();
+            for resources in &requested {
+                granted.push(lock.try_acquire_default(resources).await?.is_some());
+            }
+            assert_eq!(
+                plan(&held, &requested),
+                granted,
+                "held {held:?}, requested {requested:?}"
+            );
+        }
+        Ok(())
+    })
+}
+
+#[cfg(feature = "prometheus")]
+#[expect(
+    clippy::panic_in_result_fn,
+    reason = "It's annoying to handle the error here."
+)]
+#[expect(clippy::tests_outside_test_module, reason = "`#[serial]` breaks it")] // TODO Fix this.
+#[test]
+#[serial]
+fn prometheus_metrics() -> Result<(), Box<dyn Error>> {
+    use prometheus::TextEncoder;
+    use redis_lock::metrics::Metrics as _;
+    use redis_lock::prometheus::PrometheusMetrics;
+    use std::time::Duration;
+
+    let metrics = PrometheusMetrics::new()?;
+    let registry = prometheus::Registry::new();
+    metrics.register(&registry)?;
+    let many = (0u32..5u32).map(|i| format!("r{i}")).collect::<Vec<_>>();
+    metrics.on_acquired_resources(&[String::from("a")], Duration::from_millis(20));
+    metrics.on_acquired_resources(&many, Duration::from_secs(20));
+    metrics.on_acquired_resources(&[String::from("b")], Duration::from_millis(20));
+    metrics.on_release();
+    // Released after it expired, so no longer active either.
+    metrics.on_release_expired();
+    metrics.on_timeout();
+    let mut rendered = String::new();
+    TextEncoder::new().encode_utf8(&registry.gather(), &mut rendered)?;
+    assert_eq!(rendered, metrics.to_string());
+    for line in [
+        "redis_lock_acquire_total{resources=\"1\"} 2",
+        "redis_lock_acquire_total{resources=\"16\"} 1",
+        "redis_lock_acquire_wait_seconds_bucket{resources=\"1\",le=\"0.01\"} 0",
+        "redis_lock_acquire_wait_seconds_bucket{resources=\"1\",le=\"0.025\"} 2",
+        "redis_lock_acquire_wait_seconds_bucket{resources=\"16\",le=\"10\"} 0",
+        "redis_lock_acquire_wait_seconds_bucket{resources=\"16\",le=\"+Inf\"} 1",
+        "redis_lock_acquire_wait_seconds_sum{resources=\"16\"} 20",
+        "redis_lock_acquire_wait_seconds_count{resources=\"1\"} 2",
+        "redis_lock_timeouts_total 1",
+        "redis_lock_active 1",
+    ] {
+        assert!(
+            rendered.lines().any(|rendered_line| rendered_line == line),
+            "{line}"
+        );
+    }
+    Ok(())
+}
+
+#[expect(
+    clippy::panic_in_result_fn,
+    reason = "It's annoying to handle the error here."
+)]
+#[expect(clippy::tests_outside_test_module, reason = "`#[serial]` breaks it")] // TODO Fix this.
+#[test]
+#[serial]
+fn indefinite() -> Result<(), Box<dyn Error>> {
+    use std::time::Duration;
+
+    let redis = redis_lock::test_support::TestRedis::start()?;
+    tokio::runtime::Runtime::new()?.block_on(async {
+        let client = redis.client().clone();
+        let mut conn = client.get_multiplexed_async_connection().await?;
+        let lock = redis.lock().await?;
+
+        lock.try_acquire(&[String::from("a")], redis_lock::INDEFINITE)
+            .await?
+            .ok_or("contended")?;
+        assert_eq!(conn.pttl::<_, i64>("lock:a").await?, -1);
+        lock.try_acquire(&[String::from("b")], Duration::from_secs(10))
+            .await?
+            .ok_or("contended")?;
+        let ttl = conn.pttl::<_, i64>("lock:b").await?;
+        assert!(ttl > 0 && ttl <= 10_000);
+        Ok(())
+    })
+}
+
+#[expect(
+    clippy::panic_in_result_fn,
+    reason = "It's annoying to handle the error here."
+)]
+#[expect(clippy::tests_outside_test_module, reason = "`#[serial]` breaks it")] // TODO Fix this.
+#[test]
+#[serial]
+fn expiring_resources() -> Result<(), Box<dyn Error>> {
+    use std::time::Duration;
+
+    let redis = redis_lock::test_support::TestRedis::start()?;
+    tokio::runtime::Runtime::new()?.block_on(async {
+        let client = redis.client().clone();
+        let mut conn = client.get_multiplexed_async_connection().await?;
+        let lock = redis.lock().await?;
+
+        let lock_id = lock
+            .try_acquire_expiring(&[
+                (String::from("a"), Duration::from_millis(200)),
+                (String::from("b"), Duration::from_secs(10)),
+                (String::from("c"), redis_lock::INDEFINITE),
+            ])
+            .await?
+            .ok_or("contended")?;
+        assert!(conn.pttl::<_, i64>("lock:a").await? <= 200);
+        assert!(conn.pttl::<_, i64>("lock:b").await? > 200);
+        assert_eq!(conn.pttl::<_, i64>("lock:c").await?, -1);
+
+        // The shortest-lived resource is released first, the rest remain held.
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        assert!(lock
+            .try_acquire_default(&[String::from("a")])
+            .await?
+            .is_some());
+        assert!(lock
+            .try_acquire_default(&[String::from("b")])
+            .await?
+            .is_none());
+        assert!(lock.is_held(&lock_id).await?);
+
+        // A resource cannot be given two expirations.
+        let duplicated = lock
+            .try_acquire_expiring(&[
+                (String::from("d"), Duration::from_secs(1)),
+                (String::from("d"), Duration::from_secs(2)),
+            ])
+            .await;
+        assert!(duplicated.is_err_and(|err| err.detail() == Some("d")));
+        Ok(())
+    })
+}
+
+#[expect(
+    clippy::panic_in_result_fn,
+    reason = "It's annoying to handle the error here."
+)]
+#[expect(clippy::tests_outside_test_module, reason = "`#[serial]` breaks it")] // TODO Fix this.
+#[test]
+#[serial]
+fn expiration_jitter() -> Result<(), Box<dyn Error>> {
+    use std::time::Duration;
+
+    let redis = redis_lock::test_support::TestRedis::start()?;
+    tokio::runtime::Runtime::new()?.block_on(async {
+        let client = redis.client().clone();
+        let mut conn = client.get_multiplexed_async_connection().await?;
+        let lock = redis.lock().await?.with_expiration_jitter(0.5);
+
+        for resource in ["a", "b", "c", "d"] {
+            lock.try_acquire(&[String::from(resource)], Duration::from_secs(10))
+                .await?
+                .ok_or("contended")?;
+            let ttl = conn.pttl::<_, i64>(format!("lock:{resource}")).await?;
+            assert!((4_000..=15_000).contains(&ttl));
+        }
+        // Indefinite locks are never given an expiration.
+        lock.try_acquire(&[String::from("e")], redis_lock::INDEFINITE)
+            .await?
+            .ok_or("contended")?;
+        assert_eq!(conn.pttl::<_, i64>("lock:e").await?, -1);
+
+        // Full jitter on a tiny expiration never rounds it down to indefinite.
+        let full = redis_lock::MultiResourceLock::new(client)?.with_expiration_jitter(1.0);
+        for index in 0u32..20u32 {
+            let resource = format!("full-{index}");
+            full.try_acquire(std::slice::from_ref(&resource), Duration::from_nanos(1))
+                .await?
+                .ok_or("contended")?;
+            assert_ne!(conn.pttl::<_, i64>(format!("lock:{resource}")).await?, -1);
+        }
+        Ok(())
+    })
+}
+
+#[expect(
+    clippy::panic_in_result_fn,
+    reason = "It's annoying to handle the error here."
+)]
+#[expect(clippy::tests_outside_test_module, reason = "`#[serial]` breaks it")] // TODO Fix this.
+#[test]
+#[serial]
+fn try_extend() -> Result<(), Box<dyn Error>> {
+    use std::time::Duration;
+
+    let redis = redis_lock::test_support::TestRedis::start()?;
+    tokio::runtime::Runtime::new()?.block_on(async {
+        let client = redis.client().clone();
+        let mut conn = client.get_multiplexed_async_connection().await?;
+        let lock = redis.lock().await?;
+
+        let resources = vec![String::from("a"), String::from("b")];
+        let lock_id = lock
+            .try_acquire(&resources, Duration::from_millis(100))
+            .await?
+            .ok_or("contended")?;
+        assert!(
+            lock.try_extend(&lock_id, &resources, Duration::from_secs(10))
+                .await?
+        );
+        assert!(conn.pttl::<_, i64>("lock:a").await? > 5_000);
+        // Another lock cannot extend it.
+        assert!(
+            !lock
+                .try_extend("other", &resources, Duration::from_secs(10))
+                .await?
+        );
+        lock.release(&lock_id).await?;
+
+        let expiring = lock
+            .try_acquire(&resources, Duration::from_millis(100))
+            .await?
+            .ok_or("contended")?;
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        // An expired lock is not brought back into existence.
+        assert!(
+            !lock
+                .try_extend(&expiring, &resources, Duration::from_secs(10))
+                .await?
+        );
+        assert!(!conn.exists::<_, bool>("lock:a").await?);
+        assert!(!lock.is_held(&expiring).await?);
+        Ok(())
+    })
+}
+
+#[expect(
+    clippy::panic_in_result_fn,
+    reason = "It's annoying to handle the error here."
+)]
+#[expect(clippy::tests_outside_test_module, reason = "`#[serial]` breaks it")] // TODO Fix this.
+#[test]
+#[serial]
+fn metadata() -> Result<(), Box<dyn Error>> {
+    let redis = redis_lock::test_support::TestRedis::start()?;
+    tokio::runtime::Runtime::new()?.block_on(async {
+        let lock = redis.lock().await?;
+
+        let resources = vec![String::from("a"), String::from("b")];
+        let lock_id = lock
+            .try_acquire_with_metadata(
+                &resources,
+                redis_lock::DEFAULT_EXPIRATION,
+                Some("pod-7 request abc"),
+            )
+            .await?
+            .ok_or("contended")?;
+        assert_eq!(
+            lock.lock_metadata(&lock_id).await?.as_deref(),
+            Some("pod-7 request abc")
+        );
+        assert_eq!(
+            lock.resource_metadata("b").await?.as_deref(),
+            Some("pod-7 request abc")
+        );
+        assert_eq!(lock.resource_metadata("c").await?, None);
+        // The metadata is removed with the lock.
+        lock.release(&lock_id).await?;
+        assert_eq!(lock.lock_metadata(&lock_id).await?, None);
+        assert_eq!(lock.resource_metadata("a").await?, None);
+        Ok(())
+    })
+}
+
+#[expect(
+    clippy::panic_in_result_fn,
+    reason = "It's annoying to handle the error here."
+)]
+#[expect(clippy::tests_outside_test_module, reason = "`#[serial]` breaks it")] // TODO Fix this.
+#[test]
+#[serial]
+fn metrics() -> Result<(), Box<dyn Error>> {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    /// Counts the calls to each hook.
+    #[derive(Default)]
+    struct Counts {
+        /// Calls to [`redis_lock::metrics::Metrics::on_acquire_attempt`].
+        attempts: AtomicUsize,
+        /// Calls to [`redis_lock::metrics::Metrics::on_acquired`].
+        acquired: AtomicUsize,
+        /// Calls to [`redis_lock::metrics::Metrics::on_timeout`].
+        timeouts: AtomicUsize,
+        /// Calls to [`redis_lock::metrics::Metrics::on_release`].
+        releases: AtomicUsize,
+    }
+    impl redis_lock::metrics::Metrics for Counts {
+        fn on_acquire_attempt(&self) {
+            self.attempts.fetch_add(1, Ordering::SeqCst);
+        }
+        fn on_acquired(&self, _wait: Duration) {
+            self.acquired.fetch_add(1, Ordering::SeqCst);
+        }
+        fn on_timeout(&self) {
+            self.timeouts.fetch_add(1, Ordering::SeqCst);
+        }
+        fn on_release(&self) {
+            self.releases.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    let redis = redis_lock::test_support::TestRedis::start()?;
+    tokio::runtime::Runtime::new()?.block_on(async {
+        let counts = Arc::new(Counts::default());
+        let lock = redis
+            .lock()
+            .await?
+            .with_metrics(Arc::<Counts>::clone(&counts));
+
+        let resources = vec![String::from("a")];
+        let lock_id = lock
+            .try_acquire_default(&resources)
+            .await?
+            .ok_or("contended")?;
+        let timed_out = lock
+            .acquire(
+                &resources,
+                redis_lock::DEFAULT_EXPIRATION,
+                Duration::from_millis(50),
+                Duration::from_millis(20),
+            )
+            .await?;
+        assert!(timed_out.is_none());
+        lock.release(&lock_id).await?;
+
+        assert!(counts.attempts.load(Ordering::SeqCst) > 1);
+        assert_eq!(counts.acquired.load(Ordering::SeqCst), 1);
+        assert_eq!(counts.timeouts.load(Ordering::SeqCst), 1);
+        assert_eq!(counts.releases.load(Ordering::SeqCst), 1);
+        Ok(())
+    })
+}
+
+#[expect(
+    clippy::panic_in_result_fn,
+    reason = "It's annoying to handle the error here."
+)]
+#[expect(clippy::tests_outside_test_module, reason = "`#[serial]` breaks it")] // TODO Fix this.
+#[test]
+#[serial]
+fn resource_metrics() -> Result<(), Box<dyn Error>> {
+    use std::sync::{Arc, Mutex, PoisonError};
+    use std::time::Duration;
+
+    /// Records the resources of each acquired lock.
+    #[derive(Default)]
+    struct Acquired(Mutex<Vec<Vec<String>>>);
+    impl redis_lock::metrics::Metrics for Acquired {
+        fn on_acquired_resources(&self, resources: &[String], _wait: Duration) {
+            self.0
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .push(resources.to_vec());
+        }
+    }
+
+    let redis = redis_lock::test_support::TestRedis::start()?;
+    tokio::runtime::Runtime::new()?.block_on(async {
+        let acquired = Arc::new(Acquired::default());
+        let lock = redis
+            .lock()
+            .await?
+            .with_metrics(Arc::<Acquired>::clone(&acquired));
+
+        let resources = vec![String::from("a"), String::from("b")];
+        lock.try_acquire_default(&resources)
+            .await?
+            .ok_or("contended")?;
+        let sets = [vec![String::from("a")], vec![String::from("c")]];
+        lock.try_acquire_any(&sets, redis_lock::DEFAULT_EXPIRATION)
+            .await?
+            .ok_or("contended")?;
+        assert_eq!(
+            *acquired.0.lock().unwrap_or_else(PoisonError::into_inner),
+            vec![resources, vec![String::from("c")]]
+        );
+        Ok(())
+    })
+}
+
+#[expect(
+    clippy::panic_in_result_fn,
+    reason = "It's annoying to handle the error here."
+)]
+#[expect(clippy::tests_outside_test_module, reason = "`#[serial]` breaks it")] // TODO Fix this.
+#[test]
+#[serial]
+fn try_acquire_many() -> Result<(), Box<dyn Error>> {
+    let redis = redis_lock::test_support::TestRedis::start()?;
+    tokio::runtime::Runtime::new()?.block_on(async {
+        let lock = redis.lock().await?;
+
+        let first = vec![String::from("a"), String::from("b")];
+        let second = vec![String::from("c")];
+        let sets = vec![first.clone(), second.clone()];
+        let held = lock
+            .try_acquire_default(&second)
+            .await?
+            .ok_or("contended")?;
+        // Nothing is acquired when any set is contended.
+        assert!(lock
+            .try_acquire_many(&sets, redis_lock::DEFAULT_EXPIRATION)
+            .await?
+            .is_none());
+        assert!(lock.try_acquire_default(&first).await?.is_some());
+        assert_eq!(lock.force_release("a").await?, 1);
+        assert_eq!(lock.force_release("b").await?, 1);
+        lock.release(&held).await?;
+
+        let lock_ids = lock
+            .try_acquire_many(&sets, redis_lock::DEFAULT_EXPIRATION)
+            .await?
+            .ok_or("contended")?;
+        let [first_id, second_id] = lock_ids.as_slice() else {
+            return Err("expected a lock per set".into());
+        };
+        // Each set is released independently.
+        assert_eq!(lock.release(second_id).await?, 1);
+        assert!(lock.try_acquire_default(&first).await?.is_none());
+        assert_eq!(lock.release(first_id).await?, 2);
+
+        // Overlapping sets are rejected.
+        let overlapping = vec![second.clone(), second];
+        if lock
+            .try_acquire_many(&overlapping, redis_lock::DEFAULT_EXPIRATION)
+            .await
+            .is_ok()
+        {
+            return Err("overlapping sets acquired".into());
+        }
+        Ok(())
+    })
+}
+
+#[expect(
+    clippy::panic_in_result_fn,
+    reason = "It's annoying to handle the error here."
+)]
+#[expect(clippy::tests_outside_test_module, reason = "`#[serial]` breaks it")] // TODO Fix this.
+#[test]
+#[serial]
+fn try_acquire_any() -> Result<(), Box<dyn Error>> {
+    let redis = redis_lock::test_support::TestRedis::start()?;
+    tokio::runtime::Runtime::new()?.block_on(async {
+        let lock = redis.lock().await?;
+
+        let shards = vec![
+            vec![String::from("shard-0")],
+            vec![String::from("shard-1")],
+            vec![String::from("shard-2")],
+        ];
+        // The first free shard is acquired.
+        let (first, first_id) = lock
+            .try_acquire_any(&shards, redis_lock::DEFAULT_EXPIRATION)
+            .await?
+            .ok_or("contended")?;
+        assert_eq!(first, 0);
+        assert_eq!(lock.lock_resources(&first_id).await?, ["shard-0"]);
+        let (second, _) = lock
+            .try_acquire_any(&shards, redis_lock::DEFAULT_EXPIRATION)
+            .await?
+            .ok_or("contended")?;
+        assert_eq!(second, 1);
+        let (third, _) = lock
+            .try_acquire_any(&shards, redis_lock::DEFAULT_EXPIRATION)
+            .await?
+            .ok_or("contended")?;
+        assert_eq!(third, 2);
+
+        // Nothing is acquired when every shard is contended.
+        assert!(lock
+            .try_acquire_any(&shards, redis_lock::DEFAULT_EXPIRATION)
+            .await?
+            .is_none());
+        lock.release(&first_id).await?;
+        let (freed, _) = lock
+            .try_acquire_any(&shards, redis_lock::DEFAULT_EXPIRATION)
+            .await?
+            .ok_or("contended")?;
+        assert_eq!(freed, 0);
+        Ok(())
+    })
+}
+
+#[expect(
+    clippy::panic_in_result_fn,
+    reason = "It's annoying to handle the error here."
+)]
+#[expect(clippy::tests_outside_test_module, reason = "`#[serial]` breaks it")] // TODO Fix this.
+#[test]
+#[serial]
+fn release_many() -> Result<(), Box<dyn Error>> {
+    let redis = redis_lock::test_support::TestRedis::start()?;
+    tokio::runtime::Runtime::new()?.block_on(async {
+        let lock = redis.lock().await?;
+
+        let sets = vec![
+            vec![String::from("a"), String::from("b")],
+            vec![String::from("c")],
+        ];
+        let mut lock_ids = lock
+            .try_acquire_many(&sets, redis_lock::DEFAULT_EXPIRATION)
+            .await?
+            .ok_or("contended")?;
+        let other = lock
+            .try_acquire_default(&[String::from("d")])
+            .await?
+            .ok_or("contended")?;
+        lock_ids.push(String::from("not-held"));
+        assert_eq!(lock.release_many(&lock_ids).await?, 3);
+        assert_eq!(lock.release_many(&lock_ids).await?, 0);
+        // Locks not listed remain held.
+        assert!(lock.is_held(&other).await?);
+        Ok(())
+    })
+}
+
+#[expect(
+    clippy::panic_in_result_fn,
+    reason = "It's annoying to handle the error here."
+)]
+#[expect(clippy::tests_outside_test_module, reason = "`#[serial]` breaks it")] // TODO Fix this.
+#[test]
+#[serial]
+fn guarded_exec() -> Result<(), Box<dyn Error>> {
+    let redis = redis_lock::test_support::TestRedis::start()?;
+    tokio::runtime::Runtime::new()?.block_on(async {
+        let client = redis.client().clone();
+        let mut conn = client.get_multiplexed_async_connection().await?;
+        conn.set::<_, _, ()>("account1", 1000i32).await?;
+        conn.set::<_, _, ()>("account2", 1000i32).await?;
+        let lock = redis.lock().await?;
+
+        let resources = vec![String::from("account1"), String::from("account2")];
+        let lock_id = lock
+            .try_acquire_default(&resources)
+            .await?
+            .ok_or("contended")?;
+        let mut decr = redis::cmd("DECRBY");
+        decr.arg("account1").arg(100i32);
+        let mut incr = redis::cmd("INCRBY");
+        incr.arg("account2").arg(100i32);
+        let transfer = [decr, incr];
+        let replies = lock
+            .guarded_exec(&lock_id, &resources, &transfer)
+            .await?
+            .ok_or("not held")?;
+        assert_eq!(
+            replies,
+            vec![redis::Value::Int(900), redis::Value::Int(1100)]
+        );
+        // Nothing is run for a resource the lock does not hold.
+        let unheld = [String::from("account1"), String::from("account3")];
+        assert!(lock
+            .guarded_exec(&lock_id, &unheld, &transfer)
+            .await?
+            .is_none());
+        // Nothing is run once the lock is released.
+        lock.release(&lock_id).await?;
+        assert!(lock
+            .guarded_exec(&lock_id, &resources, &transfer)
+            .await?
+            .is_none());
+        assert_eq!(conn.get::<_, i64>("account1").await?, 900);
+        Ok(())
+    })
+}
+
+#[expect(
+    clippy::panic_in_result_fn,
+    reason = "It's annoying to handle the error here."
+)]
+#[expect(clippy::tests_outside_test_module, reason = "`#[serial]` breaks it")] // TODO Fix this.
+#[test]
+#[serial]
+fn try_acquire_and_exec() -> Result<(), Box<dyn Error>> {
+    let redis = redis_lock::test_support::TestRedis::start()?;
+    tokio::runtime::Runtime::new()?.block_on(async {
+        let client = redis.client().clone();
+        let mut conn = client.get_multiplexed_async_connection().await?;
+        conn.set::<_, _, ()>("account1", 1000i32).await?;
+        let lock = redis.lock().await?;
+
+        let resources = (0i32..64i32)
+            .map(|i| format!("account{i}"))
+            .collect::<Vec<_>>();
+        let mut get = redis::cmd("GET");
+        get.arg("account1");
+        let read = [get];
+        let (lock_id, replies) = lock
+            .try_acquire_and_exec(&resources, redis_lock::DEFAULT_EXPIRATION, &read)
+            .await?
+            .ok_or("contended")?;
+        assert_eq!(replies, vec![redis::Value::BulkString(b"1000".to_vec())]);
+        assert!(lock.is_held(&lock_id).await?);
+        // Nothing is run when the lock cannot be acquired.
+        let mut incr = redis::cmd("INCR");
+        incr.arg("account1");
+        assert!(lock
+            .try_acquire_and_exec(&resources, redis_lock::DEFAULT_EXPIRATION, &[incr])
+            .await?
+            .is_none());
+        assert_eq!(conn.get::<_, i64>("account1").await?, 1000);
+        lock.release(&lock_id).await?;
+        Ok(())
+    })
+}
+
+#[cfg(feature = "sync")]
+#[expect(
+    clippy::panic_in_result_fn,
+    reason = "It's annoying to handle the error here."
+)]
+#[expect(clippy::tests_outside_test_module, reason = "`#[serial]` breaks it")] // TODO Fix this.
+#[test]
+#[serial]
+fn guard_release_error() -> Result<(), Box<dyn Error>> {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    /// Counts the calls to [`redis_lock::metrics::Metrics::on_release_error`].
+    #[derive(Default)]
+    struct Errors(AtomicUsize);
+    impl redis_lock::metrics::Metrics for Errors {
+        fn on_release_error(&self, _error: &redis::RedisError) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    let redis = redis_lock::test_support::TestRedis::start()?;
+    let client = redis.client().clone();
+    let mut conn = client.get_connection()?;
+    redis_lock::sync::setup(&client)?;
+    let errors = Arc::new(Errors::default());
+    let lock = redis_lock::sync::MultiResourceLock::new(&client)?
+        .with_metrics(Arc::<Errors>::clone(&errors));
+
+    let guard = lock
+        .try_lock_default(&[String::from("a")])?
+        .ok_or("contended")?;
+    // Releasing fails once the functions are removed.
+    redis::cmd("FUNCTION").arg("FLUSH").exec(&mut conn)?;
+    drop(guard);
+    assert_eq!(errors.0.load(Ordering::SeqCst), 1);
+    Ok(())
+}
+
+#[cfg(feature = "sync")]
+#[expect(
+    clippy::panic_in_result_fn,
+    reason = "It's annoying to handle the error here."
+)]
+#[expect(clippy::tests_outside_test_module, reason = "`#[serial]` breaks it")] // TODO Fix this.
+#[test]
+#[serial]
+fn sync_error_leaves_queue() -> Result<(), Box<dyn Error>> {
+    use std::time::Duration;
+
+    let redis = redis_lock::test_support::TestRedis::start()?;
+    let client = redis.client().clone();
+    let mut conn = client.get_connection()?;
+    redis_lock::sync::setup(&client)?;
+    let lock = redis_lock::sync::MultiResourceLock::new(&client)?.with_fair_queue(true);
+
+    // Queueing on `b` errors after the waiter has queued on `a`.
+    conn.set::<_, _, ()>("queue:b", "not a queue")?;
+    let result = lock.acquire(
+        &[String::from("a"), String::from("b")],
+        redis_lock::DEFAULT_EXPIRATION,
+        Duration::from_secs(1),
+        Duration::from_millis(10),
+    );
+    assert!(result.is_err_and(|err| err.to_string().contains("WRONGTYPE")));
+    // The waiter left the queue rather than blocking others until it expires.
+    assert_eq!(conn.zcard::<_, usize>("queue:a")?, 0);
+    Ok(())
+}
+
+#[cfg(feature = "sync")]
+#[expect(
+    clippy::panic_in_result_fn,
+    reason = "It's annoying to handle the error here."
+)]
+#[expect(clippy::tests_outside_test_module, reason = "`#[serial]` breaks it")] // TODO Fix this.
+#[test]
+#[serial]
+fn guard_from_id() -> Result<(), Box<dyn Error>> {
+    let redis = redis_lock::test_support::TestRedis::start()?;
+    let client = redis.client().clone();
+    redis_lock::sync::setup(&client)?;
+    let lock = redis_lock::sync::MultiResourceLock::new(&client)?;
+
+    let resources = vec![String::from("a"), String::from("b")];
+    // Persisted before a restart.
+    let lock_id = lock.try_acquire_default(&resources)?.ok_or("contended")?;
+    assert!(lock.is_held(&lock_id)?);
+    // Handing off the lock id does not release it.
+    let handed_off = lock.try_guard_from_id(lock_id.clone())?.ok_or("not held")?;
+    assert_eq!(handed_off.into_lock_id(), lock_id);
+    assert!(lock.is_held(&lock_id)?);
+    let guard = lock.try_guard_from_id(lock_id.clone())?.ok_or("not held")?;
+    assert_eq!(guard.lock_id(), lock_id);
+    assert_eq!(guard.resources(), resources);
+    drop(guard);
+    // Dropping the adopted guard released the lock.
+    assert!(!lock.is_held(&lock_id)?);
+    assert!(lock.try_guard_from_id(lock_id)?.is_none());
+
+    // Duplicates are ignored by default, the guard holds each resource once.
+    let duplicated = vec![String::from("a"), String::from("a"), String::from("b")];
+    let deduplicated = lock.try_lock_default(&duplicated)?.ok_or("contended")?;
+    assert_eq!(deduplicated.resources(), resources);
+    Ok(())
+}
+
+#[cfg(feature = "sync")]
+#[expect(
+    clippy::panic_in_result_fn,
+    reason = "It's annoying to handle the error here."
+)]
+#[expect(clippy::tests_outside_test_module, reason = "`#[serial]` breaks it")] // TODO Fix this.
+#[test]
+#[serial]
+fn guard_conn() -> Result<(), Box<dyn Error>> {
+    use redis::Commands as _;
+
+    let redis = redis_lock::test_support::TestRedis::start()?;
+    let client = redis.client().clone();
+    let mut conn = client.get_connection()?;
+    redis_lock::sync::setup(&client)?;
+    conn.set::<_, _, ()>("account1", 1000i32)?;
+    let lock = redis_lock::sync::MultiResourceLock::new(&client)?;
+
+    let resources = vec![String::from("account1")];
+    let mut guard = lock.try_lock_default(&resources)?.ok_or("contended")?;
+    assert_eq!(guard.resources(), resources);
+    // Read-modify-write on the connection holding the lock.
+    let balance: i64 = guard.conn()?.get("account1")?;
+    guard.conn()?.set::<_, _, ()>("account1", balance - 100)?;
+    drop(guard);
+    assert_eq!(conn.get::<_, i64>("account1")?, 900);
+    assert!(lock.try_acquire_default(&resources)?.is_some());
+    Ok(())
+}
+
+#[cfg(feature = "sync")]
+#[expect(
+    clippy::panic_in_result_fn,
+    reason = "It's annoying to handle the error here."
+)]
+#[expect(clippy::tests_outside_test_module, reason = "`#[serial]` breaks it")] // TODO Fix this.
+#[test]
+#[serial]
+fn add_resources() -> Result<(), Box<dyn Error>> {
+    let redis = redis_lock::test_support::TestRedis::start()?;
+    let client = redis.client().clone();
+    redis_lock::sync::setup(&client)?;
+    let lock = redis_lock::sync::MultiResourceLock::new(&client)?;
+    let other = redis_lock::sync::MultiResourceLock::new(&client)?;
+
+    let mut guard = lock
+        .try_lock_default(&[String::from("a")])?
+        .ok_or("contended")?;
+    // Resources already held are kept.
+    assert!(guard.add_resources(&[String::from("a"), String::from("b")])?);
+    assert_eq!(guard.resources(), [String::from("a"), String::from("b")]);
+    assert!(other.try_acquire_default(&[String::from("b")])?.is_none());
+
+    // Contended resources are not added and the held resources stay held.
+    let held_c = other
+        .try_acquire_default(&[String::from("c")])?
+        .ok_or("contended")?;
+    assert!(!guard.add_resources(&[String::from("c")])?);
+    assert_eq!(guard.resources(), [String::from("a"), String::from("b")]);
+    assert!(other.try_acquire_default(&[String::from("a")])?.is_none());
+    other.release(&held_c)?;
+
+    drop(guard);
+    assert!(other
+        .try_acquire_default(&[String::from("a"), String::from("b")])?
+        .is_some());
+    Ok(())
+}
+
+#[cfg(feature = "sync")]
+#[expect(
+    clippy::panic_in_result_fn,
+    reason = "It's annoying to handle the error here."
+)]
+#[expect(clippy::tests_outside_test_module, reason = "`#[serial]` breaks it")] // TODO Fix this.
+#[test]
+#[serial]
+fn release_resources() -> Result<(), Box<dyn Error>> {
+    let redis = redis_lock::test_support::TestRedis::start()?;
+    let client = redis.client().clone();
+    redis_lock::sync::setup(&client)?;
+    let lock = redis_lock::sync::MultiResourceLock::new(&client)?;
+    let other = redis_lock::sync::MultiResourceLock::new(&client)?;
+
+    let resources = vec![String::from("a"), String::from("b"), String::from("c")];
+    let mut guard = lock.try_lock_default(&resources)?.ok_or("contended")?;
+    assert_eq!(guard.release_resources(&[String::from("a")])?, 1);
+    assert_eq!(guard.resources(), [String::from("b"), String::from("c")]);
+
+    // Only the released resources are free.
+    let held_a = other
+        .try_acquire_default(&[String::from("a")])?
+        .ok_or("contended")?;
+    assert!(other.try_acquire_default(&[String::from("b")])?.is_none());
+    // Resources of another lock cannot be released.
+    if guard.release_resources(&[String::from("a")]).is_ok() {
+        return Err("released a resource of another lock".into());
+    }
+
+    drop(guard);
+    assert!(other
+        .try_acquire_default(&[String::from("b"), String::from("c")])?
+        .is_some());
+    other.release(&held_a)?;
+    Ok(())
+}
+
+#[cfg(feature = "sync")]
+#[expect(
+    clippy::panic_in_result_fn,
+    reason = "It's annoying to handle the error here."
+)]
+#[expect(clippy::tests_outside_test_module, reason = "`#[serial]` breaks it")] // TODO Fix this.
+#[test]
+#[serial]
+fn resize_keeps_expirations() -> Result<(), Box<dyn Error>> {
+    use std::time::Duration;
+
+    let redis = redis_lock::test_support::TestRedis::start()?;
+    let client = redis.client().clone();
+    let mut conn = client.get_connection()?;
+    redis_lock::sync::setup(&client)?;
+    let lock = redis_lock::sync::MultiResourceLock::new(&client)?;
+
+    let lock_id = lock
+        .try_acquire_expiring(&[
+            (String::from("a"), Duration::from_secs(5)),
+            (String::from("b"), Duration::from_secs(100)),
+            (String::from("c"), redis_lock::INDEFINITE),
+        ])?
+        .ok_or("contended")?;
+    let mut guard = lock.guard_from_id(
+        lock_id,
+        vec![String::from("a"), String::from("b"), String::from("c")],
+    );
+
+    // Each held resource keeps its own expiration.
+    assert!(guard.add_resources(&[String::from("d")])?);
+    assert!(conn.pttl::<_, i64>("lock:a")? <= 5000);
+    assert!(conn.pttl::<_, i64>("lock:b")? > 5000);
+    assert_eq!(conn.pttl::<_, i64>("lock:c")?, -1);
+    assert_eq!(guard.release_resources(&[String::from("c")])?, 1);
+    assert!(conn.pttl::<_, i64>("lock:a")? <= 5000);
+    assert!(conn.pttl::<_, i64>("lock:b")? > 5000);
+    Ok(())
+}
+
+#[cfg(feature = "sync")]
+#[expect(
+    clippy::panic_in_result_fn,
+    reason = "It's annoying to handle the error here."
+)]
+#[expect(clippy::tests_outside_test_module, reason = "`#[serial]` breaks it")] // TODO Fix this.
+#[test]
+#[serial]
+fn checkpoint() -> Result<(), Box<dyn Error>> {
+    use std::time::Duration;
+
+    let redis = redis_lock::test_support::TestRedis::start()?;
+    let client = redis.client().clone();
+    let mut conn = client.get_connection()?;
+    redis_lock::sync::setup(&client)?;
+    let lock = redis_lock::sync::MultiResourceLock::new(&client)?;
+
+    let guard = lock
+        .try_lock(&[String::from("a")], Duration::from_secs(1))?
+        .ok_or("contended")?;
+    guard.checkpoint(Duration::from_secs(60))?;
+    assert!(conn.pttl::<_, i64>("lock:a")? > 50_000i64);
+
+    // Checkpoints error once the lock is lost.
+    conn.del::<_, ()>("lock:a")?;
+    if guard.checkpoint(Duration::from_secs(60)).is_ok() {
+        return Err("renewed a lost lock".into());
+    }
+    Ok(())
+}
+
+#[cfg(feature = "sync")]
+#[expect(
+    clippy::panic_in_result_fn,
+    reason = "It's annoying to handle the error here."
+)]
+#[expect(clippy::tests_outside_test_module, reason = "`#[serial]` breaks it")] // TODO Fix this.
+#[test]
+#[serial]
+fn owns() -> Result<(), Box<dyn Error>> {
+    let redis = redis_lock::test_support::TestRedis::start()?;
+    let client = redis.client().clone();
+    let mut conn = client.get_connection()?;
+    redis_lock::sync::setup(&client)?;
+    let lock = redis_lock::sync::MultiResourceLock::new(&client)?;
+    let resources = vec![String::from("a"), String::from("b")];
+
+    let held = lock.try_acquire_default(&resources)?.ok_or("contended")?;
+    assert!(lock.owns(&held, &resources)?);
+    assert!(!lock.owns("other", &resources)?);
+    assert!(!lock.owns(&held, &[String::from("a"), String::from("c")])?);
+    // Losing any resource, e.g. to expiry, is detected.
+    conn.del::<_, ()>("lock:b")?;
+    assert!(lock.owns(&held, &[String::from("a")])?);
+    assert!(!lock.owns(&held, &resources)?);
+    lock.release(&held)?;
+
+    let guard = lock.try_lock_default(&resources)?.ok_or("contended")?;
+    assert!(guard.is_valid()?);
+    conn.del::<_, ()>("lock:a")?;
+    assert!(!guard.is_valid()?);
+    Ok(())
+}
+
+#[cfg(feature = "sync")]
+#[expect(
+    clippy::panic_in_result_fn,
+    reason = "It's annoying to handle the error here."
+)]
+#[expect(clippy::tests_outside_test_module, reason = "`#[serial]` breaks it")] // TODO Fix this.
+#[test]
+#[serial]
+fn shared_sync_lock() -> Result<(), Box<dyn Error>> {
+    use std::time::Duration;
+
+    let redis = redis_lock::test_support::TestRedis::start()?;
+    let client = redis.client().clone();
+    redis_lock::sync::setup(&client)?;
+    let lock = redis_lock::sync::MultiResourceLock::new(&client)?;
+
+    // Threads share the lock by reference, each holding a guard at once.
+    let acquired = std::thread::scope(|scope| {
+        let workers = ["a", "b", "c"].map(|resource| {
+            let shared = &lock;
+            scope.spawn(move || {
+                let guard = shared.lock(
+                    &[String::from(resource)],
+                    redis_lock::DEFAULT_EXPIRATION,
+                    Duration::from_secs(10),
+                    Duration::from_millis(10),
+                )?;
+                std::thread::sleep(Duration::from_millis(100));
+                Ok::<_, redis::RedisError>(guard.is_some())
+            })
+        });
+        workers
+            .map(|worker| worker.join().map_err(|_panic| "worker panicked"))
+            .into_iter()
+            .collect::<Result<Vec<_>, _>>()
+    })?;
+    for held in acquired {
+        assert!(held?);
+    }
+    assert_eq!(lock.stats()?.active_locks, 0);
+    Ok(())
+}
+
+#[cfg(feature = "sync")]
+#[expect(clippy::tests_outside_test_module, reason = "`#[serial]` breaks it")] // TODO Fix this.
+#[test]
+#[serial]
+fn current_thread_drop() -> Result<(), Box<dyn Error>> {
+    use std::time::Duration;
+
+    let redis = redis_lock::test_support::TestRedis::start()?;
+    let client = redis.client().clone();
+    redis_lock::sync::setup(&client)?;
+    let lock = redis_lock::sync::MultiResourceLock::new(&client)?;
+
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?
+        .block_on(async {
+            let mut async_conn = client.get_multiplexed_async_connection().await?;
+            let guard = lock
+                .try_lock_default(&[String::from("a")])?
+                .ok_or("contended")?;
+            // Released from a task rather than blocking the runtime's only thread.
+            drop(guard);
+            tokio::time::timeout(Duration::from_secs(5), async {
+                while async_conn.exists::<_, bool>("lock:a").await? {
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+                }
+                Ok::<_, redis::RedisError>(())
+            })
+            .await??;
+            Ok(())
+        })
+}
+
+#[cfg(feature = "sync")]
+#[expect(
+    clippy::panic_in_result_fn,
+    reason = "It's annoying to handle the error here."
+)]
+#[expect(clippy::tests_outside_test_module, reason = "`#[serial]` breaks it")] // TODO Fix this.
+#[test]
+#[serial]
+fn guard_metadata() -> Result<(), Box<dyn Error>> {
+    let redis = redis_lock::test_support::TestRedis::start()?;
+    let client = redis.client().clone();
+    redis_lock::sync::setup(&client)?;
+    let lock = redis_lock::sync::MultiResourceLock::new(&client)?;
+    let resources = vec![String::from("a"), String::from("b")];
+
+    let guard = lock
+        .lock_with_metadata(
+            &resources,
+            redis_lock::DEFAULT_EXPIRATION,
+            redis_lock::DEFAULT_TIMEOUT,
+            redis_lock::DEFAULT_SLEEP,
+            "host-1",
+        )?
+        .ok_or("timed out")?;
+    assert_eq!(guard.metadata(), Some("host-1"));
+    // Other workers see who holds the resources.
+    assert_eq!(lock.resource_metadata("b")?.as_deref(), Some("host-1"));
+    drop(guard);
+    assert_eq!(lock.resource_metadata("b")?, None);
+
+    let retried = lock
+        .try_lock_with_metadata(&resources, redis_lock::DEFAULT_EXPIRATION, "host-2")?
+        .ok_or("contended")?;
+    assert_eq!(
+        lock.lock_metadata(retried.lock_id())?.as_deref(),
+        Some("host-2")
+    );
+    Ok(())
+}
+
+#[cfg(feature = "sync")]
+#[expect(
+    clippy::panic_in_result_fn,
+    reason = "It's annoying to handle the error here."
+)]
+#[expect(clippy::tests_outside_test_module, reason = "`#[serial]` breaks it")] // TODO Fix this.
+#[test]
+#[serial]
+fn sync_lock_scope() -> Result<(), Box<dyn Error>> {
+    let redis = redis_lock::test_support::TestRedis::start()?;
+    let client = redis.client().clone();
+    let mut conn = client.get_connection()?;
+    redis_lock::sync::setup(&client)?;
+    let lock = redis_lock::sync::MultiResourceLock::new(&client)?;
+
+    let mut scope = lock.scope();
+    scope
+        .try_lock(
+            &[String::from("a"), String::from("b")],
+            redis_lock::DEFAULT_EXPIRATION,
+        )?
+        .ok_or("contended")?;
+    scope
+        .try_lock(&[String::from("c")], redis_lock::DEFAULT_EXPIRATION)?
+        .ok_or("contended")?;
+    assert_eq!(scope.guards().len(), 2);
+    // Dropping the scope releases every lock it holds.
+    drop(scope);
+    assert!(!conn.exists::<_, bool>("lock:a")?);
+    assert!(!conn.exists::<_, bool>("lock:c")?);
+
+    let mut released = lock.scope();
+    released
+        .try_lock(&[String::from("a")], redis_lock::DEFAULT_EXPIRATION)?
+        .ok_or("contended")?;
+    assert_eq!(released.release_all()?, 1);
+    Ok(())
+}
+
+#[cfg(feature = "sync")]
+#[expect(
+    clippy::panic_in_result_fn,
+    reason = "It's annoying to handle the error here."
+)]
+#[expect(clippy::tests_outside_test_module, reason = "`#[serial]` breaks it")] // TODO Fix this.
+#[test]
+#[serial]
+fn poisoned() -> Result<(), Box<dyn Error>> {
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+    use std::time::Duration;
+
+    let redis = redis_lock::test_support::TestRedis::start()?;
+    let client = redis.client().clone();
+    let mut conn = client.get_connection()?;
+    redis_lock::sync::setup(&client)?;
+    let lock =
+        redis_lock::sync::MultiResourceLock::new(&client)?.with_poison_ttl(Duration::from_secs(60));
+    let resources = vec![String::from("a")];
+
+    // Releasing normally doesn't poison.
+    drop(lock.try_lock_default(&resources)?.ok_or("contended")?);
+    assert!(!lock.is_poisoned("a")?);
+
+    // A guard dropped while panicking releases the lock poisoned.
+    let crashed = catch_unwind(AssertUnwindSafe(|| {
+        let _guard = lock.try_lock_default(&resources);
+        std::panic::resume_unwind(Box::new("crashed"));
+    }));
+    assert!(crashed.is_err());
+    assert!(!conn.exists::<_, bool>("lock:a")?);
+    assert!(lock.is_poisoned("a")?);
+    assert!(conn.pttl::<_, i64>("poison:a")? > 50_000i64);
+
+    // The next holder recovers then clears the poison.
+    let held = lock.try_acquire_default(&resources)?.ok_or("contended")?;
+    assert!(lock.clear_poison("a")?);
+    assert!(!lock.is_poisoned("a")?);
+    assert!(!lock.clear_poison("a")?);
+    lock.release_poisoned(&held, redis_lock::INDEFINITE)?;
+    assert_eq!(conn.pttl::<_, i64>("poison:a")?, -1i64);
+    Ok(())
+}
+
+#[cfg(feature = "sync")]
+#[expect(
+    clippy::panic_in_result_fn,
+    reason = "It's annoying to handle the error here."
+)]
+#[expect(clippy::tests_outside_test_module, reason = "`#[serial]` breaks it")] // TODO Fix this.
+#[test]
+#[serial]
+fn drop_policy() -> Result<(), Box<dyn Error>> {
+    use redis_lock::sync::DropPolicy;
+    use redis_lock::ReleaseOutcome;
+    use std::time::{Duration, Instant};
+
+    let redis = redis_lock::test_support::TestRedis::start()?;
+    let client = redis.client().clone();
+    redis_lock::sync::setup(&client)?;
+    let resources = vec![String::from("a")];
+
+    // Skipping leaves the lock held.
+    let skipping =
+        redis_lock::sync::MultiResourceLock::new(&client)?.with_drop_policy(DropPolicy::LogAndSkip);
+    let skipped_guard = skipping.try_lock_default(&resources)?.ok_or("contended")?;
+    let skipped = skipped_guard.lock_id().to_owned();
+    drop(skipped_guard);
+    assert!(skipping.is_held(&skipped)?);
+    skipping.release(&skipped)?;
+
+    // Detached releases complete shortly after the guard is dropped.
+    let detached = redis_lock::sync::MultiResourceLock::new(&client)?
+        .with_drop_policy(DropPolicy::SpawnDetached);
+    let detached_guard = detached.try_lock_default(&resources)?.ok_or("contended")?;
+    let released = detached_guard.lock_id().to_owned();
+    drop(detached_guard);
+    let dropped = Instant::now();
+    while detached.is_held(&released)? {
+        assert!(dropped.elapsed() < Duration::from_secs(5), "still held");
+        std::thread::sleep(Duration::from_millis(10));
+    }
+
+    // Manual guards are only released explicitly.
+    let manual =
+        redis_lock::sync::MultiResourceLock::new(&client)?.with_drop_policy(DropPolicy::Manual);
+    let manual_guard = manual.try_lock_default(&resources)?.ok_or("contended")?;
+    let kept = manual_guard.lock_id().to_owned();
+    drop(manual_guard);
+    assert!(manual.is_held(&kept)?);
+    let adopted = manual.guard_from_id(kept.clone(), resources.clone());
+    assert_eq!(adopted.release()?, ReleaseOutcome::Released(1));
+    assert!(!manual.is_held(&kept)?);
+    Ok(())
+}
+
+#[cfg(all(feature = "sync", feature = "log", debug_assertions))]
+#[expect(
+    clippy::panic_in_result_fn,
+    reason = "It's annoying to handle the error here."
+)]
+#[expect(clippy::tests_outside_test_module, reason = "`#[serial]` breaks it")] // TODO Fix this.
+#[test]
+#[serial]
+fn guard_close() -> Result<(), Box<dyn Error>> {
+    use redis_lock::ReleaseOutcome;
+
+    record_logs();
+    let redis = redis_lock::test_support::TestRedis::start()?;
+    let client = redis.client().clone();
+    redis_lock::sync::setup(&client)?;
+    let lock = redis_lock::sync::MultiResourceLock::new(&client)?.with_log_target("locks");
+    let resources = vec![String::from("a")];
+
+    // Closing releases the lock without a warning.
+    let closed = lock.try_lock_default(&resources)?.ok_or("contended")?;
+    assert_eq!(closed.release()?, ReleaseOutcome::Released(1));
+    let handed_off = lock.try_lock_default(&resources)?.ok_or("contended")?;
+    let lock_id = handed_off.into_lock_id();
+    lock.release(&lock_id)?;
+    let warnings = |logged: Vec<String>| {
+        logged
+            .into_iter()
+            .filter(|message| message.contains("dropped without release"))
+            .collect::<Vec<_>>()
+    };
+    assert!(warnings(recorded_logs()).is_empty());
+
+    // Dropping still releases the lock, but warns in debug builds.
+    let dropped = lock.try_lock_default(&resources)?.ok_or("contended")?;
+    let dropped_id = dropped.lock_id().to_owned();
+    drop(dropped);
+    assert!(!lock.is_held(&dropped_id)?);
+    assert_eq!(
+        warnings(recorded_logs()),
+        vec![format!(
+            "guard of lock {dropped_id} dropped without release"
+        )]
+    );
+    Ok(())
+}
+
+#[cfg(feature = "serde")]
+#[expect(
+    clippy::panic_in_result_fn,
+    reason = "It's annoying to handle the error here."
+)]
+#[expect(clippy::tests_outside_test_module, reason = "`#[serial]` breaks it")] // TODO Fix this.
+#[test]
+#[serial]
+fn token() -> Result<(), Box<dyn Error>> {
+    use redis_lock::token::LockToken;
+
+    let redis = redis_lock::test_support::TestRedis::start()?;
+    tokio::runtime::Runtime::new()?.block_on(async {
+        let client = redis.client().clone();
+        let coordinator = redis.lock().await?;
+        let worker = redis_lock::MultiResourceLock::new(client.clone())?;
+
+        let resources = vec![String::from("a"), String::from("b")];
+        let token = coordinator
+            .try_acquire_token(&resources, redis_lock::DEFAULT_EXPIRATION)
+            .await?
+            .ok_or("contended")?;
+        let sent = serde_json::to_string(&token)?;
+
+        let received: LockToken = serde_json::from_str(&sent)?;
+        assert_eq!(received, token);
+        assert!(received.remaining().is_some());
+        assert!(worker.is_held(&received.lock_id).await?);
+        assert_eq!(worker.release(&received.lock_id).await?, 2);
+        assert!(!coordinator.is_held(&token.lock_id).await?);
+        Ok(())
+    })
+}
+
+#[expect(
+    clippy::panic_in_result_fn,
+    reason = "It's annoying to handle the error here."
+)]
+#[expect(clippy::tests_outside_test_module, reason = "`#[serial]` breaks it")] // TODO Fix this.
+#[test]
+#[serial]
+fn stats() -> Result<(), Box<dyn Error>> {
+    use redis_lock::metrics::LockStats;
+    use std::time::Duration;
+
+    let redis = redis_lock::test_support::TestRedis::start()?;
+    tokio::runtime::Runtime::new()?.block_on(async {
+        let lock = redis.lock().await?;
+
+        let held = lock
+            .try_acquire_default(&[String::from("a"), String::from("b")])
+            .await?
+            .ok_or("contended")?;
+        lock.try_acquire(&[String::from("c")], Duration::from_millis(100))
+            .await?
+            .ok_or("contended")?;
+        let broken = lock
+            .try_acquire_default(&[String::from("d")])
+            .await?
+            .ok_or("contended")?;
+        assert_eq!(
+            lock.stats().await?,
+            LockStats {
+                active_locks: 3,
+                locked_resources: 4
+            }
+        );
+        // Expired, released and broken locks are no longer counted.
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        lock.force_release("d").await?;
+        assert!(!lock.is_held(&broken).await?);
+        assert_eq!(
+            lock.stats().await?,
+            LockStats {
+                active_locks: 1,
+                locked_resources: 2
+            }
+        );
+        lock.release(&held).await?;
+        assert_eq!(lock.stats().await?, LockStats::default());
+        Ok(())
+    })
+}
+
+#[expect(
+    clippy::panic_in_result_fn,
+    reason = "It's annoying to handle the error here."
+)]
+#[expect(clippy::tests_outside_test_module, reason = "`#[serial]` breaks it")] // TODO Fix this.
+#[test]
+#[serial]
+fn connection_provider() -> Result<(), Box<dyn Error>> {
+    let redis = redis_lock::test_support::TestRedis::start()?;
+    tokio::runtime::Runtime::new()?.block_on(async {
+        let client = redis.client().clone();
+        let conn = client.get_multiplexed_async_connection().await?;
+        redis_lock::setup(&conn).await?;
+        // All commands go through the one existing connection.
+        let lock = redis_lock::MultiResourceLock::new(conn.clone())?;
+
+        let resources = vec![String::from("a")];
+        let lock_id = lock
+            .try_acquire_default(&resources)
+            .await?
+            .ok_or("contended")?;
+        assert!(lock.try_acquire_default(&resources).await?.is_none());
+        assert_eq!(lock.release(&lock_id).await?, 1);
+        Ok(())
+    })
+}
+
+#[cfg(feature = "bb8")]
+#[expect(
+    clippy::panic_in_result_fn,
+    reason = "It's annoying to handle the error here."
+)]
+#[expect(clippy::tests_outside_test_module, reason = "`#[serial]` breaks it")] // TODO Fix this.
+#[test]
+#[serial]
+fn bb8_pool() -> Result<(), Box<dyn Error>> {
+    let redis = redis_lock::test_support::TestRedis::start()?;
+    tokio::runtime::Runtime::new()?.block_on(async {
+        let manager = bb8_redis::RedisConnectionManager::new(redis.url())?;
+        let pool = bb8::Pool::builder().max_size(1).build(manager).await?;
+        redis::cmd("FLUSHALL")
+            .exec_async(&mut *pool.get().await?)
+            .await?;
+        redis_lock::setup(&pool).await?;
+        // Each command checks out the pool's only connection and returns it.
+        let lock = redis_lock::MultiResourceLock::new(pool.clone())?;
+
+        let resources = vec![String::from("a")];
+        let lock_id = lock
+            .try_acquire_default(&resources)
+            .await?
+            .ok_or("contended")?;
+        assert!(lock.try_acquire_default(&resources).await?.is_none());
+        assert_eq!(lock.release(&lock_id).await?, 1);
+        assert_eq!(pool.state().idle_connections, 1);
+        Ok(())
+    })
+}
+
+#[expect(
+    clippy::panic_in_result_fn,
+    reason = "It's annoying to handle the error here."
+)]
+#[expect(clippy::tests_outside_test_module, reason = "`#[serial]` breaks it")] // TODO Fix this.
+#[test]
+#[serial]
+fn fair_queue() -> Result<(), Box<dyn Error>> {
+    use std::time::Duration;
+
+    let redis = redis_lock::test_support::TestRedis::start()?;
+    tokio::runtime::Runtime::new()?.block_on(async {
+        let client = redis.client().clone();
+        let lock = redis.lock().await?;
+        let resources = vec![String::from("a")];
+        let waiter = |waiting_on: Vec<String>| {
+            let waiting_client = client.clone();
+            tokio::spawn(async move {
+                redis_lock::MultiResourceLock::new(waiting_client)?
+                    .with_fair_queue(true)
+                    .acquire(
+                        &waiting_on,
+                        redis_lock::DEFAULT_EXPIRATION,
+                        Duration::from_secs(10),
+                        Duration::from_millis(10),
+                    )
+                    .await
+            })
+        };
+
+        let held = lock
+            .try_acquire_default(&resources)
+            .await?
+            .ok_or("contended")?;
+        let first = waiter(resources.clone());
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let second = waiter(resources.clone());
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        lock.release(&held).await?;
+
+        // Waiters acquire in the order they started waiting, ahead of those that do not queue.
+        let first_id = first.await??.ok_or("timed out")?;
+        assert!(lock.try_acquire_default(&resources).await?.is_none());
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(!second.is_finished());
+        lock.release(&first_id).await?;
+        let second_id = second.await??.ok_or("timed out")?;
+        lock.release(&second_id).await?;
+
+        // Waiters that time out leave the queue.
+        let held_again = lock
+            .try_acquire_default(&resources)
+            .await?
+            .ok_or("contended")?;
+        let timed_out = redis_lock::MultiResourceLock::new(client.clone())?
+            .with_fair_queue(true)
+            .acquire(
+                &resources,
+                redis_lock::DEFAULT_EXPIRATION,
+                Duration::from_millis(50),
+                Duration::from_millis(10),
+            )
+            .await?;
+        assert!(timed_out.is_none());
+        lock.release(&held_again).await?;
+        assert!(lock.try_acquire_default(&resources).await?.is_some());
+        Ok(())
+    })
+}
+
+#[expect(
+    clippy::panic_in_result_fn,
+    reason = "It's annoying to handle the error here."
+)]
+#[expect(clippy::tests_outside_test_module, reason = "`#[serial]` breaks it")] // TODO Fix this.
+#[test]
+#[serial]
+fn priority() -> Result<(), Box<dyn Error>> {
+    use std::time::Duration;
+
+    let redis = redis_lock::test_support::TestRedis::start()?;
+    tokio::runtime::Runtime::new()?.block_on(async {
+        let client = redis.client().clone();
+        let lock = redis.lock().await?;
+        let resources = vec![String::from("a")];
+        let waiter = |waiting_on: Vec<String>, priority: i8| {
+            let waiting_client = client.clone();
+            tokio::spawn(async move {
+                redis_lock::MultiResourceLock::new(waiting_client)?
+                    .acquire_with_priority(
+                        &waiting_on,
+                        redis_lock::DEFAULT_EXPIRATION,
+                        Duration::from_secs(10),
+                        Duration::from_millis(10),
+                        priority,
+                    )
+                    .await
+            })
+        };
+
+        let held = lock
+            .try_acquire_default(&resources)
+            .await?
+            .ok_or("contended")?;
+        let low = waiter(resources.clone(), -1);
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let high = waiter(resources.clone(), 1);
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        lock.release(&held).await?;
+
+        // The higher priority waiter acquires first despite starting to wait later.
+        let high_id = high.await??.ok_or("timed out")?;
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(!low.is_finished());
+        lock.release(&high_id).await?;
+        let low_id = low.await??.ok_or("timed out")?;
+        lock.release(&low_id).await?;
+        Ok(())
+    })
+}
+
+#[expect(
+    clippy::panic_in_result_fn,
+    reason = "It's annoying to handle the error here."
+)]
+#[expect(clippy::tests_outside_test_module, reason = "`#[serial]` breaks it")] // TODO Fix this.
+#[test]
+#[serial]
+fn queue_position() -> Result<(), Box<dyn Error>> {
+    use std::time::Duration;
+
+    let redis = redis_lock::test_support::TestRedis::start()?;
+    tokio::runtime::Runtime::new()?.block_on(async {
+        let client = redis.client().clone();
+        let mut conn = client.get_multiplexed_async_connection().await?;
+        let lock = redis.lock().await?;
+        let resources = vec![String::from("a")];
+        let waiter = |waiting_on: Vec<String>| {
+            let waiting_client = client.clone();
+            tokio::spawn(async move {
+                redis_lock::MultiResourceLock::new(waiting_client)?
+                    .with_fair_queue(true)
+                    .acquire(
+                        &waiting_on,
+                        redis_lock::DEFAULT_EXPIRATION,
+                        Duration::from_secs(10),
+                        Duration::from_millis(10),
+                    )
+                    .await
+            })
+        };
+
+        let held = lock
+            .try_acquire_default(&resources)
+            .await?
+            .ok_or("contended")?;
+        let first = waiter(resources.clone());
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let second = waiter(resources.clone());
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let queued: Vec<String> = conn.zrange("queue:a", 0, -1).await?;
+        let [first_waiter, second_waiter] = queued.as_slice() else {
+            return Err("expected two waiters".into());
+        };
+        assert_eq!(lock.queue_position("a", first_waiter).await?, Some(0));
+        assert_eq!(lock.queue_position("a", second_waiter).await?, Some(1));
+        assert_eq!(lock.queue_position("b", first_waiter).await?, None);
+        assert_eq!(lock.queue_position("a", &held).await?, None);
+
+        lock.release(&held).await?;
+        let first_id = first.await??.ok_or("timed out")?;
+        assert_eq!(lock.queue_position("a", &first_id).await?, None);
+        lock.release(&first_id).await?;
+        let second_id = second.await??.ok_or("timed out")?;
+        lock.release(&second_id).await?;
+        Ok(())
+    })
+}
+
+#[expect(
+    clippy::panic_in_result_fn,
+    reason = "It's annoying to handle the error here."
+)]
+#[expect(clippy::tests_outside_test_module, reason = "`#[serial]` breaks it")] // TODO Fix this.
+#[test]
+#[serial]
+fn call_timeout() -> Result<(), Box<dyn Error>> {
+    use redis::aio::MultiplexedConnection;
+    use redis_lock::connection::ConnectionProvider;
+    use std::future::Future;
+    use std::time::{Duration, Instant};
+
+    /// A provider for a Redis instance that never responds.
+    struct Stalled;
+    impl ConnectionProvider for Stalled {
+        type Connection = MultiplexedConnection;
+        fn connection(&self) -> impl Future<Output = redis::RedisResult<Self::Connection>> + Send {
+            std::future::pending()
+        }
+    }
+
+    tokio::runtime::Runtime::new()?.block_on(async {
+        let lock = redis_lock::MultiResourceLock::new(Stalled)?;
+        let start = Instant::now();
+        let result = lock
+            .acquire(
+                &[String::from("a")],
+                redis_lock::DEFAULT_EXPIRATION,
+                Duration::from_millis(100),
+                Duration::from_millis(10),
+            )
+            .await;
+        assert!(result.is_err_and(|err| err.is_timeout()));
+        assert!(start.elapsed() < Duration::from_secs(5));
+        Ok(())
+    })
+}
+
+#[expect(
+    clippy::panic_in_result_fn,
+    reason = "It's annoying to handle the error here."
+)]
+#[expect(clippy::tests_outside_test_module, reason = "`#[serial]` breaks it")] // TODO Fix this.
+#[test]
+#[serial]
+fn release_retry() -> Result<(), Box<dyn Error>> {
+    use redis::aio::MultiplexedConnection;
+    use redis_lock::connection::ConnectionProvider;
+    use std::future::Future;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    /// A provider failing to connect once after being told to, like a dropped connection.
+    #[derive(Clone)]
+    struct Flaky {
+        client: Client,
+        fail_next: Arc<AtomicBool>,
+    }
+    impl ConnectionProvider for Flaky {
+        type Connection = MultiplexedConnection;
+        fn connection(&self) -> impl Future<Output = redis::RedisResult<Self::Connection>> + Send {
+            let fail = self.fail_next.swap(false, Ordering::SeqCst);
+            let client = self.client.clone();
+            async move {
+                if fail {
+                    return Err(std::io::Error::from(std::io::ErrorKind::ConnectionReset).into());
+                }
+                client.get_multiplexed_async_connection().await
+            }
+        }
+    }
+
+    let redis = redis_lock::test_support::TestRedis::start()?;
+    tokio::runtime::Runtime::new()?.block_on(async {
+        let client = redis.client().clone();
+        let mut conn = client.get_multiplexed_async_connection().await?;
+        redis_lock::setup(&client).await?;
+        let fail_next = Arc::new(AtomicBool::new(false));
+        let unretried = redis_lock::MultiResourceLock::new(Flaky {
+            client,
+            fail_next: Arc::clone(&fail_next),
+        })?;
+        let lock = unretried.clone().with_release_retries(2);
+        let resources = [String::from("a"), String::from("b")];
+
+        let lock_id = lock
+            .try_acquire_default(&resources)
+            .await?
+            .ok_or("contended")?;
+        fail_next.store(true, Ordering::SeqCst);
+        // The first attempt fails to connect, the retry releases the lock.
+        assert_eq!(lock.release(&lock_id).await?, 2);
+        assert!(!conn.exists::<_, bool>("lock:a").await?);
+
+        // Without opting in the release errors, leaving the lock held.
+        let held = unretried
+            .try_acquire_default(&resources)
+            .await?
+            .ok_or("contended")?;
+        fail_next.store(true, Ordering::SeqCst);
+        assert!(unretried
+            .release(&held)
+            .await
+            .is_err_and(|err| redis_lock::is_transient(&err)));
+        assert_eq!(unretried.release(&held).await?, 2);
+        Ok(())
+    })
+}
+
+#[expect(
+    clippy::panic_in_result_fn,
+    reason = "It's annoying to handle the error here."
+)]
+#[expect(clippy::tests_outside_test_module, reason = "`#[serial]` breaks it")] // TODO Fix this.
+#[test]
+#[serial]
+fn retry_strategy() -> Result<(), Box<dyn Error>> {
+    use redis_lock::retry::{
+        Attempts, Constant, DecorrelatedJitter, Exponential, RetryStrategy as _,
+    };
+    use std::time::Duration;
+
+    let ms = Duration::from_millis;
+    let mut constant = Constant::new(ms(10), ms(100));
+    assert_eq!(constant.next_delay(5, ms(50)), Some(ms(10)));
+    assert_eq!(constant.next_delay(6, ms(101)), None);
+    let mut attempts = Attempts::new(3, ms(10));
+    assert_eq!(attempts.next_delay(2, Duration::MAX), Some(ms(10)));
+    assert_eq!(attempts.next_delay(3, Duration::ZERO), None);
+    let mut exponential = Exponential::new(ms(10), ms(50), ms(1000));
+    let delays = (1u32..=4u32)
+        .map(|attempt| exponential.next_delay(attempt, Duration::ZERO))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        delays,
+        [Some(ms(10)), Some(ms(20)), Some(ms(40)), Some(ms(50))]
+    );
+    let mut jitter = DecorrelatedJitter::new(ms(10), ms(100), ms(1000));
+    for attempt in 1u32..=20u32 {
+        let delay = jitter
+            .next_delay(attempt, Duration::ZERO)
+            .ok_or("gave up")?;
+        assert!((ms(10)..=ms(100)).contains(&delay));
+    }
+    assert_eq!(jitter.next_delay(21, ms(1001)), None);
+
+    let redis = redis_lock::test_support::TestRedis::start()?;
+    tokio::runtime::Runtime::new()?.block_on(async {
+        let lock = redis.lock().await?;
+        let resources = vec![String::from("a")];
+
+        let mut retry = Exponential::new(ms(10), ms(50), ms(200));
+        let held = lock
+            .acquire_with_retry(&resources, redis_lock::DEFAULT_EXPIRATION, &mut retry)
+            .await?
+            .ok_or("timed out")?;
+        // Gives up once the timeout has passed.
+        assert!(lock
+            .acquire_with_retry(&resources, redis_lock::DEFAULT_EXPIRATION, &mut retry)
+            .await?
+            .is_none());
+        lock.release(&held).await?;
+        Ok(())
+    })
+}
+
+#[expect(
+    clippy::panic_in_result_fn,
+    reason = "It's annoying to handle the error here."
+)]
+#[expect(clippy::tests_outside_test_module, reason = "`#[serial]` breaks it")] // TODO Fix this.
+#[test]
+#[serial]
+fn try_acquire_n() -> Result<(), Box<dyn Error>> {
+    use std::time::Duration;
+
+    let redis = redis_lock::test_support::TestRedis::start()?;
+    tokio::runtime::Runtime::new()?.block_on(async {
+        let lock = redis.lock().await?;
+        let resources = vec![String::from("a")];
+
+        let held = lock
+            .try_acquire_n(
+                &resources,
+                redis_lock::DEFAULT_EXPIRATION,
+                3,
+                Duration::ZERO,
+            )
+            .await?
+            .ok_or("contended")?;
+        assert!(lock
+            .try_acquire_n(
+                &resources,
+                redis_lock::DEFAULT_EXPIRATION,
+                3,
+                Duration::from_millis(10)
+            )
+            .await?
+            .is_none());
+        lock.release(&held).await?;
+        Ok(())
+    })
+}
+
+#[expect(
+    clippy::panic_in_result_fn,
+    reason = "It's annoying to handle the error here."
+)]
+#[expect(clippy::tests_outside_test_module, reason = "`#[serial]` breaks it")] // TODO Fix this.
+#[test]
+#[serial]
+fn acquire_with_progress() -> Result<(), Box<dyn Error>> {
+    use std::time::Duration;
+
+    let redis = redis_lock::test_support::TestRedis::start()?;
+    tokio::runtime::Runtime::new()?.block_on(async {
+        let lock = redis.lock().await?;
+        let resources = vec![String::from("a")];
+
+        let held = lock
+            .try_acquire_default(&resources)
+            .await?
+            .ok_or("contended")?;
+        let mut waits = Vec::new();
+        let result = lock
+            .acquire_with_progress(
+                &resources,
+                redis_lock::DEFAULT_EXPIRATION,
+                Duration::from_millis(100),
+                Duration::from_millis(10),
+                |attempt, elapsed| waits.push((attempt, elapsed)),
+            )
+            .await?;
+        assert!(result.is_none());
+        // Called after each failed attempt.
+        assert!(waits.len() > 1);
+        assert!(waits
+            .iter()
+            .zip(1u32..)
+            .all(|(&(attempt, _), expected)| attempt == expected));
+        assert!(waits.windows(2).all(|pair| match pair {
+            [(_, before), (_, after)] => before <= after,
+            _ => false,
+        }));
+        lock.release(&held).await?;
+        Ok(())
+    })
+}
+
+#[expect(
+    clippy::panic_in_result_fn,
+    reason = "It's annoying to handle the error here."
+)]
+#[expect(clippy::tests_outside_test_module, reason = "`#[serial]` breaks it")] // TODO Fix this.
+#[test]
+#[serial]
+fn acquire_outcome() -> Result<(), Box<dyn Error>> {
+    use redis_lock::AcquireResult;
+    use std::time::Duration;
+
+    let redis = redis_lock::test_support::TestRedis::start()?;
+    tokio::runtime::Runtime::new()?.block_on(async {
+        let lock = redis.lock().await?;
+
+        let held = lock
+            .try_acquire_outcome(
+                &[String::from("a"), String::from("b")],
+                redis_lock::DEFAULT_EXPIRATION,
+                None,
+            )
+            .await?;
+        let AcquireResult::Acquired(lock_id) = held else {
+            return Err("contended".into());
+        };
+        let contended = [String::from("b"), String::from("c")];
+        assert_eq!(
+            lock.try_acquire_outcome(&contended, redis_lock::DEFAULT_EXPIRATION, None)
+                .await?,
+            AcquireResult::Contended {
+                resources: vec![String::from("b")]
+            }
+        );
+        assert_eq!(
+            lock.acquire_outcome(
+                &contended,
+                redis_lock::DEFAULT_EXPIRATION,
+                Duration::from_millis(50),
+                Duration::from_millis(10),
+                None
+            )
+            .await?,
+            AcquireResult::TimedOut
+        );
+        lock.release(&lock_id).await?;
+        assert!(lock
+            .try_acquire_outcome(&contended, redis_lock::DEFAULT_EXPIRATION, None)
+            .await?
+            .is_acquired());
+        Ok(())
+    })
+}
+
+#[expect(
+    clippy::panic_in_result_fn,
+    reason = "It's annoying to handle the error here."
+)]
+#[expect(clippy::tests_outside_test_module, reason = "`#[serial]` breaks it")] // TODO Fix this.
+#[test]
+#[serial]
+fn strict_resources() -> Result<(), Box<dyn Error>> {
+    let redis = redis_lock::test_support::TestRedis::start()?;
+    tokio::runtime::Runtime::new()?.block_on(async {
+        let client = redis.client().clone();
+        redis_lock::setup(&client).await?;
+        let resources = vec![String::from("a"), String::from("a"), String::from("b")];
+
+        // Duplicates are ignored by default.
+        let lenient = redis_lock::MultiResourceLock::new(client.clone())?;
+        let lock_id = lenient
+            .try_acquire_default(&resources)
+            .await?
+            .ok_or("contended")?;
+        assert_eq!(lenient.release(&lock_id).await?, 2);
+
+        let strict =
+            redis_lock::MultiResourceLock::new(client.clone())?.with_strict_resources(true);
+        if strict.try_acquire_default(&resources).await.is_ok() {
+            return Err("duplicate resources accepted".into());
+        }
+        if strict
+            .try_acquire_many(
+                std::slice::from_ref(&resources),
+                redis_lock::DEFAULT_EXPIRATION,
+            )
+            .await
+            .is_ok()
+        {
+            return Err("duplicate resources accepted".into());
+        }
+        let unique = [String::from("a"), String::from("b")];
+        assert!(strict.try_acquire_default(&unique).await?.is_some());
+        Ok(())
+    })
+}
+
+#[expect(
+    clippy::panic_in_result_fn,
+    reason = "It's annoying to handle the error here."
+)]
+#[expect(clippy::tests_outside_test_module, reason = "`#[serial]` breaks it")] // TODO Fix this.
+#[test]
+#[serial]
+fn acquire_weighted() -> Result<(), Box<dyn Error>> {
+    use std::time::Duration;
+
+    let redis = redis_lock::test_support::TestRedis::start()?;
+    tokio::runtime::Runtime::new()?.block_on(async {
+        let lock = redis.lock().await?;
+        let expiration = redis_lock::DEFAULT_EXPIRATION;
+
+        let first = lock
+            .acquire_weighted("pool", 3, 10, expiration)
+            .await?
+            .ok_or("over capacity")?;
+        assert!(lock
+            .acquire_weighted("pool", 7, 10, expiration)
+            .await?
+            .is_some());
+        assert!(lock
+            .acquire_weighted("pool", 1, 10, expiration)
+            .await?
+            .is_none());
+
+        // Releasing frees the units.
+        assert!(lock.release_weighted("pool", &first).await?);
+        assert!(!lock.release_weighted("pool", &first).await?);
+        assert!(lock
+            .acquire_weighted("pool", 3, 10, expiration)
+            .await?
+            .is_some());
+
+        // Units of expired holders are reclaimed.
+        assert!(lock
+            .acquire_weighted("other", 10, 10, Duration::from_millis(100))
+            .await?
+            .is_some());
+        assert!(lock
+            .acquire_weighted("other", 1, 10, expiration)
+            .await?
+            .is_none());
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert!(lock
+            .acquire_weighted("other", 1, 10, expiration)
+            .await?
+            .is_some());
+        Ok(())
+    })
+}
+
+#[expect(
+    clippy::panic_in_result_fn,
+    reason = "It's annoying to handle the error here."
+)]
+#[expect(clippy::tests_outside_test_module, reason = "`#[serial]` breaks it")] // TODO Fix this.
+#[test]
+#[serial]
+fn shared_lock() -> Result<(), Box<dyn Error>> {
+    use std::sync::Arc;
+
+    let redis = redis_lock::test_support::TestRedis::start()?;
+    tokio::runtime::Runtime::new()?.block_on(async {
+        let lock = Arc::new(redis.lock().await?);
+
+        // Tasks share one lock without a mutex, only one of them acquires the resource.
+        let tasks = (0..4u8)
+            .map(|_| {
+                let shared = Arc::clone(&lock);
+                tokio::spawn(async move { shared.try_acquire_default(&[String::from("a")]).await })
+            })
+            .collect::<Vec<_>>();
+        let mut acquired = Vec::new();
+        for task in tasks {
+            acquired.extend(task.await??);
+        }
+        assert_eq!(acquired.len(), 1);
+
+        // Clones release locks acquired by the original.
+        let cloned = (*lock).clone();
+        for lock_id in &acquired {
+            assert_eq!(cloned.release(lock_id).await?, 1);
+        }
+        assert!(lock
+            .try_acquire_default(&[String::from("a")])
+            .await?
+            .is_some());
+        Ok(())
+    })
+}
+
+#[expect(
+    clippy::panic_in_result_fn,
+    reason = "It's annoying to handle the error here."
+)]
+#[expect(clippy::tests_outside_test_module, reason = "`#[serial]` breaks it")] // TODO Fix this.
+#[test]
+#[serial]
+fn healthcheck() -> Result<(), Box<dyn Error>> {
+    let redis = redis_lock::test_support::TestRedis::start()?;
+    tokio::runtime::Runtime::new()?.block_on(async {
+        let client = redis.client().clone();
+        let mut conn = client.get_multiplexed_async_connection().await?;
+        redis::cmd("FUNCTION")
+            .arg("FLUSH")
+            .exec_async(&mut conn)
+            .await?;
+        let lock = redis_lock::MultiResourceLock::new(client.clone())?;
+
+        // Fails until the library is loaded.
+        assert!(lock.healthcheck().await.is_err());
+        redis_lock::setup(&client).await?;
+        lock.healthcheck().await?;
+        Ok(())
+    })
+}
+
+#[expect(
+    clippy::panic_in_result_fn,
+    reason = "It's annoying to handle the error here."
+)]
+#[expect(clippy::tests_outside_test_module, reason = "`#[serial]` breaks it")] // TODO Fix this.
+#[test]
+#[serial]
+fn auto_setup() -> Result<(), Box<dyn Error>> {
+    let redis = redis_lock::test_support::TestRedis::start()?;
+    tokio::runtime::Runtime::new()?.block_on(async {
+        let client = redis.client().clone();
+        let mut conn = client.get_multiplexed_async_connection().await?;
+        redis::cmd("FUNCTION")
+            .arg("FLUSH")
+            .exec_async(&mut conn)
+            .await?;
+        let resources = vec![String::from("a")];
+
+        // Without auto setup the missing functions error.
+        let lock = redis_lock::MultiResourceLock::new(client.clone())?;
+        if lock.try_acquire_default(&resources).await.is_ok() {
+            return Err("missing functions called".into());
+        }
+
+        // With auto setup the library is loaded and the call retried.
+        let auto = redis_lock::MultiResourceLock::new(client.clone())?.with_auto_setup(true);
+        let lock_id = auto
+            .try_acquire_default(&resources)
+            .await?
+            .ok_or("contended")?;
+        lock.healthcheck().await?;
+        assert_eq!(lock.release(&lock_id).await?, 1);
+        Ok(())
+    })
+}
+
+#[cfg(feature = "sync")]
+#[expect(
+    clippy::panic_in_result_fn,
+    reason = "It's annoying to handle the error here."
+)]
+#[expect(clippy::tests_outside_test_module, reason = "`#[serial]` breaks it")] // TODO Fix this.
+#[test]
+#[serial]
+fn auto_setup_after_flush() -> Result<(), Box<dyn Error>> {
+    let redis = redis_lock::test_support::TestRedis::start()?;
+    let client = redis.client().clone();
+    let mut conn = client.get_connection()?;
+    redis_lock::sync::setup(&client)?;
+    let lock = redis_lock::sync::MultiResourceLock::new(&client)?.with_auto_setup(true);
+    let resources = vec![String::from("a")];
+    let mut guard = lock.try_lock_default(&resources)?.ok_or("contended")?;
+
+    // Errors raised by the reloaded functions are not retried.
+    redis::cmd("FUNCTION").arg("FLUSH").exec(&mut conn)?;
+    let err = guard
+        .release_resources(&[String::from("b")])
+        .err()
+        .ok_or("released unheld resource")?;
+    assert!(err.to_string().contains("not held by lock"), "{err}");
+
+    // The library is reloaded when a guard releases its lock after the functions were flushed.
+    redis::cmd("FUNCTION").arg("FLUSH").exec(&mut conn)?;
+    drop(guard);
+    assert!(conn.get::<_, Option<String>>("lock:a")?.is_none());
+    Ok(())
+}
+
+#[expect(
+    clippy::panic_in_result_fn,
+    reason = "It's annoying to handle the error here."
+)]
+#[expect(clippy::tests_outside_test_module, reason = "`#[serial]` breaks it")] // TODO Fix this.
+#[test]
+#[serial]
+fn hash_tag() -> Result<(), Box<dyn Error>> {
+    use redis_lock::hash_tag::HashTag;
+    use redis_lock::AcquireResult;
+    use std::sync::Arc;
+
+    /// Stores every lock under the hash tag `{locks}`.
+    struct Tagged;
+    impl redis_lock::keys::KeyFormatter for Tagged {
+        fn lock_prefix(&self) -> &'static str {
+            "{locks}:"
+        }
+    }
+
+    let redis = redis_lock::test_support::TestRedis::start()?;
+    tokio::runtime::Runtime::new()?.block_on(async {
+        let client = redis.client().clone();
+        let mut conn = client.get_multiplexed_async_connection().await?;
+        redis_lock::setup(&client).await?;
+        let resources = vec![String::from("a"), String::from("b")];
+
+        // Resources are locked under the group's hash tag and reported by their own names.
+        let grouped = redis_lock::MultiResourceLock::new(client.clone())?
+            .with_hash_tag(HashTag::Group(String::from("accounts")));
+        let lock_id = grouped
+            .try_acquire_default(&resources)
+            .await?
+            .ok_or("contended")?;
+        assert!(conn.exists::<_, bool>("lock:{accounts}:a").await?);
+        assert_eq!(grouped.lock_resources(&lock_id).await?, resources);
+        assert_eq!(
+            grouped
+                .try_acquire_outcome(&[String::from("a")], redis_lock::DEFAULT_EXPIRATION, None)
+                .await?,
+            AcquireResult::Contended {
+                resources: vec![String::from("a")]
+            }
+        );
+        grouped.release(&lock_id).await?;
+
+        // Resources with their own hash tags must share a slot.
+        let existing =
+            redis_lock::MultiResourceLock::new(client.clone())?.with_hash_tag(HashTag::Existing);
+        let tagged = [String::from("{x}:a"), String::from("{x}:b")];
+        assert!(existing.try_acquire_default(&tagged).await?.is_some());
+        let err = existing
+            .try_acquire_default(&resources)
+            .await
+            .err()
+            .ok_or("cross slot resources accepted")?;
+        assert_eq!(err.kind(), redis::ErrorKind::CrossSlot);
+        // The slots are those of the keys named by the key formatter.
+        let formatted = redis_lock::MultiResourceLock::new(client.clone())?
+            .with_hash_tag(HashTag::Existing)
+            .with_key_formatter(Arc::new(Tagged));
+        assert!(formatted.try_acquire_default(&resources).await?.is_some());
+        Ok(())
+    })
+}
+
+#[expect(
+    clippy::panic_in_result_fn,
+    reason = "It's annoying to handle the error here."
+)]
+#[expect(clippy::tests_outside_test_module, reason = "`#[serial]` breaks it")] // TODO Fix this.
+#[test]
+#[serial]
+fn release_outcome() -> Result<(), Box<dyn Error>> {
+    use redis_lock::ReleaseOutcome;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    /// Counts the calls to [`redis_lock::metrics::Metrics::on_release_expired`].
+    #[derive(Default)]
+    struct Expired(AtomicUsize);
+    impl redis_lock::metrics::Metrics for Expired {
+        fn on_release_expired(&self) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    let redis = redis_lock::test_support::TestRedis::start()?;
+    tokio::runtime::Runtime::new()?.block_on(async {
+        let expired = Arc::new(Expired::default());
+        let lock = redis
+            .lock()
+            .await?
+            .with_metrics(Arc::<Expired>::clone(&expired));
+        let resources = vec![String::from("a"), String::from("b")];
+
+        let held = lock
+            .try_acquire_default(&resources)
+            .await?
+            .ok_or("contended")?;
+        assert_eq!(
+            lock.release_outcome(&held).await?,
+            ReleaseOutcome::Released(2)
+        );
+        assert_eq!(lock.release_outcome(&held).await?, ReleaseOutcome::NotHeld);
+
+        // Releasing after the expiration lapsed is reported.
+        let lapsed = lock
+            .try_acquire(&resources, Duration::from_millis(50))
+            .await?
+            .ok_or("contended")?;
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(
+            lock.release_outcome(&lapsed).await?,
+            ReleaseOutcome::AlreadyExpired
+        );
+        assert_eq!(expired.0.load(Ordering::SeqCst), 1);
+        Ok(())
+    })
+}
+
+#[expect(
+    clippy::panic_in_result_fn,
+    reason = "It's annoying to handle the error here."
+)]
+#[expect(clippy::tests_outside_test_module, reason = "`#[serial]` breaks it")] // TODO Fix this.
+#[test]
+#[serial]
+fn acquire_timed() -> Result<(), Box<dyn Error>> {
+    use std::time::Duration;
+
+    let redis = redis_lock::test_support::TestRedis::start()?;
+    tokio::runtime::Runtime::new()?.block_on(async {
+        let lock = redis.lock().await?;
+        let resources = vec![String::from("a")];
+
+        let held = lock
+            .try_acquire_default(&resources)
+            .await?
+            .ok_or("contended")?;
+        let releasing = lock.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            releasing.release(&held).await
+        });
+        let (_lock_id, wait) = lock
+            .acquire_timed(
+                &resources,
+                redis_lock::DEFAULT_EXPIRATION,
+                Duration::from_secs(10),
+                Duration::from_millis(20),
+            )
+            .await?
+            .ok_or("timed out")?;
+        assert!(wait >= Duration::from_millis(150));
+        assert!(wait < Duration::from_secs(10));
+        Ok(())
+    })
+}
+
+#[expect(
+    clippy::panic_in_result_fn,
+    reason = "It's annoying to handle the error here."
+)]
+#[expect(clippy::tests_outside_test_module, reason = "`#[serial]` breaks it")] // TODO Fix this.
+#[test]
+#[serial]
+fn key_formatter() -> Result<(), Box<dyn Error>> {
+    use std::sync::Arc;
+
+    /// Stores locks under `locks:<resource>`.
+    struct Plural;
+    impl redis_lock::keys::KeyFormatter for Plural {
+        fn lock_prefix(&self) -> &'static str {
+            "locks:"
+        }
+    }
+
+    let redis = redis_lock::test_support::TestRedis::start()?;
+    tokio::runtime::Runtime::new()?.block_on(async {
+        let client = redis.client().clone();
+        let mut conn = client.get_multiplexed_async_connection().await?;
+        let lock = redis.lock().await?.with_key_formatter(Arc::new(Plural));
+        let resources = vec![String::from("a")];
+
+        let held = lock
+            .try_acquire_default(&resources)
+            .await?
+            .ok_or("contended")?;
+        assert!(conn.exists::<_, bool>("locks:a").await?);
+        assert!(!conn.exists::<_, bool>("lock:a").await?);
+        assert_eq!(lock.lock_resources(&held).await?, resources);
+        assert_eq!(lock.release(&held).await?, 1);
+        assert!(!conn.exists::<_, bool>("locks:a").await?);
+        Ok(())
+    })
+}
+
+#[expect(
+    clippy::panic_in_result_fn,
+    reason = "It's annoying to handle the error here."
+)]
+#[expect(clippy::tests_outside_test_module, reason = "`#[serial]` breaks it")] // TODO Fix this.
+#[test]
+#[serial]
+fn probe() -> Result<(), Box<dyn Error>> {
+    let redis = redis_lock::test_support::TestRedis::start()?;
+    tokio::runtime::Runtime::new()?.block_on(async {
+        let client = redis.client().clone();
+        let mut conn = client.get_multiplexed_async_connection().await?;
+        let lock = redis.lock().await?;
+        let resources = vec![String::from("a"), String::from("b"), String::from("c")];
+
+        let _held = lock
+            .try_acquire_default(&[String::from("b")])
+            .await?
+            .ok_or("contended")?;
+        let mut before: Vec<String> = conn.keys("*").await?;
+        before.sort();
+        assert_eq!(
+            lock.probe(&resources).await?,
+            vec![
+                (String::from("a"), false),
+                (String::from("b"), true),
+                (String::from("c"), false)
+            ]
+        );
+        // Probing writes nothing.
+        let mut after: Vec<String> = conn.keys("*").await?;
+        after.sort();
+        assert_eq!(before, after);
+        Ok(())
+    })
+}
+
+#[expect(
+    clippy::panic_in_result_fn,
+    reason = "It's annoying to handle the error here."
+)]
+#[expect(clippy::tests_outside_test_module, reason = "`#[serial]` breaks it")] // TODO Fix this.
+#[test]
+#[serial]
+fn waiter_count() -> Result<(), Box<dyn Error>> {
+    use std::time::Duration;
+
+    let redis = redis_lock::test_support::TestRedis::start()?;
+    tokio::runtime::Runtime::new()?.block_on(async {
+        let lock = redis.lock().await?;
+        let resources = vec![String::from("a")];
+
+        let held = lock
+            .try_acquire_default(&resources)
+            .await?
+            .ok_or("contended")?;
+        assert_eq!(lock.waiter_count("a").await?, 0);
+
+        // Contended attempts are counted for a while.
+        assert!(lock.try_acquire_default(&resources).await?.is_none());
+        assert!(lock.try_acquire_default(&resources).await?.is_none());
+        assert_eq!(lock.waiter_count("a").await?, 2);
+        tokio::time::sleep(redis_lock::DEFAULT_SLEEP + Duration::from_millis(100)).await;
+        assert_eq!(lock.waiter_count("a").await?, 0);
+
+        // Queued waiters are counted exactly.
+        let fair = lock.clone().with_fair_queue(true);
+        let waiting = tokio::spawn(async move {
+            fair.acquire(
+                &[String::from("a")],
+                redis_lock::DEFAULT_EXPIRATION,
+                Duration::from_secs(10),
+                Duration::from_millis(100),
+            )
+            .await
+        });
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        assert_eq!(lock.waiter_count("a").await?, 1);
+        lock.release(&held).await?;
+        let acquired = waiting.await??.ok_or("timed out")?;
+        assert_eq!(lock.waiter_count("a").await?, 0);
+        lock.release(&acquired).await?;
+        Ok(())
+    })
+}
+
+#[expect(
+    clippy::panic_in_result_fn,
+    reason = "It's annoying to handle the error here."
+)]
+#[expect(clippy::tests_outside_test_module, reason = "`#[serial]` breaks it")] // TODO Fix this.
+#[test]
+#[serial]
+fn try_acquire_if() -> Result<(), Box<dyn Error>> {
+    use redis_lock::condition::LuaCondition;
+    use redis_lock::AcquireResult;
+
+    let redis = redis_lock::test_support::TestRedis::start()?;
+    tokio::runtime::Runtime::new()?.block_on(async {
+        let client = redis.client().clone();
+        let mut conn = client.get_multiplexed_async_connection().await?;
+        let lock = redis.lock().await?;
+        let resources = vec![String::from("account1")];
+        let positive = LuaCondition::GreaterThan {
+            key: String::from("balance"),
+            value: 0.0f64,
+        };
+
+        // Missing keys fail numeric conditions.
+        assert_eq!(
+            lock.try_acquire_if(&resources, redis_lock::DEFAULT_EXPIRATION, &positive)
+                .await?,
+            AcquireResult::PreconditionFailed
+        );
+        assert!(!conn.exists::<_, bool>("lock:account1").await?);
+
+        conn.set::<_, _, ()>("balance", 0i32).await?;
+        assert_eq!(
+            lock.try_acquire_if(&resources, redis_lock::DEFAULT_EXPIRATION, &positive)
+                .await?,
+            AcquireResult::PreconditionFailed
+        );
+
+        conn.set::<_, _, ()>("balance", 10i32).await?;
+        let lock_id = lock
+            .try_acquire_if(&resources, redis_lock::DEFAULT_EXPIRATION, &positive)
+            .await?
+            .acquired()
+            .ok_or("not acquired")?;
+        assert!(!lock
+            .try_acquire_if(
+                &resources,
+                redis_lock::DEFAULT_EXPIRATION,
+                &LuaCondition::Exists(String::from("balance"))
+            )
+            .await?
+            .is_acquired());
+        assert_eq!(lock.release(&lock_id).await?, 1);
+        Ok(())
+    })
+}
+
+#[expect(
+    clippy::panic_in_result_fn,
+    reason = "It's annoying to handle the error here."
+)]
+#[expect(clippy::tests_outside_test_module, reason = "`#[serial]` breaks it")] // TODO Fix this.
+#[test]
+#[serial]
+fn extend_at_least() -> Result<(), Box<dyn Error>> {
+    use std::time::Duration;
+
+    let redis = redis_lock::test_support::TestRedis::start()?;
+    tokio::runtime::Runtime::new()?.block_on(async {
+        let client = redis.client().clone();
+        let mut conn = client.get_multiplexed_async_connection().await?;
+        let lock = redis.lock().await?;
+        let resources = vec![String::from("a"), String::from("b")];
+
+        let lock_id = lock
+            .try_acquire(&resources, Duration::from_secs(60))
+            .await?
+            .ok_or("contended")?;
+        // A shorter extension keeps the longer expiration.
+        assert!(
+            lock.extend_at_least(&lock_id, Duration::from_secs(5))
+                .await?
+        );
+        assert!(conn.pttl::<_, i64>("lock:a").await? > 50_000i64);
+        // A longer extension lengthens it.
+        assert!(
+            lock.extend_at_least(&lock_id, Duration::from_secs(120))
+                .await?
+        );
+        assert!(conn.pttl::<_, i64>("lock:b").await? > 100_000i64);
+
+        lock.release(&lock_id).await?;
+        assert!(
+            !lock
+                .extend_at_least(&lock_id, Duration::from_secs(5))
+                .await?
+        );
+        Ok(())
+    })
+}
+
+#[expect(
+    clippy::panic_in_result_fn,
+    reason = "It's annoying to handle the error here."
+)]
+#[expect(clippy::tests_outside_test_module, reason = "`#[serial]` breaks it")] // TODO Fix this.
+#[test]
+#[serial]
+fn retry_transient() -> Result<(), Box<dyn Error>> {
+    use redis::{ErrorKind, RedisError};
+    use std::time::Duration;
+
+    let reset = RedisError::from(std::io::Error::from(std::io::ErrorKind::ConnectionReset));
+    assert!(redis_lock::is_transient(&reset));
+    assert!(redis_lock::is_transient(&RedisError::from((
+        ErrorKind::MasterDown,
+        "MASTERDOWN"
+    ))));
+    assert!(!redis_lock::is_transient(&RedisError::from((
+        ErrorKind::ResponseError,
+        "An error was signalled by the server",
+        String::from("Function not found")
+    ))));
+
+    let redis = redis_lock::test_support::TestRedis::start()?;
+    tokio::runtime::Runtime::new()?.block_on(async {
+        let client = redis.client().clone();
+        let mut conn = client.get_multiplexed_async_connection().await?;
+        redis::cmd("FUNCTION")
+            .arg("FLUSH")
+            .exec_async(&mut conn)
+            .await?;
+        let lock = redis_lock::MultiResourceLock::new(client.clone())?.with_retry_transient(true);
+        let resources = vec![String::from("a")];
+
+        // Logical errors still fail fast.
+        let acquiring = lock.acquire(
+            &resources,
+            redis_lock::DEFAULT_EXPIRATION,
+            Duration::from_secs(10),
+            Duration::from_millis(10),
+        );
+        let result = tokio::time::timeout(Duration::from_secs(1), acquiring).await?;
+        if result.is_ok() {
+            return Err("acquired without the library loaded".into());
+        }
+
+        redis_lock::setup(&client).await?;
+        let lock_id = lock.acquire_default(&resources).await?.ok_or("timed out")?;
+        assert_eq!(lock.release(&lock_id).await?, 1);
+        Ok(())
+    })
+}
+
+#[expect(
+    clippy::panic_in_result_fn,
+    reason = "It's annoying to handle the error here."
+)]
+#[expect(clippy::tests_outside_test_module, reason = "`#[serial]` breaks it")] // TODO Fix this.
+#[test]
+#[serial]
+fn command_timeout() -> Result<(), Box<dyn Error>> {
+    use std::net::TcpListener;
+    use std::time::{Duration, Instant};
+
+    // A server that accepts connections but never replies.
+    let stalled = TcpListener::bind("127.0.0.1:0")?;
+    let port = stalled.local_addr()?.port();
+    let _accepting = std::thread::spawn(move || {
+        let _connections = stalled.incoming().collect::<Vec<_>>();
+    });
+
+    tokio::runtime::Runtime::new()?.block_on(async {
+        let client = Client::open(format!("redis://127.0.0.1:{port}/"))?;
+        let lock = redis_lock::MultiResourceLock::new(client)?
+            .with_command_timeout(Duration::from_millis(100));
+
+        // Fails within the command timeout rather than the acquire timeout.
+        let start = Instant::now();
+        let result = lock
+            .acquire(
+                &[String::from("a")],
+                redis_lock::DEFAULT_EXPIRATION,
+                Duration::from_secs(30),
+                Duration::from_millis(10),
+            )
+            .await;
+        assert!(result.is_err_and(|err| err.is_timeout()));
+        assert!(start.elapsed() < Duration::from_secs(5));
+        Ok(())
+    })
+}
+
+#[expect(
+    clippy::panic_in_result_fn,
+    reason = "It's annoying to handle the error here."
+)]
+#[expect(clippy::tests_outside_test_module, reason = "`#[serial]` breaks it")] // TODO Fix this.
+#[test]
+#[serial]
+fn acquire_within() -> Result<(), Box<dyn Error>> {
+    use redis_lock::AcquireResult;
+    use std::net::TcpListener;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+
+    /// Counts the calls to [`redis_lock::metrics::Metrics::on_timeout`].
+    #[derive(Default)]
+    struct Timeouts(AtomicUsize);
+    impl redis_lock::metrics::Metrics for Timeouts {
+        fn on_timeout(&self) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    // A server that accepts connections but never replies.
+    let stalled = TcpListener::bind("127.0.0.1:0")?;
+    let port = stalled.local_addr()?.port();
+    let _accepting = std::thread::spawn(move || {
+        let _connections = stalled.incoming().collect::<Vec<_>>();
+    });
+
+    tokio::runtime::Runtime::new()?.block_on(async {
+        let client = Client::open(format!("redis://127.0.0.1:{port}/"))?;
+        let timeouts = Arc::new(Timeouts::default());
+        let lock = redis_lock::MultiResourceLock::new(client)?
+            .with_metrics(Arc::<Timeouts>::clone(&timeouts));
+
+        // Times out within the total bound even though no Redis call ever completes.
+        let start = Instant::now();
+        let result = lock
+            .acquire_within(
+                &[String::from("a")],
+                redis_lock::DEFAULT_EXPIRATION,
+                Duration::from_millis(200),
+                Duration::from_millis(10),
+            )
+            .await?;
+        assert_eq!(result, AcquireResult::TimedOut);
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert_eq!(timeouts.0.load(Ordering::SeqCst), 1);
+        Ok(())
+    })
+}
+
+#[expect(
+    clippy::panic_in_result_fn,
+    reason = "It's annoying to handle the error here."
+)]
+#[expect(clippy::tests_outside_test_module, reason = "`#[serial]` breaks it")] // TODO Fix this.
+#[test]
+#[serial]
+fn acquire_within_releases_cut_off() -> Result<(), Box<dyn Error>> {
+    use redis_lock::AcquireResult;
+    use std::time::{Duration, Instant};
+
+    let redis = redis_lock::test_support::TestRedis::start()?;
+    tokio::runtime::Runtime::new()?.block_on(async {
+        let client = redis.client().clone();
+        let mut conn = client.get_multiplexed_async_connection().await?;
+        let lock = redis.lock().await?;
+
+        // Writes stall, so the first attempt is cut short though it acquires the lock once they
+        // resume.
+        redis::cmd("CLIENT")
+            .arg("PAUSE")
+            .arg(1000u32)
+            .arg("WRITE")
+            .exec_async(&mut conn)
+            .await?;
+        let start = Instant::now();
+        let result = lock
+            .acquire_within(
+                &[String::from("a")],
+                redis_lock::INDEFINITE,
+                Duration::from_millis(200),
+                Duration::from_millis(10),
+            )
+            .await?;
+        assert_eq!(result, AcquireResult::TimedOut);
+        // Returned without waiting for the writes to resume.
+        assert!(start.elapsed() < Duration::from_millis(1000));
+
+        // The lock acquired by the cut off attempt is released rather than held forever.
+        tokio::time::sleep(Duration::from_millis(1500)).await;
+        let other = redis_lock::MultiResourceLock::new(client.clone())?;
+        assert!(other
+            .try_acquire_default(&[String::from("a")])
+            .await?
+            .is_some());
+        Ok(())
+    })
+}
+
+#[expect(
+    clippy::panic_in_result_fn,
+    reason = "It's annoying to handle the error here."
+)]
+#[expect(clippy::tests_outside_test_module, reason = "`#[serial]` breaks it")] // TODO Fix this.
+#[test]
+#[serial]
+fn call_timeout_leaves_queue() -> Result<(), Box<dyn Error>> {
+    use std::time::Duration;
+
+    let redis = redis_lock::test_support::TestRedis::start()?;
+    tokio::runtime::Runtime::new()?.block_on(async {
+        let client = redis.client().clone();
+        let mut conn = client.get_multiplexed_async_connection().await?;
+        let holder = redis.lock().await?;
+        holder
+            .try_acquire_default(&[String::from("a")])
+            .await?
+            .ok_or("contended")?;
+        let lock = redis_lock::MultiResourceLock::new(client.clone())?.with_fair_queue(true);
+
+        // Writes stall part way through waiting, so an attempt is cut short by the timeout.
+        let pausing = tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(250)).await;
+            let mut paused = client.get_multiplexed_async_connection().await?;
+            redis::cmd("CLIENT")
+                .arg("PAUSE")
+                .arg(1000u32)
+                .arg("WRITE")
+                .exec_async(&mut paused)
+                .await
+        });
+        let result = lock
+            .acquire(
+                &[String::from("a")],
+                redis_lock::DEFAULT_EXPIRATION,
+                Duration::from_millis(400),
+                Duration::from_millis(100),
+            )
+            .await;
+        assert!(result.is_err_and(|err| err.is_timeout()));
+        pausing.await??;
+        // The waiter left the queue rather than blocking others until it expires.
+        assert_eq!(conn.zcard::<_, usize>("queue:a").await?, 0);
+        Ok(())
+    })
+}
+
+#[expect(
+    clippy::panic_in_result_fn,
+    reason = "It's annoying to handle the error here."
+)]
+#[expect(clippy::tests_outside_test_module, reason = "`#[serial]` breaks it")] // TODO Fix this.
+#[test]
+#[serial]
+fn local_queue() -> Result<(), Box<dyn Error>> {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    /// Counts the calls to [`redis_lock::metrics::Metrics::on_acquire_attempt`].
+    #[derive(Default)]
+    struct Attempts(AtomicUsize);
+    impl redis_lock::metrics::Metrics for Attempts {
+        fn on_acquire_attempt(&self) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    let redis = redis_lock::test_support::TestRedis::start()?;
+    tokio::runtime::Runtime::new()?.block_on(async {
+        let client = redis.client().clone();
+        let other = redis.lock().await?;
+        let attempts = Arc::new(Attempts::default());
+        let lock = redis_lock::MultiResourceLock::new(client.clone())?
+            .with_metrics(Arc::<Attempts>::clone(&attempts))
+            .with_local_queue(true);
+
+        let held = other
+            .try_acquire_default(&[String::from("a")])
+            .await?
+            .ok_or("contended")?;
+        let contenders = (0..8u8)
+            .map(|_| {
+                let local = lock.clone();
+                tokio::spawn(async move {
+                    let resources = [String::from("a")];
+                    let lock_id = local
+                        .acquire(
+                            &resources,
+                            redis_lock::DEFAULT_EXPIRATION,
+                            Duration::from_secs(10),
+                            Duration::from_millis(50),
+                        )
+                        .await?
+                        .ok_or("timed out")?;
+                    local.release(&lock_id).await?;
+                    Ok::<_, Box<dyn Error + Send + Sync>>(())
+                })
+            })
+            .collect::<Vec<_>>();
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        other.release(&held).await?;
+        for contender in contenders {
+            contender.await?.map_err(|err| err.to_string())?;
+        }
+
+        // Only one contender polled Redis while the lock was held elsewhere.
+        assert!(attempts.0.load(Ordering::SeqCst) < 40);
+        Ok(())
+    })
+}
+
+/// Records the messages logged to the `locks` target.
+#[cfg(feature = "log")]
+struct Recorder(std::sync::Mutex<Vec<String>>);
+
+#[cfg(feature = "log")]
+impl log::Log for Recorder {
+    fn enabled(&self, metadata: &log::Metadata<'_>) -> bool {
+        metadata.target() == "locks"
+    }
+    fn log(&self, record: &log::Record<'_>) {
+        if self.enabled(record.metadata()) {
+            self.0
+                .lock()
+                .unwrap_or_else(std::sync::PoisonError::into_inner)
+                .push(record.args().to_string());
+        }
+    }
+    fn flush(&self) {}
+}
+
+/// The logger of every test, set once by [`record_logs`].
+#[cfg(feature = "log")]
+static RECORDER: Recorder = Recorder(std::sync::Mutex::new(Vec::new()));
+
+/// Records the messages logged to the `locks` target from now on, see [`recorded_logs`].
+#[cfg(feature = "log")]
+fn record_logs() {
+    if log::set_logger(&RECORDER).is_ok() {
+        log::set_max_level(log::LevelFilter::Debug);
+    }
+    RECORDER
+        .0
+        .lock()
+        .unwrap_or_else(std::sync::PoisonError::into_inner)
+        .clear();
+}
+
+/// Returns the messages logged to the `locks` target since [`record_logs`].
+#[cfg(feature = "log")]
+fn recorded_logs() -> Vec<String> {
+    RECORDER
+        .0
+        .lock()
+        .unwrap_or_else(std::sync::PoisonError::into_inner)
+        .clone()
+}
+
+#[cfg(feature = "log")]
+#[expect(
+    clippy::panic_in_result_fn,
+    reason = "It's annoying to handle the error here."
+)]
+#[expect(clippy::tests_outside_test_module, reason = "`#[serial]` breaks it")] // TODO Fix this.
+#[test]
+#[serial]
+fn log_target() -> Result<(), Box<dyn Error>> {
+    record_logs();
+    let redis = redis_lock::test_support::TestRedis::start()?;
+    tokio::runtime::Runtime::new()?.block_on(async {
+        let lock = redis.lock().await?.with_log_target("locks");
+
+        let lock_id = lock
+            .try_acquire_default(&[String::from("a"), String::from("b")])
+            .await?
+            .ok_or("contended")?;
+        lock.release(&lock_id).await?;
+        assert_eq!(
+            recorded_logs(),
+            vec![
+                format!("attempting to acquire lock {lock_id} on 2 resources"),
+                format!("acquired lock {lock_id} on 2 resources"),
+                format!("released lock {lock_id} on 2 resources"),
+            ]
+        );
+        Ok(())
+    })
+}
+
+#[expect(
+    clippy::panic_in_result_fn,
+    reason = "It's annoying to handle the error here."
+)]
+#[expect(clippy::tests_outside_test_module, reason = "`#[serial]` breaks it")] // TODO Fix this.
+#[test]
+#[serial]
+fn events() -> Result<(), Box<dyn Error>> {
+    use futures_util::StreamExt as _;
+    use redis_lock::events::LockEvent;
+    use std::time::Duration;
+
+    let redis = redis_lock::test_support::TestRedis::start()?;
+    tokio::runtime::Runtime::new()?.block_on(async {
+        let lock = redis.lock().await?;
+        let resources = vec![String::from("a")];
+
+        let events = lock.events().await?;
+        let lock_id = lock
+            .try_acquire_default(&resources)
+            .await?
+            .ok_or("contended")?;
+        lock.release(&lock_id).await?;
+
+        let received = tokio::time::timeout(
+            Duration::from_secs(1),
+            Box::pin(events).take(2).collect::<Vec<_>>(),
+        )
+        .await?;
+        assert_eq!(
+            received,
+            vec![
+                LockEvent::Acquired {
+                    lock_id: lock_id.clone(),
+                    resources: resources.clone()
+                },
+                LockEvent::Released { lock_id, resources }
+            ]
+        );
+        Ok(())
+    })
+}
+
+#[expect(
+    clippy::panic_in_result_fn,
+    reason = "It's annoying to handle the error here."
+)]
+#[expect(clippy::tests_outside_test_module, reason = "`#[serial]` breaks it")] // TODO Fix this.
+#[test]
+#[serial]
+fn id_generator() -> Result<(), Box<dyn Error>> {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    let redis = redis_lock::test_support::TestRedis::start()?;
+    tokio::runtime::Runtime::new()?.block_on(async {
+        let client = redis.client().clone();
+        redis_lock::setup(&client).await?;
+        let counter = AtomicUsize::new(0);
+        let lock = redis_lock::MultiResourceLock::new(client.clone())?.with_id_generator(Arc::new(
+            move || format!("node-{}", counter.fetch_add(1, Ordering::SeqCst)),
+        ));
+
+        let first = lock
+            .try_acquire_default(&[String::from("a")])
+            .await?
+            .ok_or("contended")?;
+        let second = lock
+            .try_acquire_default(&[String::from("b")])
+            .await?
+            .ok_or("contended")?;
+        assert_eq!(first, "node-0");
+        assert_eq!(second, "node-1");
+        assert_eq!(lock.release(&first).await?, 1);
+        assert_eq!(lock.release(&second).await?, 1);
+        Ok(())
+    })
+}
+
+#[expect(
+    clippy::panic_in_result_fn,
+    reason = "It's annoying to handle the error here."
+)]
+#[expect(clippy::tests_outside_test_module, reason = "`#[serial]` breaks it")] // TODO Fix this.
+#[test]
+#[serial]
+fn strict_expiration() -> Result<(), Box<dyn Error>> {
+    use std::time::Duration;
+
+    let redis = redis_lock::test_support::TestRedis::start()?;
+    tokio::runtime::Runtime::new()?.block_on(async {
+        let client = redis.client().clone();
+        let mut conn = client.get_multiplexed_async_connection().await?;
+        let lock = redis.lock().await?.with_strict_expiration(true);
+        let resources = vec![String::from("a")];
+
+        let shorter_than_timeout = lock
+            .acquire(
+                &resources,
+                Duration::from_millis(500),
+                Duration::from_secs(60),
+                Duration::from_millis(100),
+            )
+            .await;
+        if shorter_than_timeout.is_ok() {
+            return Err("acquired with an expiration shorter than the timeout".into());
+        }
+        let shorter_than_sleep = lock
+            .acquire(
+                &resources,
+                Duration::from_millis(500),
+                Duration::from_millis(100),
+                Duration::from_secs(1),
+            )
+            .await;
+        if shorter_than_sleep.is_ok() {
+            return Err("acquired with an expiration shorter than the sleep".into());
+        }
+        assert!(!conn.exists::<_, bool>("lock:a").await?);
+
+        let indefinite = lock
+            .acquire(
+                &resources,
+                redis_lock::INDEFINITE,
+                Duration::from_secs(60),
+                Duration::from_millis(100),
+            )
+            .await?
+            .ok_or("timed out")?;
+        assert_eq!(lock.release(&indefinite).await?, 1);
+        let longer = lock
+            .acquire(
+                &resources,
+                Duration::from_secs(60),
+                Duration::from_secs(1),
+                Duration::from_millis(100),
+            )
+            .await?
+            .ok_or("timed out")?;
+        assert_eq!(lock.release(&longer).await?, 1);
+        Ok(())
+    })
+}
+
+#[expect(
+    clippy::panic_in_result_fn,
+    reason = "It's annoying to handle the error here."
+)]
+#[expect(clippy::tests_outside_test_module, reason = "`#[serial]` breaks it")] // TODO Fix this.
+#[test]
+#[serial]
+fn guarded_move() -> Result<(), Box<dyn Error>> {
+    use redis_lock::MoveOutcome;
+
+    let redis = redis_lock::test_support::TestRedis::start()?;
+    tokio::runtime::Runtime::new()?.block_on(async {
+        let client = redis.client().clone();
+        let mut conn = client.get_multiplexed_async_connection().await?;
+        let lock = redis.lock().await?;
+        let resources = vec![String::from("account1"), String::from("account2")];
+        conn.set::<_, _, ()>("account1", 100i32).await?;
+
+        let lock_id = lock
+            .try_acquire_default(&resources)
+            .await?
+            .ok_or("contended")?;
+        assert_eq!(
+            lock.guarded_move(&lock_id, "account1", "account2", 60)
+                .await?,
+            MoveOutcome::Moved
+        );
+        assert_eq!(
+            lock.guarded_move(&lock_id, "account1", "account2", 60)
+                .await?,
+            MoveOutcome::Insufficient
+        );
+        assert_eq!(conn.get::<_, i64>("account1").await?, 40);
+        assert_eq!(conn.get::<_, i64>("account2").await?, 60);
+        // Nothing is moved to a key the lock does not hold.
+        assert_eq!(
+            lock.guarded_move(&lock_id, "account1", "account3", 10)
+                .await?,
+            MoveOutcome::NotHeld
+        );
+
+        assert_eq!(lock.release(&lock_id).await?, 2);
+        assert_eq!(
+            lock.guarded_move(&lock_id, "account1", "account2", 10)
+                .await?,
+            MoveOutcome::NotHeld
+        );
+        assert_eq!(conn.get::<_, i64>("account1").await?, 40);
+        Ok(())
+    })
+}
+
+#[expect(
+    clippy::panic_in_result_fn,
+    reason = "It's annoying to handle the error here."
+)]
+#[expect(clippy::tests_outside_test_module, reason = "`#[serial]` breaks it")] // TODO Fix this.
+#[test]
+#[serial]
+fn ttls() -> Result<(), Box<dyn Error>> {
+    use std::time::Duration;
+
+    let redis = redis_lock::test_support::TestRedis::start()?;
+    tokio::runtime::Runtime::new()?.block_on(async {
+        let lock = redis.lock().await?;
+
+        let lock_id = lock
+            .try_acquire(
+                &[String::from("b"), String::from("a")],
+                Duration::from_secs(60),
+            )
+            .await?
+            .ok_or("contended")?;
+        assert!(
+            lock.try_extend(&lock_id, &[String::from("a")], Duration::from_secs(120))
+                .await?
+        );
+        let ttls = lock.ttls(&lock_id).await?;
+        let resources: Vec<&str> = ttls.iter().map(|(resource, _)| resource.as_str()).collect();
+        assert_eq!(resources, ["a", "b"]);
+        assert!(ttls.first().ok_or("no ttls")?.1 > Duration::from_secs(100));
+        assert!(ttls.get(1).ok_or("no ttls")?.1 <= Duration::from_secs(60));
+
+        lock.release(&lock_id).await?;
+        assert!(lock.ttls(&lock_id).await?.is_empty());
+        Ok(())
+    })
+}
+
+#[expect(
+    clippy::panic_in_result_fn,
+    reason = "It's annoying to handle the error here."
+)]
+#[expect(clippy::tests_outside_test_module, reason = "`#[serial]` breaks it")] // TODO Fix this.
+#[test]
+#[serial]
+fn namespaced_resources() -> Result<(), Box<dyn Error>> {
+    use redis_lock::resource::Resource;
+    use std::time::Duration;
+
+    let redis = redis_lock::test_support::TestRedis::start()?;
+    tokio::runtime::Runtime::new()?.block_on(async {
+        let client = redis.client().clone();
+        let mut conn = client.get_multiplexed_async_connection().await?;
+        let lock = redis.lock().await?;
+        let expiration = Duration::from_secs(10);
+
+        // One lock spans both namespaces.
+        let accounts = Resource::new(String::from("accounts"), String::from("a"));
+        let orders = Resource::new(String::from("orders"), String::from("a"));
+        let held = lock
+            .try_acquire_resources(&[accounts.clone(), orders.clone()], expiration)
+            .await?
+            .ok_or("contended")?;
+        assert!(conn.exists::<_, bool>("lock:accounts:a").await?);
+        assert!(conn.exists::<_, bool>("lock:orders:a").await?);
+        let locked = lock.lock_resources(&held).await?;
+        assert_eq!(
+            locked
+                .iter()
+                .map(|key| Resource::parse(key))
+                .collect::<Vec<_>>(),
+            vec![Some(accounts.clone()), Some(orders)]
+        );
+        assert!(lock
+            .try_acquire_resources(&[accounts], expiration)
+            .await?
+            .is_none());
+
+        // Namespaces containing the separator don't collide.
+        let first = Resource::new(String::from("a:b"), String::from("c"));
+        let second = Resource::new(String::from("a"), String::from("b:c"));
+        assert_ne!(first.key(), second.key());
+        assert_eq!(Resource::parse(&first.key()), Some(first.clone()));
+        assert_eq!(Resource::parse(&second.key()), Some(second.clone()));
+        assert!(lock
+            .try_acquire_resources(&[first], expiration)
+            .await?
+            .is_some());
+        assert!(lock
+            .try_acquire_resources(&[second], expiration)
+            .await?
+            .is_some());
+        Ok(())
+    })
+}
+
+#[expect(
+    clippy::panic_in_result_fn,
+    reason = "It's annoying to handle the error here."
+)]
+#[expect(clippy::tests_outside_test_module, reason = "`#[serial]` breaks it")] // TODO Fix this.
+#[test]
+#[serial]
+fn resource_keys() -> Result<(), Box<dyn Error>> {
+    use redis_lock::resource::{Resource, ResourceKey};
+    use std::time::Duration;
+
+    /// An account locked as `account:<id>`.
+    struct AccountId(u64);
+    impl ResourceKey for AccountId {
+        fn resource_key(&self) -> String {
+            Resource::new(String::from("account"), self.0.to_string()).key()
+        }
+    }
+
+    let redis = redis_lock::test_support::TestRedis::start()?;
+    tokio::runtime::Runtime::new()?.block_on(async {
+        let client = redis.client().clone();
+        let mut conn = client.get_multiplexed_async_connection().await?;
+        let lock = redis.lock().await?;
+        let expiration = Duration::from_secs(10);
+
+        let held = lock
+            .try_acquire_keys(&[AccountId(1), AccountId(2)], expiration)
+            .await?
+            .ok_or("contended")?;
+        assert!(conn.exists::<_, bool>("lock:account:1").await?);
+        assert!(conn.exists::<_, bool>("lock:account:2").await?);
+        assert!(lock
+            .try_acquire_keys(&[AccountId(2)], expiration)
+            .await?
+            .is_none());
+        // Other types lock their own keys.
+        assert!(lock.try_acquire_keys(&[2u64], expiration).await?.is_some());
+        assert!(lock.try_acquire_keys(&["b"], expiration).await?.is_some());
+        assert!(conn.exists::<_, bool>("lock:2").await?);
+        lock.release(&held).await?;
+        Ok(())
+    })
+}
+
+#[expect(
+    clippy::panic_in_result_fn,
+    reason = "It's annoying to handle the error here."
+)]
+#[expect(clippy::tests_outside_test_module, reason = "`#[serial]` breaks it")] // TODO Fix this.
+#[test]
+#[serial]
+fn ephemeral_redis() -> Result<(), Box<dyn Error>> {
+    let redis = redis_lock::test_support::TestRedis::start()?;
+    tokio::runtime::Runtime::new()?.block_on(async {
+        let lock = redis.lock().await?;
+        let resources = vec![String::from("a")];
+
+        let lock_id = lock
+            .try_acquire_default(&resources)
+            .await?
+            .ok_or("contended")?;
+        let mut conn = redis.client().get_multiplexed_async_connection().await?;
+        assert!(conn.exists::<_, bool>("lock:a").await?);
+        assert_eq!(lock.release(&lock_id).await?, 1);
+        Ok(())
+    })
+}
+
+#[expect(
+    clippy::panic_in_result_fn,
+    reason = "It's annoying to handle the error here."
+)]
+#[expect(clippy::tests_outside_test_module, reason = "`#[serial]` breaks it")] // TODO Fix this.
+#[test]
+#[serial]
+fn library_version() -> Result<(), Box<dyn Error>> {
+    use redis_lock::SetupOutcome;
+    use std::collections::HashMap;
+
+    let redis = redis_lock::test_support::TestRedis::start()?;
+    tokio::runtime::Runtime::new()?.block_on(async {
+        let client = redis.client().clone();
+        let mut conn = client.get_multiplexed_async_connection().await?;
+        redis::cmd("FUNCTION").arg("FLUSH").exec_async(&mut conn).await?;
+        // A library loaded by a binary built against an older version.
+        redis::cmd("FUNCTION")
+            .arg("LOAD")
+            .arg("#!lua name=multilock\nredis.register_function('lock_held', function() return 1 end)")
+            .exec_async(&mut conn)
+            .await?;
+        assert_eq!(redis_lock::setup(&client).await?, SetupOutcome::Loaded);
+        // Re-doing it leaves the loaded library as is.
+        assert_eq!(
+            redis_lock::setup(&client).await?,
+            SetupOutcome::AlreadyCurrent
+        );
+
+        let libraries: Vec<HashMap<String, redis::Value>> = redis::cmd("FUNCTION")
+            .arg("LIST")
+            .query_async(&mut conn)
+            .await?;
+        assert_eq!(libraries.len(), 2);
+        // The version is bumped whenever the arguments of a function change, so this only
+        // changes with it.
+        assert_eq!(redis_lock::LIBRARY_VERSION, 3);
+        let current: Vec<HashMap<String, redis::Value>> = redis::cmd("FUNCTION")
+            .arg("LIST")
+            .arg("LIBRARYNAME")
+            .arg("multilock_v3")
+            .query_async(&mut conn)
+            .await?;
+        assert_eq!(current.len(), 1);
+        let held: bool = redis::cmd("FCALL")
+            .arg("lock_held_v3")
+            .arg(0i32)
+            // The key prefixes, the events channel, whether to index and the lock identifier.
+            .arg(&["lock:", "queue:", "waiter:", "weighted:", "weights:"])
+            .arg(&["index:", "poison:", "lock-events", "1", "id"])
+            .query_async(&mut conn)
+            .await?;
+        assert!(!held);
+        let old: i64 = redis::cmd("FCALL")
+            .arg("lock_held")
+            .arg(0i32)
+            .query_async(&mut conn)
+            .await?;
+        assert_eq!(old, 1);
+        let lock = redis_lock::MultiResourceLock::new(client.clone())?;
+        let lock_id = lock
+            .try_acquire_default(&[String::from("a")])
+            .await?
+            .ok_or("contended")?;
+        assert!(lock.is_held(&lock_id).await?);
+
+        // Different code of the same version is replaced.
+        redis::cmd("FUNCTION")
+            .arg("LOAD")
+            .arg("REPLACE")
+            .arg("#!lua name=multilock_v3\nredis.register_function('stale_v3', function() return 1 end)")
+            .exec_async(&mut conn)
+            .await?;
+        assert_eq!(redis_lock::setup(&client).await?, SetupOutcome::Replaced);
+        Ok(())
+    })
+}
+
+#[expect(
+    clippy::panic_in_result_fn,
+    reason = "It's annoying to handle the error here."
+)]
+#[expect(clippy::tests_outside_test_module, reason = "`#[serial]` breaks it")] // TODO Fix this.
+#[test]
+#[serial]
+fn mock_clock() -> Result<(), Box<dyn Error>> {
+    use redis_lock::time::Clock;
+    use std::future::Future;
+    use std::pin::Pin;
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, Instant};
+
+    /// A clock where sleeping advances time instantly.
+    struct MockClock {
+        /// The time the clock started at.
+        start: Instant,
+        /// The time slept so far.
+        elapsed: Mutex<Duration>,
+    }
+    #[expect(clippy::unwrap_used, reason = "It's a test")]
+    impl Clock for MockClock {
+        fn now(&self) -> Instant {
+            self.start + *self.elapsed.lock().unwrap()
+        }
+        fn sleep(&self, duration: Duration) -> Pin<Box<dyn Future<Output = ()> + Send>> {
+            *self.elapsed.lock().unwrap() += duration;
+            Box::pin(std::future::ready(()))
+        }
+    }
+
+    let redis = redis_lock::test_support::TestRedis::start()?;
+    tokio::runtime::Runtime::new()?.block_on(async {
+        let client = redis.client().clone();
+        redis_lock::setup(&client).await?;
+        let clock = Arc::new(MockClock {
+            start: Instant::now(),
+            elapsed: Mutex::new(Duration::ZERO),
+        });
+        let lock = redis_lock::MultiResourceLock::new(client.clone())?
+            .with_clock(Arc::<MockClock>::clone(&clock));
+
+        let resources = vec![String::from("a")];
+        lock.try_acquire_default(&resources)
+            .await?
+            .ok_or("contended")?;
+        let real = Instant::now();
+        let timed_out = lock
+            .acquire(
+                &resources,
+                redis_lock::DEFAULT_EXPIRATION,
+                Duration::from_secs(60),
+                Duration::from_secs(1),
+            )
+            .await?;
+        assert!(timed_out.is_none());
+        // Attempts are made at each second up to and including the timeout.
+        assert_eq!(
+            clock.now().duration_since(clock.start),
+            Duration::from_secs(61)
+        );
+        assert!(real.elapsed() < Duration::from_secs(10));
+        Ok(())
+    })
+}
+
+#[cfg(feature = "mock")]
+#[expect(
+    clippy::panic_in_result_fn,
+    reason = "It's annoying to handle the error here."
+)]
+#[expect(clippy::tests_outside_test_module, reason = "`#[serial]` breaks it")] // TODO Fix this.
+#[test]
+#[serial]
+fn mock() -> Result<(), Box<dyn Error>> {
+    use redis_lock::Lock;
+    use std::time::Duration;
+
+    /// Application code written against the shared trait.
+    async fn exclusive<L: Lock>(lock: &L) -> redis::RedisResult<bool> {
+        let resources = [String::from("b"), String::from("c")];
+        let Some(lock_id) = lock.try_acquire(&resources, Duration::from_secs(1)).await? else {
+            return Ok(false);
+        };
+        lock.release(&lock_id).await?;
+        Ok(true)
+    }
+
+    let redis = redis_lock::test_support::TestRedis::start()?;
+    tokio::runtime::Runtime::new()?.block_on(async {
+        let lock = redis_lock::mock::MockLock::new();
+        let other = lock.clone();
+
+        let resources = vec![String::from("a"), String::from("b")];
+        let lock_id = lock
+            .try_acquire(&resources, Duration::from_secs(60))?
+            .ok_or("contended")?;
+        // Partially overlapping locks are blocked across clones.
+        assert!(!exclusive(&other).await?);
+        assert_eq!(lock.release(&lock_id)?, 2);
+        assert!(exclusive(&other).await?);
+
+        // Locking no resources errors as it does against Redis.
+        let redis_err = redis
+            .lock()
+            .await?
+            .try_acquire(&[], Duration::from_secs(1))
+            .await
+            .err()
+            .ok_or("acquired no resources")?;
+        let mock_err = lock
+            .try_acquire(&[], Duration::from_secs(1))
+            .err()
+            .ok_or("acquired no resources")?;
+        assert_eq!(mock_err.kind(), redis_err.kind());
+        assert_eq!(mock_err.code(), redis_err.code());
+        assert_eq!(mock_err.detail(), redis_err.detail());
+        Ok(())
+    })
+}
+
+#[expect(
+    clippy::panic_in_result_fn,
+    reason = "It's annoying to handle the error here."
+)]
+#[expect(clippy::tests_outside_test_module, reason = "`#[serial]` breaks it")] // TODO Fix this.
+#[test]
+#[serial]
+fn null_lock() -> Result<(), Box<dyn Error>> {
+    use redis_lock::null::NullLock;
+    use redis_lock::Lock as _;
+
+    tokio::runtime::Runtime::new()?.block_on(async {
+        let lock = NullLock;
+        let resources = vec![String::from("a"), String::from("b")];
+
+        // Overlapping locks are never contended and releasing does nothing.
+        let first = lock
+            .try_acquire(&resources, redis_lock::DEFAULT_EXPIRATION)
+            .await?
+            .ok_or("contended")?;
+        let second = lock
+            .acquire(
+                &resources,
+                redis_lock::DEFAULT_EXPIRATION,
+                redis_lock::DEFAULT_TIMEOUT,
+                redis_lock::DEFAULT_SLEEP,
+            )
+            .await?
+            .ok_or("timed out")?;
+        assert_ne!(first, second);
+        assert_eq!(lock.release(&first).await?, 0);
+
+        // Guards are handed out for overlapping resources too.
+        let guard = lock
+            .try_lock(&resources, redis_lock::DEFAULT_EXPIRATION)?
+            .ok_or("contended")?;
+        let other = lock
+            .lock(
+                &resources,
+                redis_lock::DEFAULT_EXPIRATION,
+                redis_lock::DEFAULT_TIMEOUT,
+                redis_lock::DEFAULT_SLEEP,
+            )?
+            .ok_or("timed out")?;
+        assert_eq!(guard.resources(), resources);
+        assert!(other.is_valid()?);
+        assert_eq!(guard.release()?, redis_lock::ReleaseOutcome::Released(0));
+        drop(other);
+        Ok(())
+    })
+}